@@ -12,4 +12,30 @@ CREATE TABLE IF NOT EXISTS categories (id INTEGER PRIMARY KEY AUTOINCREMENT, nam
 CREATE TABLE IF NOT EXISTS rfid_users (rfid TEXT, user INTEGER NOT NULL REFERENCES users);
 CREATE INDEX IF NOT EXISTS invoiceindex ON sales (user ASC, timestamp DESC);
 CREATE TABLE IF NOT EXISTS product_metadata (product INTEGER PRIMARY KEY NOT NULL REFERENCES products, product_size INTEGER NOT NULL, product_size_is_weight BOOLEAN NOT NULL, container_size INTEGER NOT NULL, calories INTEGER NOT NULL, carbohydrates INTEGER NOT NULL, fats INTEGER NOT NULL, proteins INTEGER NOT NULL, deposit INTEGER NOT NULL, container_deposit INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS invoices (id TEXT PRIMARY KEY NOT NULL, user INTEGER NOT NULL REFERENCES users, timestamp INTEGER NOT NULL, period_from INTEGER NOT NULL, period_to INTEGER NOT NULL, amount INTEGER NOT NULL);
+CREATE TABLE IF NOT EXISTS sessions (id TEXT PRIMARY KEY NOT NULL, user INTEGER NOT NULL REFERENCES users, created INTEGER NOT NULL, last_seen INTEGER NOT NULL, expires INTEGER NOT NULL, user_agent TEXT, csrf_token TEXT NOT NULL);
+CREATE INDEX IF NOT EXISTS sessionuserindex ON sessions (user ASC);
+CREATE TABLE IF NOT EXISTS api_tokens (id INTEGER PRIMARY KEY AUTOINCREMENT, token TEXT NOT NULL UNIQUE, user INTEGER NOT NULL REFERENCES users, name TEXT NOT NULL, scopes TEXT NOT NULL, created INTEGER NOT NULL, last_used INTEGER);
+CREATE INDEX IF NOT EXISTS apitokenuserindex ON api_tokens (user ASC);
+CREATE TABLE IF NOT EXISTS disputes (id INTEGER PRIMARY KEY AUTOINCREMENT, user INTEGER NOT NULL REFERENCES users, product INTEGER NOT NULL REFERENCES products, timestamp INTEGER NOT NULL, comment TEXT NOT NULL, created INTEGER NOT NULL, state TEXT NOT NULL DEFAULT 'open', resolved_by INTEGER REFERENCES users, resolved_at INTEGER, reassigned_to INTEGER REFERENCES users, resolution TEXT);
+CREATE INDEX IF NOT EXISTS disputestateindex ON disputes (state ASC);
+CREATE TABLE IF NOT EXISTS audit_log (id INTEGER PRIMARY KEY AUTOINCREMENT, timestamp INTEGER NOT NULL, actor INTEGER NOT NULL REFERENCES users, action TEXT NOT NULL, entity TEXT NOT NULL, entity_id INTEGER NOT NULL, before TEXT, after TEXT);
+CREATE INDEX IF NOT EXISTS auditactorindex ON audit_log (actor ASC);
+CREATE INDEX IF NOT EXISTS auditentityindex ON audit_log (entity ASC, entity_id ASC);
+CREATE INDEX IF NOT EXISTS audittimestampindex ON audit_log (timestamp ASC);
+CREATE TABLE IF NOT EXISTS cashbox_counts (id INTEGER PRIMARY KEY AUTOINCREMENT, user INTEGER NOT NULL REFERENCES users, timestamp INTEGER NOT NULL, expected INTEGER NOT NULL, counted INTEGER NOT NULL, correction INTEGER REFERENCES cashbox_diff, comment TEXT NOT NULL DEFAULT '');
+CREATE TABLE IF NOT EXISTS cashbox_diff_types (diff INTEGER PRIMARY KEY NOT NULL REFERENCES cashbox_diff, type TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS purchase_orders (id INTEGER PRIMARY KEY AUTOINCREMENT, supplier INTEGER NOT NULL REFERENCES supplier, user INTEGER NOT NULL REFERENCES users, created INTEGER NOT NULL, ordered INTEGER, received INTEGER, status TEXT NOT NULL DEFAULT 'draft', comment TEXT NOT NULL DEFAULT '');
+CREATE TABLE IF NOT EXISTS purchase_order_items (purchase_order INTEGER NOT NULL REFERENCES purchase_orders, product INTEGER NOT NULL REFERENCES products, amount INTEGER NOT NULL DEFAULT 0, price INTEGER NOT NULL DEFAULT 0, received INTEGER, best_before_date INTEGER, PRIMARY KEY (purchase_order, product));
+CREATE INDEX IF NOT EXISTS purchaseordersupplierindex ON purchase_orders (supplier ASC);
+CREATE TABLE IF NOT EXISTS supplier_details (supplier INTEGER PRIMARY KEY NOT NULL REFERENCES supplier, contact_person TEXT NOT NULL DEFAULT '', email TEXT NOT NULL DEFAULT '', customer_number TEXT NOT NULL DEFAULT '', minimum_order INTEGER NOT NULL DEFAULT 0, archived BOOLEAN NOT NULL DEFAULT false);
+CREATE TABLE IF NOT EXISTS supplier_catalogue (supplier INTEGER NOT NULL REFERENCES supplier, product INTEGER NOT NULL REFERENCES products, article_number TEXT NOT NULL DEFAULT '', price INTEGER NOT NULL DEFAULT 0, updated INTEGER NOT NULL, PRIMARY KEY (supplier, product));
+CREATE INDEX IF NOT EXISTS suppliercatalogueproductindex ON supplier_catalogue (product ASC);
+CREATE TABLE IF NOT EXISTS deposits (id INTEGER PRIMARY KEY AUTOINCREMENT, user INTEGER NOT NULL REFERENCES users, product INTEGER NOT NULL REFERENCES products, timestamp INTEGER NOT NULL, amount INTEGER NOT NULL);
+CREATE INDEX IF NOT EXISTS depositindex ON deposits (user ASC, timestamp DESC);
+CREATE TABLE IF NOT EXISTS supplier_deposits (id INTEGER PRIMARY KEY AUTOINCREMENT, supplier INTEGER NOT NULL REFERENCES supplier, user INTEGER NOT NULL REFERENCES users, product INTEGER REFERENCES products, timestamp INTEGER NOT NULL, amount INTEGER NOT NULL, comment TEXT NOT NULL DEFAULT '');
+CREATE INDEX IF NOT EXISTS supplierdepositindex ON supplier_deposits (supplier ASC);
+CREATE TABLE IF NOT EXISTS product_ingredients (product INTEGER PRIMARY KEY NOT NULL REFERENCES products, allergens TEXT NOT NULL DEFAULT '', ingredients TEXT NOT NULL DEFAULT '');
+CREATE TABLE IF NOT EXISTS category_vat (category INTEGER PRIMARY KEY NOT NULL REFERENCES categories, vat INTEGER NOT NULL DEFAULT 19);
+CREATE TABLE IF NOT EXISTS invoice_pdfs (invoice TEXT PRIMARY KEY NOT NULL REFERENCES invoices, pdf BLOB NOT NULL);
 COMMIT;
@@ -14,16 +14,41 @@
  */
 use std::{error::Error, future::pending};
 use zbus::{connection, DBusError, interface};
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use r2d2_sqlite::SqliteConnectionManager;
 use r2d2_sqlite::rusqlite::OptionalExtension;
 use serde::{Serialize, Deserialize};
 use sha2::{Sha256, Digest};
 use hex::ToHex;
+use argon2::{Argon2, PasswordHash, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{rand_core::OsRng, SaltString};
 use configparser::ini::Ini;
 
 struct Database {
     pool: r2d2::Pool<SqliteConnectionManager>,
+    valuation: Valuation,
+    /* default days of stock an order suggestion should cover */
+    order_coverage: u32,
+    /* default days of sales history used for order suggestions */
+    order_history: u32,
+}
+
+/* how sold and remaining stock is valued at purchase price */
+#[derive(Clone, Copy, PartialEq)]
+enum Valuation {
+    /* sales consume the oldest restock lots first */
+    Fifo,
+    /* running average over all restocks, like the purchaseprices view */
+    Average,
+}
+
+impl Valuation {
+    fn from_config(value: Option<String>) -> Self {
+        match value.as_deref() {
+            Some("average") => Valuation::Average,
+            _ => Valuation::Fifo,
+        }
+    }
 }
 
 #[derive(DBusError, Debug)]
@@ -33,6 +58,8 @@ enum DatabaseError {
     ZBus(zbus::Error),
     R2D2(String),
     SQL(String),
+    PasswordHash(String),
+    InvalidArgument(String),
 }
 
 impl From<r2d2::Error> for DatabaseError {
@@ -41,6 +68,12 @@ impl From<r2d2::Error> for DatabaseError {
     }
 }
 
+impl From<argon2::password_hash::Error> for DatabaseError {
+    fn from(err: argon2::password_hash::Error) -> DatabaseError {
+            DatabaseError::PasswordHash(err.to_string())
+    }
+}
+
 impl From<r2d2_sqlite::rusqlite::Error> for DatabaseError {
     fn from(err: r2d2_sqlite::rusqlite::Error) -> DatabaseError {
             DatabaseError::SQL(err.to_string())
@@ -82,6 +115,16 @@ struct PriceEntry {
 	guestprice: i32,
 }
 
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct PriceChange {
+	product: Product,
+	valid_from: i64,
+	memberprice: i32,
+	guestprice: i32,
+	previous_memberprice: i32,
+	previous_guestprice: i32,
+}
+
 #[derive(Deserialize,Serialize, zbus::zvariant::Type)]
 struct RestockEntry {
 	timestamp: i64,
@@ -102,6 +145,87 @@ struct InvoiceEntry {
 	timestamp: i64,
 	product: Product,
 	price: i32,
+	vat: i32,
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct DisputeInfo {
+	id: i64,
+	user: i32,
+	product: Product,
+	timestamp: i64,
+	comment: String,
+	created: i64,
+	state: String,
+	resolved_by: i32,
+	resolved_at: i64,
+	reassigned_to: i32,
+	resolution: String,
+}
+
+const DISPUTE_QUERY: &str = "SELECT disputes.id, user, product, products.name, timestamp, comment, created, state, resolved_by, resolved_at, reassigned_to, resolution FROM disputes INNER JOIN products ON disputes.product = products.id";
+
+fn dispute_from_row(row: &r2d2_sqlite::rusqlite::Row) -> Result<DisputeInfo, r2d2_sqlite::rusqlite::Error> {
+	Ok(DisputeInfo {
+		id: row.get(0)?,
+		user: row.get(1)?,
+		product: Product {
+			ean: row.get(2)?,
+			name: row.get(3)?,
+		},
+		timestamp: row.get(4)?,
+		comment: row.get(5)?,
+		created: row.get(6)?,
+		state: row.get(7)?,
+		resolved_by: row.get::<_, Option<i32>>(8)?.unwrap_or_default(),
+		resolved_at: row.get::<_, Option<i64>>(9)?.unwrap_or_default(),
+		reassigned_to: row.get::<_, Option<i32>>(10)?.unwrap_or_default(),
+		resolution: row.get::<_, Option<String>>(11)?.unwrap_or_default(),
+	})
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct ApiTokenInfo {
+	id: i64,
+	user: i32,
+	name: String,
+	scopes: Vec<String>,
+	created: i64,
+	last_used: i64,
+}
+
+const API_SCOPES: [&str; 3] = ["products", "users", "cashbox"];
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct AuditEntry {
+	id: i64,
+	timestamp: i64,
+	actor: i32,
+	action: String,
+	entity: String,
+	entity_id: i64,
+	before: String,
+	after: String,
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct SessionInfo {
+	id: i64,
+	created: i64,
+	last_seen: i64,
+	expires: i64,
+	user_agent: String,
+	current: bool,
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct InvoiceInfo {
+	id: String,
+	user: i32,
+	timestamp: i64,
+	period_from: i64,
+	period_to: i64,
+	amount: i32,
 }
 
 #[derive(Deserialize,Serialize, zbus::zvariant::Type)]
@@ -111,6 +235,15 @@ struct SalesEntry {
 	product: Product,
 }
 
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct ProfitReportEntry {
+	product: Product,
+	category: String,
+	units: u32,
+	revenue: i64,
+	cost: i64,
+}
+
 #[derive(Deserialize,Serialize, zbus::zvariant::Type)]
 struct UserSaleStatsEntry {
     timedatecode: String,
@@ -121,6 +254,7 @@ struct UserSaleStatsEntry {
 struct Category {
 	id: i32,
 	name: String,
+	vat: i32,
 }
 
 #[derive(Deserialize,Serialize, zbus::zvariant::Type)]
@@ -132,6 +266,60 @@ struct Supplier {
 	street: String,
 	phone: String,
 	website: String,
+	contact_person: String,
+	email: String,
+	customer_number: String,
+	minimum_order: u32,
+	archived: bool,
+}
+
+const SUPPLIER_QUERY: &str = "SELECT supplier.id, supplier.name, supplier.postal_code, supplier.city, supplier.street, supplier.phone, supplier.website, COALESCE(supplier_details.contact_person, ''), COALESCE(supplier_details.email, ''), COALESCE(supplier_details.customer_number, ''), COALESCE(supplier_details.minimum_order, 0), COALESCE(supplier_details.archived, false) FROM supplier LEFT JOIN supplier_details ON supplier_details.supplier = supplier.id";
+
+fn supplier_from_row(row: &r2d2_sqlite::rusqlite::Row) -> Result<Supplier, r2d2_sqlite::rusqlite::Error> {
+	Ok(Supplier {
+		id: row.get(0)?,
+		name: row.get(1)?,
+		postal_code: row.get(2).unwrap_or("".to_string()),
+		city: row.get(3).unwrap_or("".to_string()),
+		street: row.get(4).unwrap_or("".to_string()),
+		phone: row.get(5).unwrap_or("".to_string()),
+		website: row.get(6).unwrap_or("".to_string()),
+		contact_person: row.get(7)?,
+		email: row.get(8)?,
+		customer_number: row.get(9)?,
+		minimum_order: row.get(10)?,
+		archived: row.get(11)?,
+	})
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct SupplierCatalogueEntry {
+	supplier: i32,
+	product: Product,
+	article_number: String,
+	price: u32,
+	updated: i64,
+}
+
+/* deposit paid to (positive) or refunded by (negative) a supplier, product is 0 for returned empties */
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct SupplierDeposit {
+	id: i64,
+	supplier: i32,
+	user: i32,
+	product: i64,
+	timestamp: i64,
+	amount: i32,
+	comment: String,
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct DepositBalance {
+	supplier: i32,
+	name: String,
+	paid: i32,
+	refunded: i32,
+	balance: i32,
 }
 
 #[derive(Deserialize,Serialize, zbus::zvariant::Type)]
@@ -215,10 +403,200 @@ struct BestBeforeEntry {
 }
 
 #[derive(Deserialize,Serialize, zbus::zvariant::Type)]
-struct CashboxDiff {
+struct StockValueEntry {
+	product: Product,
+	category: String,
+	amount: i32,
+	value: i64,
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type, Clone)]
+struct StockLot {
+	timestamp: i64,
+	amount: u32,
+	remaining: u32,
+	price: i64,
+	best_before_date: i64,
+}
+
+/* state of a product after replaying its restocks and sales in order */
+#[derive(Default)]
+struct StockValuation {
+	lots: VecDeque<StockLot>,
+	amount: i64,
+	/* purchase price of every replayed sale, indexed by the sales rowid */
+	sale_costs: HashMap<i64, i64>,
+	last_price: i64,
+	total_value: i64,
+	total_amount: i64,
+}
+
+impl StockValuation {
+	fn average(&self) -> i64 {
+		match self.total_amount {
+			0 => 0,
+			amount => self.total_value / amount,
+		}
+	}
+
+	fn add_lot(&mut self, timestamp: i64, amount: u32, price: i64, best_before_date: i64) {
+		self.amount += amount as i64;
+		self.total_value += price * amount as i64;
+		self.total_amount += amount as i64;
+
+		/* zero priced restocks are inventory corrections, value them at the last purchase price */
+		let price = match price {
+			0 => self.last_price,
+			price => { self.last_price = price; price },
+		};
+
+		self.lots.push_back(StockLot {
+			timestamp: timestamp,
+			amount: amount,
+			remaining: amount,
+			price: price,
+			best_before_date: best_before_date,
+		});
+	}
+
+	fn consume(&mut self, sale: i64, method: Valuation) {
+		self.amount -= 1;
+
+		while self.lots.front().is_some_and(|lot| lot.remaining == 0) {
+			self.lots.pop_front();
+		}
+
+		/* selling more than has been restocked falls back to the last purchase price */
+		let lot_price = match self.lots.front_mut() {
+			Some(lot) => { lot.remaining -= 1; lot.price },
+			None => self.last_price,
+		};
+
+		let cost = match method {
+			Valuation::Fifo => lot_price,
+			Valuation::Average => self.average(),
+		};
+		self.sale_costs.insert(sale, cost);
+	}
+
+	fn value(&self, method: Valuation) -> i64 {
+		match method {
+			Valuation::Fifo => self.lots.iter().map(|lot| lot.remaining as i64 * lot.price).sum(),
+			Valuation::Average => self.amount.max(0) * self.average(),
+		}
+	}
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type, Clone)]
+struct CashboxLedgerEntry {
+	id: i64,
+	timestamp: i64,
+	entry_type: String,
 	user: i32,
 	amount: i32,
+	balance: i64,
+}
+
+const CASHBOX_ENTRY_TYPES: [&str; 6] = ["withdrawal", "deposit", "loss", "donation", "count", "guest_sale"];
+
+/* entries booked before typed entries were introduced get their type from the old conventions, the balance includes everything before the requested period */
+const CASHBOX_LEDGER_QUERY: &str = "WITH ledger(id, timestamp, type, user, amount) AS (SELECT cashbox_diff.id, cashbox_diff.timestamp, COALESCE(cashbox_diff_types.type, CASE WHEN cashbox_diff.id IN (SELECT correction FROM cashbox_counts WHERE correction IS NOT NULL) THEN 'count' WHEN cashbox_diff.user = -3 AND cashbox_diff.amount < 0 THEN 'loss' WHEN cashbox_diff.user = -3 THEN 'donation' WHEN cashbox_diff.amount < 0 THEN 'withdrawal' ELSE 'deposit' END), cashbox_diff.user, cashbox_diff.amount FROM cashbox_diff LEFT JOIN cashbox_diff_types ON cashbox_diff_types.diff = cashbox_diff.id WHERE cashbox_diff.timestamp <= ?1 UNION ALL SELECT 0, MAX(timestamp), 'guest_sale', 0, COALESCE(SUM(amount), 0) FROM (SELECT timestamp, (SELECT guestprice FROM prices WHERE product = sales.product AND valid_from <= sales.timestamp ORDER BY valid_from DESC LIMIT 1) AS amount FROM sales WHERE user = 0 AND timestamp <= ?1 UNION ALL SELECT timestamp, amount FROM deposits WHERE user = 0 AND timestamp <= ?1) GROUP BY date(timestamp, 'unixepoch', 'localtime')), balanced AS (SELECT *, SUM(amount) OVER (ORDER BY timestamp ASC, id ASC ROWS UNBOUNDED PRECEDING) AS balance FROM ledger) SELECT id, timestamp, type, user, amount, balance FROM balanced WHERE timestamp >= ?2 AND (?3 = '' OR type = ?3)";
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct CashboxCount {
+	id: i64,
+	user: i32,
 	timestamp: i64,
+	expected: i32,
+	counted: i32,
+	correction: i64,
+	comment: String,
+}
+
+const CASHBOX_COUNT_QUERY: &str = "SELECT id, user, timestamp, expected, counted, COALESCE(correction, 0), comment FROM cashbox_counts";
+
+fn cashbox_count_from_row(row: &r2d2_sqlite::rusqlite::Row) -> Result<CashboxCount, r2d2_sqlite::rusqlite::Error> {
+	Ok(CashboxCount {
+		id: row.get(0)?,
+		user: row.get(1)?,
+		timestamp: row.get(2)?,
+		expected: row.get(3)?,
+		counted: row.get(4)?,
+		correction: row.get(5)?,
+		comment: row.get(6)?,
+	})
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct PurchaseOrder {
+	id: i64,
+	supplier: i32,
+	user: i32,
+	created: i64,
+	ordered: i64,
+	received: i64,
+	status: String,
+	comment: String,
+	items: u32,
+	total: i64,
+	discrepancies: u32,
+}
+
+/* states of a purchase order, 'draft' orders can still be edited */
+const PURCHASE_ORDER_STATES: [&str; 4] = ["draft", "ordered", "received", "cancelled"];
+
+const PURCHASE_ORDER_QUERY: &str = "SELECT id, supplier, user, created, COALESCE(ordered, 0), COALESCE(received, 0), status, comment, (SELECT COUNT(*) FROM purchase_order_items WHERE purchase_order = purchase_orders.id), (SELECT COALESCE(SUM(amount * price), 0) FROM purchase_order_items WHERE purchase_order = purchase_orders.id), (SELECT COUNT(*) FROM purchase_order_items WHERE purchase_order = purchase_orders.id AND received IS NOT NULL AND received != amount) FROM purchase_orders";
+
+/* catalogue price or last purchase price, preferably from the same supplier */
+const PURCHASE_ORDER_PRICE_QUERY: &str = "SELECT COALESCE((SELECT price FROM supplier_catalogue WHERE product = ?1 AND supplier = ?2 AND price > 0), (SELECT price FROM restock WHERE product = ?1 AND price > 0 ORDER BY supplier = ?2 DESC, timestamp DESC LIMIT 1))";
+
+fn purchase_order_from_row(row: &r2d2_sqlite::rusqlite::Row) -> Result<PurchaseOrder, r2d2_sqlite::rusqlite::Error> {
+	Ok(PurchaseOrder {
+		id: row.get(0)?,
+		supplier: row.get(1)?,
+		user: row.get(2)?,
+		created: row.get(3)?,
+		ordered: row.get(4)?,
+		received: row.get(5)?,
+		status: row.get(6)?,
+		comment: row.get(7)?,
+		items: row.get(8)?,
+		total: row.get(9)?,
+		discrepancies: row.get(10)?,
+	})
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct PurchaseOrderItem {
+	product: Product,
+	amount: u32,
+	price: u32,
+	/* -1 until the delivery has been received */
+	received: i32,
+	best_before_date: i64,
+	article_number: String,
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct PurchaseOrderDelivery {
+	product: i64,
+	amount: u32,
+	price: u32,
+	best_before_date: i64,
+}
+
+#[derive(Deserialize,Serialize, zbus::zvariant::Type)]
+struct OrderSuggestion {
+	product: Product,
+	stock: i32,
+	/* units sold within the history period */
+	sold: u32,
+	/* days of the history period on which the product was available */
+	selling_days: u32,
+	/* units sold per selling day */
+	velocity: f64,
+	container_size: u32,
+	suggestion: u32,
 }
 
 #[derive(Deserialize,Serialize, zbus::zvariant::Type)]
@@ -232,6 +610,9 @@ struct ProductMetadata {
     proteins: u32,
     deposit: u32,
     container_deposit: u32,
+    /* comma separated, e.g. "Gluten, Soja" */
+    allergens: String,
+    ingredients: String,
 }
 
 fn get_unix_time() -> i64 {
@@ -241,12 +622,138 @@ fn get_unix_time() -> i64 {
     }
 }
 
+fn api_token_from_row(row: &r2d2_sqlite::rusqlite::Row) -> Result<ApiTokenInfo, r2d2_sqlite::rusqlite::Error> {
+	let scopes: String = row.get(3)?;
+	Ok(ApiTokenInfo {
+		id: row.get(0)?,
+		user: row.get(1)?,
+		name: row.get(2)?,
+		scopes: scopes.split(',').filter(|s| !s.is_empty()).map(|s| s.to_string()).collect(),
+		created: row.get(4)?,
+		last_used: row.get::<_, Option<i64>>(5)?.unwrap_or_default(),
+	})
+}
+
+fn to_json<T: Serialize>(value: &T) -> Option<serde_json::Value> {
+    serde_json::to_value(value).ok()
+}
+
+/* replay restocks and sales of a product up to the given time */
+fn stock_valuation(connection: &r2d2_sqlite::rusqlite::Connection, product: i64, until: i64, method: Valuation) -> Result<StockValuation, DatabaseError> {
+    let query_restock = "SELECT timestamp, amount, price, COALESCE(best_before_date, 0) FROM restock WHERE product = ? AND timestamp <= ? ORDER BY timestamp ASC";
+    let query_sales = "SELECT rowid, timestamp FROM sales WHERE product = ? AND timestamp <= ? ORDER BY timestamp ASC, rowid ASC";
+
+    let mut statement = connection.prepare(query_restock)?;
+    let restocks = statement.query_map((product, until), |r| Ok((r.get::<_, i64>(0)?, r.get::<_, u32>(1)?, r.get::<_, i64>(2)?, r.get::<_, i64>(3)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut statement = connection.prepare(query_sales)?;
+    let sales = statement.query_map((product, until), |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?)))?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut valuation = StockValuation::default();
+    let mut restocks = restocks.into_iter().peekable();
+
+    for (sale, timestamp) in sales {
+        while let Some((restocked, amount, price, best_before_date)) = restocks.next_if(|r| r.0 <= timestamp) {
+            valuation.add_lot(restocked, amount, price, best_before_date);
+        }
+        valuation.consume(sale, method);
+    }
+    for (restocked, amount, price, best_before_date) in restocks {
+        valuation.add_lot(restocked, amount, price, best_before_date);
+    }
+
+    Ok(valuation)
+}
+
+/* money expected in the cashbox */
+fn cashbox_balance(connection: &r2d2_sqlite::rusqlite::Connection) -> Result<i32, DatabaseError> {
+    /* guests pay and get refunded deposit in cash */
+    let query = "SELECT COALESCE(amount, 0) + (SELECT COALESCE(SUM(amount), 0) FROM deposits WHERE user = 0) FROM current_cashbox_status";
+    let balance = connection.query_row(query, [], |r| r.get(0)).optional()?;
+    Ok(balance.unwrap_or(0))
+}
+
+/* record a state change, before and after are stored as JSON */
+fn audit_log(connection: &r2d2_sqlite::rusqlite::Connection, actor: i32, action: &str, entity: &str, entity_id: impl Into<i64>, before: Option<serde_json::Value>, after: Option<serde_json::Value>) -> Result<(), DatabaseError> {
+    let query = "INSERT INTO audit_log (timestamp, actor, action, entity, entity_id, before, after) VALUES (?, ?, ?, ?, ?, ?, ?)";
+    let mut statement = connection.prepare(query)?;
+    let _inserted_row_count = statement.execute((get_unix_time(), actor, action, entity, entity_id.into(), before.map(|v| v.to_string()), after.map(|v| v.to_string())))?;
+    Ok(())
+}
+
+/* deposit for bottles and (if the product is delivered in crates) containers of a delivery */
+fn supplier_deposit_add(connection: &r2d2_sqlite::rusqlite::Connection, actor: i32, supplier: i32, product: i64, amount: u32, timestamp: i64) -> Result<(), DatabaseError> {
+    let query_metadata = "SELECT deposit, container_size, container_deposit FROM product_metadata WHERE product = ?";
+    let query = "INSERT INTO supplier_deposits ('supplier', 'user', 'product', 'timestamp', 'amount') VALUES (?, ?, ?, ?, ?)";
+
+    if supplier <= 0 {
+        return Ok(());
+    }
+
+    let metadata: Option<(u32, u32, u32)> = connection.query_row(query_metadata, [product], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?))).optional()?;
+    let (deposit, container_size, container_deposit) = metadata.unwrap_or_default();
+    let containers = if container_size > 0 { amount.div_ceil(container_size) } else { 0 };
+    let total = (amount * deposit + containers * container_deposit) as i32;
+
+    if total == 0 {
+        return Ok(());
+    }
+
+    connection.execute(query, (supplier, actor, product, timestamp, total))?;
+    let id = connection.last_insert_rowid();
+    let after = serde_json::json!({"supplier": supplier, "product": product, "amount": total, "timestamp": timestamp});
+    audit_log(connection, actor, "create", "supplier_deposit", id, None, Some(after))?;
+    Ok(())
+}
+
+/* deposit charged together with a sale, which is identified by user, product and timestamp */
+fn deposit_remove(connection: &r2d2_sqlite::rusqlite::Connection, user: i32, product: i64, timestamp: i64) -> Result<(), DatabaseError> {
+    let query = "DELETE FROM deposits WHERE id = (SELECT id FROM deposits WHERE user = ? AND product = ? AND timestamp = ? AND amount > 0 LIMIT 1)";
+    connection.execute(query, (user, product, timestamp))?;
+    Ok(())
+}
+
+fn deposit_reassign(connection: &r2d2_sqlite::rusqlite::Connection, user: i32, product: i64, timestamp: i64, new_user: i32) -> Result<(), DatabaseError> {
+    /* system users do not pay deposit */
+    if new_user < 0 {
+        return deposit_remove(connection, user, product, timestamp);
+    }
+
+    let query = "UPDATE deposits SET user = ? WHERE id = (SELECT id FROM deposits WHERE user = ? AND product = ? AND timestamp = ? AND amount > 0 LIMIT 1)";
+    connection.execute(query, (new_user, user, product, timestamp))?;
+    Ok(())
+}
+
 fn sha256(msg: &str) -> String {
     let mut hasher = Sha256::new();
     hasher.update(msg);
     hasher.finalize().encode_hex::<String>()
 }
 
+/* Argon2id hash in PHC string format */
+fn password_hash(password: &str) -> Result<String, DatabaseError> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+fn password_is_legacy(pwhash: &str) -> bool {
+    !pwhash.starts_with('$')
+}
+
+fn password_verify(password: &str, pwhash: &str) -> bool {
+    /* old installations store unsalted SHA-256 hashes */
+    if password_is_legacy(pwhash) {
+        return pwhash == sha256(password);
+    }
+
+    match PasswordHash::new(pwhash) {
+        Ok(hash) => Argon2::default().verify_password(password.as_bytes(), &hash).is_ok(),
+        Err(_) => false,
+    }
+}
+
 #[interface(name = "io.mainframe.shopsystem.Database")]
 impl Database {
 
@@ -283,10 +790,10 @@ impl Database {
 
     fn get_productlist(&mut self) -> Result<Vec<DetailedProductInfo>, DatabaseError> {
 		let mut result = Vec::new();
-        let query = "SELECT products.id, products.name, categories.name, amount, memberprice, guestprice, deprecated FROM products, prices, categories WHERE products.id = prices.product AND categories.id = products.category AND prices.valid_from = (SELECT valid_from FROM prices WHERE product = products.id ORDER BY valid_from DESC LIMIT 1) ORDER BY categories.name, products.name";
+        let query = "SELECT products.id, products.name, categories.name, amount, memberprice, guestprice, deprecated FROM products, prices, categories WHERE products.id = prices.product AND categories.id = products.category AND prices.valid_from = (SELECT valid_from FROM prices WHERE product = products.id AND valid_from <= ? ORDER BY valid_from DESC LIMIT 1) ORDER BY categories.name, products.name";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([])?;
+        let mut rows = statement.query([get_unix_time()])?;
 
         while let Some(row) = rows.next()? {
             let id = row.get(0)?;
@@ -308,10 +815,10 @@ impl Database {
 
     fn get_stock(&mut self) -> Result<Vec<DetailedProduct>, DatabaseError> {
 		let mut result = Vec::new();
-        let query = "SELECT stock.id, stock.name, categories.name, amount, memberprice, guestprice FROM stock, prices, categories WHERE stock.id = prices.product AND categories.id = stock.category AND prices.valid_from = (SELECT valid_from FROM prices WHERE product = stock.id ORDER BY valid_from DESC LIMIT 1) ORDER BY categories.name, stock.name";
+        let query = "SELECT stock.id, stock.name, categories.name, amount, memberprice, guestprice FROM stock, prices, categories WHERE stock.id = prices.product AND categories.id = stock.category AND prices.valid_from = (SELECT valid_from FROM prices WHERE product = stock.id AND valid_from <= ? ORDER BY valid_from DESC LIMIT 1) ORDER BY categories.name, stock.name";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([])?;
+        let mut rows = statement.query([get_unix_time()])?;
 
         while let Some(row) = rows.next()? {
             result.push(DetailedProduct {
@@ -340,9 +847,11 @@ impl Database {
         Ok(result)
 	}
 
-	fn product_metadata_set(&mut self, ean: i64, metadata: ProductMetadata) -> Result<(), DatabaseError> {
+	fn product_metadata_set(&mut self, actor: i32, ean: i64, metadata: ProductMetadata) -> Result<(), DatabaseError> {
         let ean = self.ean_alias_get(ean)?;
+        let before = self.product_metadata_get(ean).ok();
         let connection = self.pool.get()?;
+        let query_ingredients = "INSERT INTO product_ingredients ('product', 'allergens', 'ingredients') VALUES (?1, ?2, ?3) ON CONFLICT (product) DO UPDATE SET allergens = ?2, ingredients = ?3";
         let query = "INSERT OR REPLACE INTO product_metadata ('product', 'product_size', 'product_size_is_weight', 'container_size', 'calories', 'carbohydrates', 'fats', 'proteins', 'deposit', 'container_deposit') VALUES (?,?,?,?,?,?,?,?,?,?)";
         let mut statement = connection.prepare(query)?;
         let _inserted_row_count = statement.execute((
@@ -357,12 +866,14 @@ impl Database {
             metadata.deposit,
             metadata.container_deposit,
         ))?;
+        connection.execute(query_ingredients, (ean, metadata.allergens.trim(), metadata.ingredients.trim()))?;
 
+        audit_log(&connection, actor, "metadata_set", "product", ean, to_json(&before), to_json(&metadata))?;
         Ok(())
 	}
 
 	fn product_metadata_get(&mut self, ean: i64) -> Result<ProductMetadata, DatabaseError> {
-        let query = "SELECT product_size, product_size_is_weight, container_size, calories, carbohydrates, fats, proteins, deposit, container_deposit FROM product_metadata WHERE product = ?";
+        let query = "SELECT product_size, product_size_is_weight, container_size, calories, carbohydrates, fats, proteins, deposit, container_deposit, COALESCE(product_ingredients.allergens, ''), COALESCE(product_ingredients.ingredients, '') FROM product_metadata LEFT JOIN product_ingredients ON product_ingredients.product = product_metadata.product WHERE product_metadata.product = ?";
         let ean = self.ean_alias_get(ean)?;
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
@@ -378,6 +889,8 @@ impl Database {
                 proteins: r.get(6)?,
                 deposit: r.get(7)?,
                 container_deposit: r.get(8)?,
+                allergens: r.get(9)?,
+                ingredients: r.get(10)?,
             })
         })?;
         Ok(result)
@@ -454,13 +967,50 @@ impl Database {
 
 	fn buy(&mut self, user: i32, article: i64) -> Result<(), DatabaseError> {
         let query = "INSERT INTO sales ('user', 'product', 'timestamp') VALUES (?, ?, ?)";
-        let connection = self.pool.get()?;
-        let mut statement = connection.prepare(query)?;
+        let query_deposit = "INSERT INTO deposits ('user', 'product', 'timestamp', 'amount') VALUES (?, ?, ?, ?)";
+        let deposit = self.get_product_deposit(article)?;
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
         let timestamp = get_unix_time();
-        let _inserted_row_count = statement.execute((user, article, timestamp))?;
+        let _inserted_row_count = transaction.execute(query, (user, article, timestamp))?;
+
+        /* system users do not pay, so they do not pay deposit either */
+        if user >= 0 && deposit > 0 {
+            transaction.execute(query_deposit, (user, article, timestamp, deposit))?;
+        }
+
+        transaction.commit()?;
         Ok(())
 	}
 
+	/* deposit for a single unit, crates stay in the shop and are not charged */
+	fn get_product_deposit(&mut self, article: i64) -> Result<u32, DatabaseError> {
+        let query = "SELECT deposit FROM product_metadata WHERE product = ?";
+        let connection = self.pool.get()?;
+        let deposit = connection.query_row(query, [article], |r| r.get(0)).optional()?;
+        Ok(deposit.unwrap_or(0))
+	}
+
+	/* returned bottle, the deposit is credited to the user */
+	fn deposit_return(&mut self, user: i32, article: i64) -> Result<u32, DatabaseError> {
+        let query = "INSERT INTO deposits ('user', 'product', 'timestamp', 'amount') VALUES (?, ?, ?, ?)";
+        let deposit = self.get_product_deposit(article)?;
+
+        if user < 0 {
+            return Err(DatabaseError::InvalidArgument("system users do not pay deposit".to_string()));
+        }
+        if deposit == 0 {
+            return Err(DatabaseError::InvalidArgument(format!("product {} has no deposit", article)));
+        }
+
+        let timestamp = get_unix_time();
+        let connection = self.pool.get()?;
+        let _inserted_row_count = connection.execute(query, (user, article, timestamp, -(deposit as i32)))?;
+        let after = serde_json::json!({"user": user, "product": article, "timestamp": timestamp, "amount": -(deposit as i32)});
+        audit_log(&connection, user, "return", "deposit", connection.last_insert_rowid(), None, Some(after))?;
+        Ok(deposit)
+	}
+
 	fn get_product_name(&mut self, article: i64) -> Result<String, DatabaseError> {
         let query = "SELECT name FROM products WHERE id = ?";
         let connection = self.pool.get()?;
@@ -522,11 +1072,13 @@ impl Database {
         Ok(deprecated)
 	}
 
-	fn product_deprecate(&mut self, article: i64, value: bool) -> Result<(), DatabaseError> {
+	fn product_deprecate(&mut self, actor: i32, article: i64, value: bool) -> Result<(), DatabaseError> {
         let query = "UPDATE products SET deprecated=? WHERE id = ?";
+        let before = self.get_product_deprecated(article)?;
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
         let _inserted_row_count = statement.execute((value, article))?;
+        audit_log(&connection, actor, "deprecate", "product", article, to_json(&before), to_json(&value))?;
         Ok(())
 	}
 
@@ -553,21 +1105,24 @@ impl Database {
         Ok(result)
 	}
 
-	fn undo(&mut self, user: i32) -> Result<String, DatabaseError> {
-        let query_undo_info = "SELECT product FROM sales WHERE user = ? ORDER BY timestamp DESC LIMIT 1";
-        let query_undo = "DELETE FROM sales WHERE user = ? ORDER BY timestamp DESC LIMIT 1";
+	fn undo(&mut self, actor: i32, user: i32) -> Result<String, DatabaseError> {
+        let query_undo_info = "SELECT product, timestamp, rowid FROM sales WHERE user = ? ORDER BY timestamp DESC LIMIT 1";
+        let query_undo = "DELETE FROM sales WHERE rowid = ?";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query_undo_info)?;
-        let pid = statement.query_row([user], |r| r.get(0))?;
+        let (pid, timestamp, sale): (i64, i64, i64) = statement.query_row([user], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?)))?;
         let pname = self.get_product_name(pid)?;
         let mut statement = connection.prepare(query_undo)?;
-        let _inserted_row_count = statement.execute([user])?;
+        let _deleted_row_count = statement.execute([sale])?;
+        deposit_remove(&connection, user, pid, timestamp)?;
+        let before = serde_json::json!({"user": user, "product": pid, "timestamp": timestamp});
+        audit_log(&connection, actor, "undo", "sale", sale, Some(before), None)?;
         Ok(pname)
 	}
 
 	fn get_category_list(&mut self) -> Result<Vec<Category>, DatabaseError> {
 		let mut result = Vec::new();
-        let query = "SELECT id, name FROM categories";
+        let query = "SELECT id, name, COALESCE((SELECT vat FROM category_vat WHERE category = categories.id), 19) FROM categories";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
         let mut rows = statement.query([])?;
@@ -576,103 +1131,221 @@ impl Database {
             result.push(Category {
                 id: row.get(0)?,
                 name: row.get(1)?,
+                vat: row.get(2)?,
             });
         }
 
 		Ok(result)
 	}
 
-	fn restock(&mut self, user: i32, product: i64, amount: u32, price: u32, supplier: i32, best_before_date: i64) -> Result<(), DatabaseError> {
+	fn set_category_vat(&mut self, actor: i32, category: i32, vat: i32) -> Result<(), DatabaseError> {
+        let query = "INSERT OR REPLACE INTO category_vat ('category', 'vat') VALUES (?, ?)";
+        let connection = self.pool.get()?;
+        let before: Option<i32> = connection.query_row("SELECT COALESCE((SELECT vat FROM category_vat WHERE category = id), 19) FROM categories WHERE id = ?", [category], |r| r.get(0)).optional()?;
+        if before.is_none() {
+            return Err(DatabaseError::InvalidArgument(format!("category {} does not exist", category)));
+        }
+        let mut statement = connection.prepare(query)?;
+        let _updated_row_count = statement.execute((category, vat))?;
+        audit_log(&connection, actor, "set_vat", "category", category, to_json(&before), to_json(&vat))?;
+        Ok(())
+	}
+
+	fn restock(&mut self, actor: i32, user: i32, product: i64, amount: u32, price: u32, supplier: i32, best_before_date: i64) -> Result<(), DatabaseError> {
         let timestamp = get_unix_time();
         let query = "INSERT INTO restock ('user', 'product', 'amount', 'price', 'timestamp', 'supplier', 'best_before_date') VALUES (?, ?, ?, ?, ?, ?, ?)";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
         let _inserted_row_count = statement.execute((user, product, amount, price, timestamp, supplier, best_before_date))?;
+        let after = serde_json::json!({"user": user, "amount": amount, "price": price, "timestamp": timestamp, "supplier": supplier, "best_before_date": best_before_date});
+        audit_log(&connection, actor, "restock", "product", product, None, Some(after))?;
+        supplier_deposit_add(&connection, actor, supplier, product, amount, timestamp)?;
         Ok(())
 	}
 
-	fn new_product(&mut self, ean: i64, name: &str, category: i32, memberprice: i32, guestprice: i32) -> Result<(), DatabaseError> {
+	fn new_product(&mut self, actor: i32, ean: i64, name: &str, category: i32, memberprice: i32, guestprice: i32) -> Result<(), DatabaseError> {
         let query = "INSERT INTO products ('id', 'name', 'category', 'amount') VALUES (?, ?, ?, ?)";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
         let _inserted_row_count = statement.execute((ean, name, category, 0))?;
-		self.new_price(ean, 0, memberprice, guestprice)?;
+        let after = serde_json::json!({"name": name, "category": category});
+        audit_log(&connection, actor, "create", "product", ean, None, Some(after))?;
+		self.new_price(actor, ean, 0, memberprice, guestprice)?;
         Ok(())
 	}
 
-	fn new_price(&mut self, product: i64, timestamp: i64, memberprice: i32, guestprice: i32) -> Result<(), DatabaseError> {
-        let query = "INSERT INTO prices ('product', 'valid_from', 'memberprice', 'guestprice') VALUES (?, ?, ?, ?)";
+	fn product_update(&mut self, actor: i32, ean: i64, name: &str, category: i32) -> Result<(), DatabaseError> {
+        let query = "UPDATE products SET name = ?, category = ? WHERE id = ?";
         let connection = self.pool.get()?;
+        let (old_name, old_category): (String, i32) = connection.query_row("SELECT name, category FROM products WHERE id = ?", [ean], |r| Ok((r.get(0)?, r.get(1)?)))?;
         let mut statement = connection.prepare(query)?;
-        let _inserted_row_count = statement.execute((product, timestamp, memberprice, guestprice))?;
+        let _updated_row_count = statement.execute((name, category, ean))?;
+        let before = serde_json::json!({"name": old_name, "category": old_category});
+        let after = serde_json::json!({"name": name, "category": category});
+        audit_log(&connection, actor, "update", "product", ean, Some(before), Some(after))?;
         Ok(())
 	}
 
-	fn check_user_password(&mut self, user: i32, password: &str) -> Result<bool, DatabaseError> {
-        let query = "SELECT password FROM authentication WHERE user = ?";
+	fn new_price(&mut self, actor: i32, product: i64, timestamp: i64, memberprice: i32, guestprice: i32) -> Result<(), DatabaseError> {
+        let query = "INSERT INTO prices ('product', 'valid_from', 'memberprice', 'guestprice') VALUES (?, ?, ?, ?)";
+        let before = self.get_prices(product)?.pop();
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let pwhash_db: String = match statement.query_row([user], |r| r.get(0)) {
-            Ok(password) => password,
-            Err(error) => {
-                return match error {
-                    r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows => Ok(false),
-                    _ => Err(error.into()),
-                };
-            }
-        };
-        let pwhash_user = sha256(password);
-        Ok(pwhash_db == pwhash_user)
+        let _inserted_row_count = statement.execute((product, timestamp, memberprice, guestprice))?;
+        let after = PriceEntry { valid_from: timestamp, memberprice: memberprice, guestprice: guestprice };
+        audit_log(&connection, actor, "new_price", "product", product, to_json(&before), to_json(&after))?;
+        Ok(())
 	}
 
-	fn get_supplier_list(&mut self) -> Result<Vec<Supplier>, DatabaseError> {
-		let mut result = Vec::new();
-        let query = "SELECT id, name, postal_code, city, street, phone, website FROM supplier";
+	fn price_cancel(&mut self, actor: i32, product: i64, valid_from: i64) -> Result<(), DatabaseError> {
+        let query = "DELETE FROM prices WHERE product = ? AND valid_from = ?";
+        if valid_from <= get_unix_time() {
+            return Err(DatabaseError::InvalidArgument("only scheduled prices can be cancelled".to_string()));
+        }
         let connection = self.pool.get()?;
+        let (memberprice, guestprice): (i32, i32) = connection.query_row("SELECT memberprice, guestprice FROM prices WHERE product = ? AND valid_from = ?", (product, valid_from), |r| Ok((r.get(0)?, r.get(1)?)))?;
         let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([])?;
+        let _deleted_row_count = statement.execute((product, valid_from))?;
+        let before = PriceEntry { valid_from: valid_from, memberprice: memberprice, guestprice: guestprice };
+        audit_log(&connection, actor, "price_cancel", "product", product, to_json(&before), None)?;
+        Ok(())
+	}
 
-        while let Some(row) = rows.next()? {
-            result.push(Supplier {
-                id: row.get(0)?,
-                name: row.get(1)?,
-                postal_code: row.get(2).unwrap_or("".to_string()),
-                city: row.get(3).unwrap_or("".to_string()),
-                street: row.get(4).unwrap_or("".to_string()),
-                phone: row.get(5).unwrap_or("".to_string()),
-                website: row.get(6).unwrap_or("".to_string()),
-            });
+	/* schedule a relative price change for all active products of a category, rounded to 10 cent */
+	fn category_price_change(&mut self, actor: i32, category: i32, valid_from: i64, percent: i32) -> Result<u32, DatabaseError> {
+        let query_products = "SELECT id FROM products WHERE category = ? AND deprecated = 0";
+        let query_price = "SELECT memberprice, guestprice FROM prices WHERE product = ? AND valid_from <= ? ORDER BY valid_from DESC LIMIT 1";
+        let query = "INSERT INTO prices ('product', 'valid_from', 'memberprice', 'guestprice') VALUES (?, ?, ?, ?)";
+
+        if percent <= -100 {
+            return Err(DatabaseError::InvalidArgument(format!("invalid price change: {} %", percent)));
+        }
+        if valid_from <= get_unix_time() {
+            return Err(DatabaseError::InvalidArgument("price changes must be scheduled for the future".to_string()));
         }
 
-		Ok(result)
-	}
+        let adjust = |price: i32| -> i32 {
+            let price = price as i64 * (100 + percent as i64);
+            ((price + 500) / 1000 * 10) as i32
+        };
 
-	fn get_supplier_product_list(&mut self, supplier: i32) -> Result<Vec<ProductInfo>, DatabaseError> {
-		let mut result = Vec::new();
-        let query = "select products.id,products.name from restock,products where products.id = restock.product and supplier = ? and deprecated = false and strftime('%Y-%m-%d', timestamp, 'unixepoch', '+1 years') > strftime('%Y-%m-%d') group by products.id";
-        let connection = self.pool.get()?;
-        let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([supplier])?;
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+        let mut count = 0;
 
-        while let Some(row) = rows.next()? {
-            result.push(ProductInfo {
-                ean: row.get(0)?,
-                name: row.get(1)?,
-            });
+        let products: Vec<i64> = {
+            let mut statement = transaction.prepare(query_products)?;
+            let rows = statement.query_map([category], |r| r.get(0))?;
+            rows.collect::<Result<Vec<i64>, _>>()?
+        };
+
+        for product in products {
+            let current: Option<(i32, i32)> = transaction.query_row(query_price, (product, valid_from), |r| Ok((r.get(0)?, r.get(1)?))).optional()?;
+            let (memberprice, guestprice) = match current {
+                Some(prices) => prices,
+                None => { continue; },
+            };
+
+            let before = PriceEntry { valid_from: valid_from, memberprice: memberprice, guestprice: guestprice };
+            let after = PriceEntry { valid_from: valid_from, memberprice: adjust(memberprice), guestprice: adjust(guestprice) };
+            transaction.execute(query, (product, valid_from, after.memberprice, after.guestprice))?;
+            audit_log(&transaction, actor, "new_price", "product", product, to_json(&before), to_json(&after))?;
+            count += 1;
         }
 
-		Ok(result)
+        transaction.commit()?;
+        Ok(count)
 	}
 
-	fn get_supplier_restock_dates(&mut self, supplier: i32) -> Result<Vec<i64>, DatabaseError> {
+	fn get_price_changes(&mut self, from: i64, to: i64) -> Result<Vec<PriceChange>, DatabaseError> {
 		let mut result = Vec::new();
-        let query = "select unixepoch(strftime('%Y-%m-%d', timestamp, 'unixepoch', 'localtime')) from restock where supplier = ? group by strftime('%Y-%m-%d', timestamp, 'unixepoch', 'localtime') order by timestamp desc limit 10";
+        let query = "SELECT prices.product, products.name, prices.valid_from, prices.memberprice, prices.guestprice, COALESCE((SELECT memberprice FROM prices AS previous WHERE previous.product = prices.product AND previous.valid_from < prices.valid_from ORDER BY previous.valid_from DESC LIMIT 1), 0), COALESCE((SELECT guestprice FROM prices AS previous WHERE previous.product = prices.product AND previous.valid_from < prices.valid_from ORDER BY previous.valid_from DESC LIMIT 1), 0) FROM prices INNER JOIN products ON prices.product = products.id WHERE prices.valid_from >= ? AND prices.valid_from <= ? ORDER BY prices.valid_from, products.name";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([supplier])?;
+        let mut rows = statement.query([from, to])?;
 
         while let Some(row) = rows.next()? {
-            result.push(row.get(0)?);
+            result.push(PriceChange {
+                product: Product {
+                    ean: row.get(0)?,
+                    name: row.get(1)?,
+                },
+                valid_from: row.get(2)?,
+                memberprice: row.get(3)?,
+                guestprice: row.get(4)?,
+                previous_memberprice: row.get(5)?,
+                previous_guestprice: row.get(6)?,
+            });
+        }
+
+		Ok(result)
+	}
+
+	fn check_user_password(&mut self, user: i32, password: &str) -> Result<bool, DatabaseError> {
+        let query = "SELECT password FROM authentication WHERE user = ?";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let pwhash_db: Option<String> = match statement.query_row([user], |r| r.get(0)) {
+            Ok(password) => password,
+            Err(error) => {
+                return match error {
+                    r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows => Ok(false),
+                    _ => Err(error.into()),
+                };
+            }
+        };
+        let pwhash_db = match pwhash_db {
+            Some(pwhash) => pwhash,
+            None => { return Ok(false); },
+        };
+
+        if !password_verify(password, &pwhash_db) {
+            return Ok(false);
+        }
+
+        /* transparently upgrade legacy hashes, the password itself does not change, so this is not audited */
+        if password_is_legacy(&pwhash_db) {
+            let query = "UPDATE authentication SET password = ? WHERE user = ?";
+            let _updated_row_count = connection.execute(query, (password_hash(password)?, user))?;
+        }
+
+        Ok(true)
+	}
+
+	fn get_supplier_list(&mut self, include_archived: bool) -> Result<Vec<Supplier>, DatabaseError> {
+        let query = format!("{} WHERE ? OR COALESCE(supplier_details.archived, false) = false ORDER BY supplier.name ASC", SUPPLIER_QUERY);
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(&query)?;
+        let rows = statement.query_map([include_archived], supplier_from_row)?;
+		Ok(rows.collect::<Result<Vec<_>, _>>()?)
+	}
+
+	fn get_supplier_product_list(&mut self, supplier: i32) -> Result<Vec<ProductInfo>, DatabaseError> {
+		let mut result = Vec::new();
+        let query = "select products.id,products.name from restock,products where products.id = restock.product and supplier = ? and deprecated = false and strftime('%Y-%m-%d', timestamp, 'unixepoch', '+1 years') > strftime('%Y-%m-%d') group by products.id";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query([supplier])?;
+
+        while let Some(row) = rows.next()? {
+            result.push(ProductInfo {
+                ean: row.get(0)?,
+                name: row.get(1)?,
+            });
+        }
+
+		Ok(result)
+	}
+
+	fn get_supplier_restock_dates(&mut self, supplier: i32) -> Result<Vec<i64>, DatabaseError> {
+		let mut result = Vec::new();
+        let query = "select unixepoch(strftime('%Y-%m-%d', timestamp, 'unixepoch', 'localtime')) from restock where supplier = ? group by strftime('%Y-%m-%d', timestamp, 'unixepoch', 'localtime') order by timestamp desc limit 10";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query([supplier])?;
+
+        while let Some(row) = rows.next()? {
+            result.push(row.get(0)?);
         }
 
 		Ok(result)
@@ -696,18 +1369,10 @@ impl Database {
 	}
 
 	fn get_supplier(&mut self, id: i32) -> Result<Supplier, DatabaseError> {
-        let query = "SELECT id, name, postal_code, city, street, phone, website FROM supplier WHERE id = ?";
+        let query = format!("{} WHERE supplier.id = ?", SUPPLIER_QUERY);
         let connection = self.pool.get()?;
-        let mut statement = connection.prepare(query)?;
-        let response = statement.query_row([id], |row| Ok(Supplier {
-            id: row.get(0)?,
-            name: row.get(1)?,
-            postal_code: row.get(2).unwrap_or("".to_string()),
-            city: row.get(3).unwrap_or("".to_string()),
-            street: row.get(4).unwrap_or("".to_string()),
-            phone: row.get(5).unwrap_or("".to_string()),
-            website: row.get(6).unwrap_or("".to_string()),
-        }));
+        let mut statement = connection.prepare(&query)?;
+        let response = statement.query_row([id], supplier_from_row);
         match response {
             Ok(supplier) => Ok(supplier),
             Err(err) => {
@@ -720,6 +1385,11 @@ impl Database {
                         street: "".to_string(),
                         phone: "".to_string(),
                         website: "".to_string(),
+                        contact_person: "".to_string(),
+                        email: "".to_string(),
+                        customer_number: "".to_string(),
+                        minimum_order: 0,
+                        archived: false,
                     }),
                     _ => Err(err.into()),
                 }
@@ -727,8 +1397,8 @@ impl Database {
         }
 	}
 
-	fn set_user_password(&mut self, user: i32, password: &str) -> Result<(), DatabaseError> {
-        let pwhash = sha256(password);
+	fn set_user_password(&mut self, actor: i32, user: i32, password: &str) -> Result<(), DatabaseError> {
+        let pwhash = password_hash(password)?;
         let connection = self.pool.get()?;
 
         let query_auth_create = "INSERT OR IGNORE INTO authentication (user) VALUES (?)";
@@ -739,32 +1409,168 @@ impl Database {
 
         let mut statement = connection.prepare(query_password_set)?;
         let _inserted_row_count = statement.execute((pwhash, user))?;
+
+        /* never log the hashes */
+        audit_log(&connection, actor, "set_password", "user", user, None, None)?;
         Ok(())
     }
 
-    fn set_sessionid(&mut self, user: i32, sessionid: &str) -> Result<() , DatabaseError> {
-        let query = "UPDATE authentication SET session=? WHERE user = ?";
+    /* like API tokens only a hash of the session id is stored, so that backups cannot be used to take over sessions */
+    fn session_create(&mut self, user: i32, sessionid: &str, csrf_token: &str, user_agent: &str, lifetime: i64) -> Result<() , DatabaseError> {
+        let now = get_unix_time();
         let connection = self.pool.get()?;
+
+        /* drop expired sessions */
+        let query = "DELETE FROM sessions WHERE expires <= ?";
         let mut statement = connection.prepare(query)?;
-        let _inserted_row_count = statement.execute((sessionid, user))?;
+        let _deleted_row_count = statement.execute([now])?;
+
+        let query = "INSERT INTO sessions (id, user, created, last_seen, expires, user_agent, csrf_token) VALUES (?, ?, ?, ?, ?, ?, ?)";
+        let mut statement = connection.prepare(query)?;
+        let _inserted_row_count = statement.execute((sha256(sessionid), user, now, now, now + lifetime, user_agent, csrf_token))?;
+
+        /* the session id and CSRF token are secrets and must not end up in the log */
+        let after = serde_json::json!({"user": user, "user_agent": user_agent, "expires": now + lifetime});
+        audit_log(&connection, user, "create", "session", connection.last_insert_rowid(), None, Some(after))?;
+        Ok(())
+    }
+
+    fn get_session_csrf_token(&mut self, sessionid: &str) -> Result<String, DatabaseError> {
+        let query = "SELECT csrf_token FROM sessions WHERE id = ? AND expires > ?";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let token = statement.query_row((sha256(sessionid), get_unix_time()), |r| r.get(0))?;
+        Ok(token)
+    }
+
+    fn session_delete(&mut self, sessionid: &str) -> Result<() , DatabaseError> {
+        let query_session = "SELECT rowid, user FROM sessions WHERE id = ?";
+        let query = "DELETE FROM sessions WHERE id = ?";
+        let connection = self.pool.get()?;
+        let session: Option<(i64, i32)> = connection.query_row(query_session, [sha256(sessionid)], |r| Ok((r.get(0)?, r.get(1)?))).optional()?;
+        let mut statement = connection.prepare(query)?;
+        let _deleted_row_count = statement.execute([sha256(sessionid)])?;
+
+        if let Some((id, user)) = session {
+            audit_log(&connection, user, "delete", "session", id, Some(serde_json::json!({"user": user})), None)?;
+        }
+        Ok(())
+    }
+
+    fn session_revoke(&mut self, actor: i32, user: i32, session: i64) -> Result<() , DatabaseError> {
+        let query = "DELETE FROM sessions WHERE rowid = ? AND user = ?";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let deleted_row_count = statement.execute((session, user))?;
+        if deleted_row_count > 0 {
+            audit_log(&connection, actor, "revoke", "session", session, Some(serde_json::json!({"user": user})), None)?;
+        }
         Ok(())
     }
 
+    /* only a hash of the token is stored, the plain token is shown to the user once */
+    fn api_token_create(&mut self, actor: i32, user: i32, name: &str, token: &str, scopes: Vec<String>) -> Result<i64, DatabaseError> {
+        for scope in &scopes {
+            if !API_SCOPES.contains(&scope.as_str()) {
+                return Err(DatabaseError::InvalidArgument(format!("unknown scope: {}", scope)));
+            }
+        }
+
+        let query = "INSERT INTO api_tokens (token, user, name, scopes, created) VALUES (?, ?, ?, ?, ?)";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let _inserted_row_count = statement.execute((sha256(token), user, name, scopes.join(","), get_unix_time()))?;
+        let id = connection.last_insert_rowid();
+
+        let after = serde_json::json!({"user": user, "name": name, "scopes": scopes});
+        audit_log(&connection, actor, "create", "api_token", id, None, Some(after))?;
+        Ok(id)
+    }
+
+    fn api_token_revoke(&mut self, actor: i32, user: i32, token: i64) -> Result<(), DatabaseError> {
+        let query = "DELETE FROM api_tokens WHERE id = ? AND user = ?";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let deleted_row_count = statement.execute((token, user))?;
+        if deleted_row_count > 0 {
+            audit_log(&connection, actor, "revoke", "api_token", token, None, None)?;
+        }
+        Ok(())
+    }
+
+    fn get_api_token(&mut self, token: &str) -> Result<ApiTokenInfo, DatabaseError> {
+        let query = "SELECT id, user, name, scopes, created, last_used FROM api_tokens WHERE token = ?";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let info = statement.query_row([sha256(token)], api_token_from_row)?;
+
+        let query = "UPDATE api_tokens SET last_used = ? WHERE id = ?";
+        let mut statement = connection.prepare(query)?;
+        let _updated_row_count = statement.execute((get_unix_time(), info.id))?;
+
+        Ok(info)
+    }
+
+    fn get_user_api_tokens(&mut self, user: i32) -> Result<Vec<ApiTokenInfo>, DatabaseError> {
+        let query = "SELECT id, user, name, scopes, created, last_used FROM api_tokens WHERE user = ? ORDER BY created DESC";
+        let mut result = Vec::new();
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query([user])?;
+
+        while let Some(row) = rows.next()? {
+            result.push(api_token_from_row(row)?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_user_sessions(&mut self, user: i32, current_sessionid: &str) -> Result<Vec<SessionInfo>, DatabaseError> {
+        let mut result = Vec::new();
+        let query = "SELECT rowid, created, last_seen, expires, user_agent, id = ? FROM sessions WHERE user = ? AND expires > ? ORDER BY last_seen DESC";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query((sha256(current_sessionid), user, get_unix_time()))?;
+
+        while let Some(row) = rows.next()? {
+            result.push(SessionInfo {
+                id: row.get(0)?,
+                created: row.get(1)?,
+                last_seen: row.get(2)?,
+                expires: row.get(3)?,
+                user_agent: row.get::<_, Option<String>>(4)?.unwrap_or_default(),
+                current: row.get(5)?,
+            });
+        }
+
+        Ok(result)
+    }
+
     #[allow(non_snake_case)]
-    fn set_userTheme(&mut self, user: i32, user_theme: &str) -> Result<() , DatabaseError> {
+    fn set_userTheme(&mut self, actor: i32, user: i32, user_theme: &str) -> Result<() , DatabaseError> {
         let query = "UPDATE users SET sound_theme=? WHERE id = ?";
         let connection = self.pool.get()?;
+        let before: Option<String> = connection.query_row("SELECT sound_theme FROM users WHERE id = ?", [user], |r| r.get(0)).optional()?.flatten();
         let mut statement = connection.prepare(query)?;
         let theme = if user_theme == "" { None } else { Some(user_theme) };
         let _inserted_row_count = statement.execute((theme, user))?;
+        audit_log(&connection, actor, "set_theme", "user", user, to_json(&before), to_json(&theme))?;
         Ok(())
     }
 
     fn get_user_by_sessionid(&mut self, sessionid: &str) -> Result<i32, DatabaseError> {
-        let query = "SELECT user FROM authentication WHERE session = ?";
+        let now = get_unix_time();
+        let sessionhash = sha256(sessionid);
+        /* disabled users are logged out, even if their sessions could not be removed */
+        let query = "SELECT sessions.user FROM sessions JOIN users ON users.id = sessions.user WHERE sessions.id = ? AND sessions.expires > ? AND NOT users.disabled";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let userid = statement.query_row([sessionid], |r| r.get(0))?;
+        let userid: i32 = statement.query_row((&sessionhash, now), |r| r.get(0))?;
+
+        let query = "UPDATE sessions SET last_seen = ? WHERE id = ?";
+        let mut statement = connection.prepare(query)?;
+        let _updated_row_count = statement.execute((now, &sessionhash))?;
+
         Ok(userid)
     }
 
@@ -832,7 +1638,8 @@ impl Database {
         }
     }
 
-    fn set_user_auth(&mut self, auth: UserAuth) -> Result<(), DatabaseError> {
+    fn set_user_auth(&mut self, actor: i32, auth: UserAuth) -> Result<(), DatabaseError> {
+        let before = self.get_user_auth(auth.id)?;
         let connection = self.pool.get()?;
 
         let query_auth_create = "INSERT OR IGNORE INTO authentication (user) VALUES (?)";
@@ -842,6 +1649,7 @@ impl Database {
         let query = "UPDATE authentication SET auth_users = ?, auth_products = ?, auth_cashbox = ? WHERE user = ?";
         let mut statement = connection.prepare(query)?;
         let _inserted_row_count = statement.execute((auth.auth_users, auth.auth_products, auth.auth_cashbox, auth.id))?;
+        audit_log(&connection, actor, "set_auth", "user", auth.id, to_json(&before), to_json(&auth))?;
         Ok(())
     }
 
@@ -891,24 +1699,137 @@ impl Database {
 		Ok(result)
     }
 
+    /* consumption by system users (user < 0) is valued at purchase price like in the invoice view */
+    fn get_profit_report(&mut self, from: i64, to: i64) -> Result<Vec<ProfitReportEntry>, DatabaseError> {
+        let query = "SELECT sales.rowid, products.id, products.name, COALESCE(categories.name, ''), user, (SELECT CASE WHEN user=0 THEN guestprice else memberprice END FROM prices WHERE prices.product = sales.product AND valid_from <= sales.timestamp ORDER BY valid_from DESC LIMIT 1) FROM sales INNER JOIN products ON sales.product = products.id LEFT JOIN categories ON products.category = categories.id WHERE timestamp >= ? AND timestamp <= ? ORDER BY categories.name, products.name, products.id, sales.timestamp";
+		let mut result: Vec<ProfitReportEntry> = Vec::new();
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query((from, to))?;
+        let mut valuation = StockValuation::default();
+
+        while let Some(row) = rows.next()? {
+            let sale: i64 = row.get(0)?;
+            let ean: i64 = row.get(1)?;
+            let user: i32 = row.get(4)?;
+            let price: Option<i64> = row.get(5)?;
+
+            if result.last().is_none_or(|entry| entry.product.ean != ean) {
+                valuation = stock_valuation(&connection, ean, to, self.valuation)?;
+                result.push(ProfitReportEntry {
+                    product: Product {
+                        ean: ean,
+                        name: row.get(2)?,
+                    },
+                    category: row.get(3)?,
+                    units: 0,
+                    revenue: 0,
+                    cost: 0,
+                });
+            }
+
+            let cost = valuation.sale_costs.get(&sale).copied().unwrap_or_default();
+            let entry = result.last_mut().unwrap();
+            entry.units += 1;
+            entry.revenue += if user < 0 { cost } else { price.unwrap_or_default() };
+            entry.cost += cost;
+        }
+
+		Ok(result)
+    }
+
+    fn get_stock_value(&mut self, timestamp: i64) -> Result<Vec<StockValueEntry>, DatabaseError> {
+        let query = "SELECT products.id, products.name, COALESCE(categories.name, '') FROM products LEFT JOIN categories ON products.category = categories.id ORDER BY categories.name, products.name";
+		let mut result = Vec::new();
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query([])?;
+
+        while let Some(row) = rows.next()? {
+            let ean: i64 = row.get(0)?;
+            let valuation = stock_valuation(&connection, ean, timestamp, self.valuation)?;
+
+            if valuation.amount == 0 {
+                continue;
+            }
+
+            result.push(StockValueEntry {
+                product: Product {
+                    ean: ean,
+                    name: row.get(1)?,
+                },
+                category: row.get(2)?,
+                amount: valuation.amount as i32,
+                value: valuation.value(self.valuation),
+            });
+        }
+
+		Ok(result)
+    }
+
+    /* restock lots of a product, that have not yet been sold */
+    fn get_stock_lots(&mut self, ean: i64) -> Result<Vec<StockLot>, DatabaseError> {
+        let ean = self.ean_alias_get(ean)?;
+        let connection = self.pool.get()?;
+        let valuation = stock_valuation(&connection, ean, get_unix_time(), self.valuation)?;
+        Ok(valuation.lots.into_iter().filter(|lot| lot.remaining > 0).collect())
+    }
+
     fn get_invoice(&mut self, user: i32, from: i64, to: i64) -> Result<Vec<InvoiceEntry>, DatabaseError> {
-        let query = "SELECT timestamp, id AS productid, name AS productname, CASE WHEN user < 0 THEN (SELECT SUM(price * amount) / SUM(amount) FROM restock WHERE restock.product = id AND restock.timestamp <= sales.timestamp) else (SELECT CASE WHEN user=0 THEN guestprice else memberprice END FROM prices WHERE product = id AND valid_from <= timestamp ORDER BY valid_from DESC LIMIT 1) END AS price FROM sales INNER JOIN products ON sales.product = products.id WHERE user = ? AND timestamp >= ? AND timestamp <= ? ORDER BY timestamp";
+        let query = "SELECT timestamp, id AS productid, name AS productname, CASE WHEN user < 0 THEN 0 else (SELECT CASE WHEN user=0 THEN guestprice else memberprice END FROM prices WHERE product = id AND valid_from <= timestamp ORDER BY valid_from DESC LIMIT 1) END AS price, COALESCE((SELECT vat FROM category_vat WHERE category = products.category), 19) AS vat, sales.rowid FROM sales INNER JOIN products ON sales.product = products.id WHERE user = ? AND timestamp >= ? AND timestamp <= ? ORDER BY timestamp";
 		let mut result = Vec::new();
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
         let to = if to < 0 { get_unix_time() } else { to };
         let mut rows = statement.query((user, from, to))?;
+        let mut valuations: HashMap<i64, StockValuation> = HashMap::new();
 
         while let Some(row) = rows.next()? {
+            let ean: i64 = row.get(1)?;
+            let sale: i64 = row.get(5)?;
+
+            /* system users consume at purchase price */
+            let price = if user < 0 {
+                if !valuations.contains_key(&ean) {
+                    valuations.insert(ean, stock_valuation(&connection, ean, to, self.valuation)?);
+                }
+                valuations[&ean].sale_costs.get(&sale).copied().unwrap_or_default() as i32
+            } else {
+                row.get(3)?
+            };
+
             result.push(InvoiceEntry {
                 timestamp: row.get(0)?,
                 product: Product {
-                    ean: row.get(1)?,
+                    ean: ean,
                     name: row.get(2)?,
                 },
-                price: row.get(3)?,
+                price: price,
+                vat: row.get(4)?,
+            });
+        }
+
+        /* deposit is listed as separate line with EAN 0 right after the sale, returns are credited */
+        let query_deposit = "SELECT deposits.timestamp, products.name, deposits.amount, COALESCE((SELECT vat FROM category_vat WHERE category = products.category), 19) FROM deposits INNER JOIN products ON deposits.product = products.id WHERE user = ? AND deposits.timestamp >= ? AND deposits.timestamp <= ? ORDER BY deposits.timestamp";
+        let mut statement = connection.prepare(query_deposit)?;
+        let mut rows = statement.query((user, from, to))?;
+
+        while let Some(row) = rows.next()? {
+            let name: String = row.get(1)?;
+            let amount: i32 = row.get(2)?;
+            let label = if amount < 0 { "Pfandrückgabe" } else { "Pfand" };
+
+            result.push(InvoiceEntry {
+                timestamp: row.get(0)?,
+                product: Product {
+                    ean: 0,
+                    name: format!("{}: {}", label, name),
+                },
+                price: amount,
+                vat: row.get(3)?,
             });
         }
+        result.sort_by_key(|entry| entry.timestamp);
 
 		Ok(result)
     }
@@ -990,11 +1911,12 @@ impl Database {
 		Ok(result)
     }
 
-    fn user_disable(&mut self, user: i32, value: bool) -> Result<(), DatabaseError> {
+    fn user_disable(&mut self, actor: i32, user: i32, value: bool) -> Result<(), DatabaseError> {
         let query = "UPDATE users SET disabled = ? WHERE id = ?";
-        // revoke permissions for disabled accounts
+        let before = self.user_is_disabled(user)?;
+        // revoke permissions, sessions and API tokens of disabled accounts
         if value == true {
-            self.set_user_auth(UserAuth {
+            self.set_user_auth(actor, UserAuth {
                 id: user,
                 superuser: false,
                 auth_users: false,
@@ -1005,10 +1927,17 @@ impl Database {
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
         let _inserted_row_count = statement.execute((value, user))?;
+        if value {
+            connection.execute("DELETE FROM sessions WHERE user = ?", [user])?;
+            connection.execute("DELETE FROM api_tokens WHERE user = ?", [user])?;
+        }
+        audit_log(&connection, actor, "disable", "user", user, to_json(&before), to_json(&value))?;
         Ok(())
     }
 
-    fn user_replace(&mut self, u: UserInfo) -> Result<(), DatabaseError> {
+    fn user_replace(&mut self, actor: i32, u: UserInfo) -> Result<(), DatabaseError> {
+        let before = self.get_user_info(u.id).ok();
+        let after = to_json(&u);
         let connection = self.pool.get()?;
 
         let query = "INSERT OR REPLACE INTO users ('id', 'email', 'firstname', 'lastname', 'gender', 'street', 'plz', 'city', 'pgp', 'hidden', 'disabled', 'joined_at', 'sound_theme') VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, (select sound_theme from users where id = ?))";
@@ -1026,6 +1955,7 @@ impl Database {
             let _inserted_row_count = statement.execute((u.id, rfid))?;
 		}
 
+        audit_log(&connection, actor, "replace", "user", u.id, to_json(&before), after)?;
         Ok(())
     }
 
@@ -1058,7 +1988,7 @@ impl Database {
         }
     }
 
-    fn add_category(&mut self, name: String) -> Result<(), DatabaseError> {
+    fn add_category(&mut self, actor: i32, name: String) -> Result<(), DatabaseError> {
 		/* check if category already exists */
         for c in self.get_category_list()? {
 			if name == c.name {
@@ -1069,148 +1999,752 @@ impl Database {
         let query = "INSERT INTO categories('name') VALUES (?)";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let _inserted_row_count = statement.execute([name])?;
+        let _inserted_row_count = statement.execute([&name])?;
+        let after = serde_json::json!({"name": name});
+        audit_log(&connection, actor, "create", "category", connection.last_insert_rowid(), None, Some(after))?;
         Ok(())
     }
 
-    fn add_supplier(&mut self, name: String, postal_code: String, city: String, street: String, phone: String, website: String) -> Result<(), DatabaseError> {
+    fn add_supplier(&mut self, actor: i32, name: String, postal_code: String, city: String, street: String, phone: String, website: String) -> Result<(), DatabaseError> {
         let query = "INSERT INTO supplier('name', 'postal_code', 'city', 'street', 'phone', 'website') VALUES (?, ?, ?, ?, ?, ?)";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let _inserted_row_count = statement.execute((name, postal_code, city, street, phone, website))?;
+        let _inserted_row_count = statement.execute((&name, &postal_code, &city, &street, &phone, &website))?;
+        let after = serde_json::json!({"name": name, "postal_code": postal_code, "city": city, "street": street, "phone": phone, "website": website});
+        audit_log(&connection, actor, "create", "supplier", connection.last_insert_rowid(), None, Some(after))?;
         Ok(())
     }
 
-    fn get_users_with_sales(&mut self, timestamp_from: i64, timestamp_to: i64) -> Result<Vec<i32>, DatabaseError> {
-        let query = "SELECT user FROM sales WHERE timestamp > ? AND timestamp < ? GROUP BY user";
-		let mut result = Vec::new();
+    fn get_order_suggestion(&mut self, supplier: i32, coverage: u32, history: u32) -> Result<(u32, u32, Vec<OrderSuggestion>), DatabaseError> {
+        let query = "SELECT products.id, products.name, products.amount, COALESCE(product_metadata.container_size, 0) FROM products LEFT JOIN product_metadata ON product_metadata.product = products.id WHERE products.deprecated = false AND products.id IN (SELECT product FROM restock WHERE supplier = ?1 AND timestamp > ?2 UNION SELECT product FROM supplier_catalogue WHERE supplier = ?1) ORDER BY products.name ASC";
+        let query_events = "SELECT timestamp, amount FROM restock WHERE product = ?1 AND timestamp > ?2 UNION ALL SELECT timestamp, -1 FROM sales WHERE product = ?1 AND timestamp > ?2 ORDER BY timestamp ASC";
+        let coverage = if coverage > 0 { coverage } else { self.order_coverage };
+        let history = if history > 0 { history } else { self.order_history };
+        let now = get_unix_time();
+        let start = now - history as i64 * 86400;
+
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([timestamp_from, timestamp_to])?;
+        let products = statement.query_map((supplier, now - 365 * 86400), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, row.get::<_, i32>(2)?, row.get::<_, u32>(3)?)))?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut statement = connection.prepare(query_events)?;
+        let mut result = Vec::new();
+
+        for (ean, name, stock, container_size) in products {
+            let events = statement.query_map((ean, start), |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i32>(1)?)))?
+                .collect::<Result<Vec<_>, _>>()?;
+
+            /* walk through the history period starting with the stock at its beginning */
+            let mut level = stock - events.iter().map(|(_, amount)| amount).sum::<i32>();
+            let mut events = events.iter().peekable();
+            let mut sold = 0;
+            let mut selling_days = 0;
+
+            for day in 0..history as i64 {
+                let day_end = start + (day + 1) * 86400;
+                let mut available = level > 0;
+
+                while let Some((_, amount)) = events.next_if(|(timestamp, _)| *timestamp <= day_end) {
+                    if *amount < 0 {
+                        sold += 1;
+                        available = true;
+                    }
+                    level += amount;
+                }
 
-        while let Some(row) = rows.next()? {
-            result.push(row.get(0)?);
+                /* days on which the product was sold out do not tell anything about the demand */
+                if available {
+                    selling_days += 1;
+                }
+            }
+
+            let velocity = if selling_days > 0 { sold as f64 / selling_days as f64 } else { 0.0 };
+            let demand = (velocity * coverage as f64).ceil() as i64 - stock.max(0) as i64;
+            let mut suggestion = demand.max(0) as u32;
+            if container_size > 0 {
+                suggestion = suggestion.div_ceil(container_size) * container_size;
+            }
+
+            result.push(OrderSuggestion {
+                product: Product {
+                    ean: ean,
+                    name: name,
+                },
+                stock: stock,
+                sold: sold,
+                selling_days: selling_days,
+                velocity: velocity,
+                container_size: container_size,
+                suggestion: suggestion,
+            });
         }
 
-		Ok(result)
+        Ok((coverage, history, result))
     }
 
-    fn get_user_invoice_sum(&mut self, user: i32, timestamp_from: i64, timestamp_to: i64) -> Result<i32, DatabaseError> {
-        let query = "SELECT SUM(CASE WHEN user < 0 THEN (SELECT SUM(price * amount) / SUM(amount) FROM restock WHERE restock.product = id AND restock.timestamp <= sales.timestamp) else (SELECT CASE WHEN user=0 THEN guestprice else memberprice END FROM prices WHERE product = id AND valid_from <= timestamp ORDER BY valid_from DESC LIMIT 1) END) FROM sales INNER JOIN products ON sales.product = products.id WHERE user = ? AND timestamp >= ? AND timestamp <= ? ORDER BY timestamp";
-        let connection = self.pool.get()?;
-        let mut statement = connection.prepare(query)?;
-        let response = statement.query_row((user, timestamp_from, timestamp_to), |r| r.get(0));
-        match response {
-            Ok(price) => Ok(price),
-            Err(err) => {
-                match err {
-                    r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows => Ok(0),
-                    _ => Err(err.into()),
-                }
+    fn purchase_order_create(&mut self, actor: i32, supplier: i32, items: Vec<(i64, u32)>) -> Result<i64, DatabaseError> {
+        let query = "INSERT INTO purchase_orders ('supplier', 'user', 'created', 'status') VALUES (?, ?, ?, 'draft')";
+        let query_item = "INSERT OR REPLACE INTO purchase_order_items ('purchase_order', 'product', 'amount', 'price') VALUES (?, ?, ?, ?)";
+        let timestamp = get_unix_time();
+
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+
+        transaction.execute(query, (supplier, actor, timestamp))?;
+        let id = transaction.last_insert_rowid();
+
+        for (product, amount) in &items {
+            if *amount == 0 {
+                continue;
             }
+            let price: u32 = transaction.query_row(PURCHASE_ORDER_PRICE_QUERY, (product, supplier), |r| r.get::<_, Option<u32>>(0))?.unwrap_or(0);
+            transaction.execute(query_item, (id, product, amount, price))?;
         }
+
+        let after = serde_json::json!({"supplier": supplier, "items": items});
+        audit_log(&transaction, actor, "create", "purchase_order", id, None, Some(after))?;
+        transaction.commit()?;
+        Ok(id)
     }
 
-    fn cashbox_status(&mut self) -> Result<i32, DatabaseError> {
-        let query = "SELECT amount FROM current_cashbox_status";
+    fn get_purchase_orders(&mut self, status: &str) -> Result<Vec<PurchaseOrder>, DatabaseError> {
+        if !status.is_empty() && !PURCHASE_ORDER_STATES.contains(&status) {
+            return Err(DatabaseError::InvalidArgument(format!("unknown purchase order status '{}'", status)));
+        }
+
+        let query = format!("{} WHERE ? = '' OR status = ? ORDER BY created DESC", PURCHASE_ORDER_QUERY);
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(&query)?;
+        let rows = statement.query_map([status, status], purchase_order_from_row)?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn get_purchase_order(&mut self, id: i64) -> Result<PurchaseOrder, DatabaseError> {
+        let query = format!("{} WHERE id = ?", PURCHASE_ORDER_QUERY);
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(&query)?;
+        Ok(statement.query_row([id], purchase_order_from_row)?)
+    }
+
+    fn get_purchase_order_items(&mut self, id: i64) -> Result<Vec<PurchaseOrderItem>, DatabaseError> {
+        let query = "SELECT purchase_order_items.product, products.name, purchase_order_items.amount, purchase_order_items.price, COALESCE(purchase_order_items.received, -1), COALESCE(purchase_order_items.best_before_date, 0), COALESCE(supplier_catalogue.article_number, '') FROM purchase_order_items JOIN products ON products.id = purchase_order_items.product JOIN purchase_orders ON purchase_orders.id = purchase_order_items.purchase_order LEFT JOIN supplier_catalogue ON supplier_catalogue.supplier = purchase_orders.supplier AND supplier_catalogue.product = purchase_order_items.product WHERE purchase_order_items.purchase_order = ? ORDER BY products.name ASC";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let response = statement.query_row([], |r| r.get(0));
-        match response {
-            Ok(price) => Ok(price),
-            Err(err) => {
-                match err {
-                    r2d2_sqlite::rusqlite::Error::QueryReturnedNoRows => Ok(0),
-                    _ => Err(err.into()),
-                }
+        let rows = statement.query_map([id], |row| Ok(PurchaseOrderItem {
+            product: Product {
+                ean: row.get(0)?,
+                name: row.get(1)?,
+            },
+            amount: row.get(2)?,
+            price: row.get(3)?,
+            received: row.get(4)?,
+            best_before_date: row.get(5)?,
+            article_number: row.get(6)?,
+        }))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn purchase_order_set_item(&mut self, actor: i32, id: i64, product: i64, amount: u32, price: u32) -> Result<(), DatabaseError> {
+        let query_insert = "INSERT INTO purchase_order_items ('purchase_order', 'product', 'amount', 'price') VALUES (?1, ?2, ?3, ?4) ON CONFLICT (purchase_order, product) DO UPDATE SET amount = ?3, price = ?4";
+        let query_delete = "DELETE FROM purchase_order_items WHERE purchase_order = ? AND product = ?";
+
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+
+        let (supplier, status): (i32, String) = transaction.query_row("SELECT supplier, status FROM purchase_orders WHERE id = ?", [id], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        if status != "draft" {
+            return Err(DatabaseError::InvalidArgument("only draft purchase orders can be edited".to_string()));
+        }
+
+        let price = match price {
+            0 => transaction.query_row(PURCHASE_ORDER_PRICE_QUERY, (product, supplier), |r| r.get::<_, Option<u32>>(0))?.unwrap_or(0),
+            price => price,
+        };
+
+        let before = transaction.query_row("SELECT amount, price FROM purchase_order_items WHERE purchase_order = ? AND product = ?", (id, product), |r| Ok(serde_json::json!({"product": product, "amount": r.get::<_, u32>(0)?, "price": r.get::<_, u32>(1)?}))).optional()?;
+
+        if amount == 0 {
+            transaction.execute(query_delete, (id, product))?;
+        } else {
+            transaction.execute(query_insert, (id, product, amount, price))?;
+        }
+
+        let after = serde_json::json!({"product": product, "amount": amount, "price": price});
+        audit_log(&transaction, actor, "update_item", "purchase_order", id, before, Some(after))?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn purchase_order_set_comment(&mut self, actor: i32, id: i64, comment: &str) -> Result<(), DatabaseError> {
+        let connection = self.pool.get()?;
+        let old_comment: String = connection.query_row("SELECT comment FROM purchase_orders WHERE id = ?", [id], |r| r.get(0))?;
+        connection.execute("UPDATE purchase_orders SET comment = ? WHERE id = ?", (comment, id))?;
+        audit_log(&connection, actor, "update", "purchase_order", id, Some(serde_json::json!({"comment": old_comment})), Some(serde_json::json!({"comment": comment})))?;
+        Ok(())
+    }
+
+    fn purchase_order_set_status(&mut self, actor: i32, id: i64, status: &str) -> Result<(), DatabaseError> {
+        let connection = self.pool.get()?;
+        let old_status: String = connection.query_row("SELECT status FROM purchase_orders WHERE id = ?", [id], |r| r.get(0))?;
+
+        /* receiving is handled by purchase_order_receive */
+        let allowed = match status {
+            "draft" => old_status == "ordered",
+            "ordered" => old_status == "draft",
+            "cancelled" => old_status == "draft" || old_status == "ordered",
+            _ => false,
+        };
+        if !allowed {
+            return Err(DatabaseError::InvalidArgument(format!("cannot change purchase order from '{}' to '{}'", old_status, status)));
+        }
+
+        if status == "ordered" {
+            let items: u32 = connection.query_row("SELECT COUNT(*) FROM purchase_order_items WHERE purchase_order = ?", [id], |r| r.get(0))?;
+            if items == 0 {
+                return Err(DatabaseError::InvalidArgument("purchase order has no items".to_string()));
             }
+            connection.execute("UPDATE purchase_orders SET status = ?, ordered = ? WHERE id = ?", (status, get_unix_time(), id))?;
+        } else {
+            connection.execute("UPDATE purchase_orders SET status = ? WHERE id = ?", (status, id))?;
         }
+
+        audit_log(&connection, actor, "status", "purchase_order", id, Some(serde_json::json!({"status": old_status})), Some(serde_json::json!({"status": status})))?;
+        Ok(())
     }
 
-    fn cashbox_add(&mut self, user: i32, amount: i32, timestamp: i64) -> Result<(), DatabaseError> {
-        let query = "INSERT INTO cashbox_diff ('user', 'amount', 'timestamp') VALUES (?, ?, ?)";
+    fn purchase_order_receive(&mut self, actor: i32, id: i64, deliveries: Vec<PurchaseOrderDelivery>) -> Result<(), DatabaseError> {
+        let query_restock = "INSERT INTO restock ('user', 'product', 'amount', 'price', 'timestamp', 'supplier', 'best_before_date') VALUES (?, ?, ?, ?, ?, ?, ?)";
+        /* products which have not been ordered are added with an ordered amount of 0 */
+        let query_item = "INSERT INTO purchase_order_items ('purchase_order', 'product', 'amount', 'price', 'received', 'best_before_date') VALUES (?1, ?2, 0, ?4, ?3, ?5) ON CONFLICT (purchase_order, product) DO UPDATE SET received = ?3, price = ?4, best_before_date = ?5";
+        let timestamp = get_unix_time();
+
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+
+        let (supplier, status): (i32, String) = transaction.query_row("SELECT supplier, status FROM purchase_orders WHERE id = ?", [id], |r| Ok((r.get(0)?, r.get(1)?)))?;
+        if status != "ordered" {
+            return Err(DatabaseError::InvalidArgument("only ordered purchase orders can be received".to_string()));
+        }
+
+        /* everything not listed in the delivery is missing */
+        transaction.execute("UPDATE purchase_order_items SET received = 0 WHERE purchase_order = ?", [id])?;
+
+        for delivery in &deliveries {
+            transaction.execute(query_item, (id, delivery.product, delivery.amount, delivery.price, delivery.best_before_date))?;
+
+            if delivery.amount == 0 {
+                continue;
+            }
+
+            transaction.execute(query_restock, (actor, delivery.product, delivery.amount, delivery.price, timestamp, supplier, delivery.best_before_date))?;
+            let after = serde_json::json!({"user": actor, "amount": delivery.amount, "price": delivery.price, "timestamp": timestamp, "supplier": supplier, "best_before_date": delivery.best_before_date, "purchase_order": id});
+            audit_log(&transaction, actor, "restock", "product", delivery.product, None, Some(after))?;
+            supplier_deposit_add(&transaction, actor, supplier, delivery.product, delivery.amount, timestamp)?;
+        }
+
+        transaction.execute("UPDATE purchase_orders SET status = 'received', received = ? WHERE id = ?", (timestamp, id))?;
+        audit_log(&transaction, actor, "status", "purchase_order", id, Some(serde_json::json!({"status": status})), Some(serde_json::json!({"status": "received"})))?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn supplier_update(&mut self, actor: i32, supplier: Supplier) -> Result<(), DatabaseError> {
+        let query = "UPDATE supplier SET name = ?, postal_code = ?, city = ?, street = ?, phone = ?, website = ? WHERE id = ?";
+        let query_details = "INSERT INTO supplier_details ('supplier', 'contact_person', 'email', 'customer_number', 'minimum_order', 'archived') VALUES (?1, ?2, ?3, ?4, ?5, ?6) ON CONFLICT (supplier) DO UPDATE SET contact_person = ?2, email = ?3, customer_number = ?4, minimum_order = ?5, archived = ?6";
+        let before = self.get_supplier(supplier.id as i32)?;
+
+        if before.id == 0 {
+            return Err(DatabaseError::InvalidArgument(format!("unknown supplier {}", supplier.id)));
+        }
+
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+        transaction.execute(query, (&supplier.name, &supplier.postal_code, &supplier.city, &supplier.street, &supplier.phone, &supplier.website, supplier.id))?;
+        transaction.execute(query_details, (supplier.id, &supplier.contact_person, &supplier.email, &supplier.customer_number, supplier.minimum_order, supplier.archived))?;
+        audit_log(&transaction, actor, "update", "supplier", supplier.id, to_json(&before), to_json(&supplier))?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn get_supplier_catalogue(&mut self, supplier: i32) -> Result<Vec<SupplierCatalogueEntry>, DatabaseError> {
+        let query = "SELECT supplier_catalogue.supplier, products.id, products.name, supplier_catalogue.article_number, supplier_catalogue.price, supplier_catalogue.updated FROM supplier_catalogue, products WHERE products.id = supplier_catalogue.product AND supplier_catalogue.supplier = ? ORDER BY products.name ASC";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let rows = statement.query_map([supplier], |row| Ok(SupplierCatalogueEntry {
+            supplier: row.get(0)?,
+            product: Product {
+                ean: row.get(1)?,
+                name: row.get(2)?,
+            },
+            article_number: row.get(3)?,
+            price: row.get(4)?,
+            updated: row.get(5)?,
+        }))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn get_product_catalogue(&mut self, product: i64) -> Result<Vec<SupplierCatalogueEntry>, DatabaseError> {
+        let query = "SELECT supplier_catalogue.supplier, products.id, products.name, supplier_catalogue.article_number, supplier_catalogue.price, supplier_catalogue.updated FROM supplier_catalogue, products WHERE products.id = supplier_catalogue.product AND supplier_catalogue.product = ? ORDER BY supplier_catalogue.supplier ASC";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let _inserted_row_count = statement.execute((user, amount, timestamp))?;
+        let rows = statement.query_map([product], |row| Ok(SupplierCatalogueEntry {
+            supplier: row.get(0)?,
+            product: Product {
+                ean: row.get(1)?,
+                name: row.get(2)?,
+            },
+            article_number: row.get(3)?,
+            price: row.get(4)?,
+            updated: row.get(5)?,
+        }))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn supplier_catalogue_set(&mut self, actor: i32, supplier: i32, product: i64, article_number: &str, price: u32) -> Result<(), DatabaseError> {
+        let query = "INSERT INTO supplier_catalogue ('supplier', 'product', 'article_number', 'price', 'updated') VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT (supplier, product) DO UPDATE SET article_number = ?3, price = ?4, updated = ?5";
+        let connection = self.pool.get()?;
+        let before = connection.query_row("SELECT article_number, price FROM supplier_catalogue WHERE supplier = ? AND product = ?", (supplier, product), |r| Ok(serde_json::json!({"product": product, "article_number": r.get::<_, String>(0)?, "price": r.get::<_, u32>(1)?}))).optional()?;
+        connection.execute(query, (supplier, product, article_number, price, get_unix_time()))?;
+        let after = serde_json::json!({"product": product, "article_number": article_number, "price": price});
+        audit_log(&connection, actor, "catalogue_set", "supplier", supplier as i64, before, Some(after))?;
+        Ok(())
+    }
+
+    fn supplier_catalogue_remove(&mut self, actor: i32, supplier: i32, product: i64) -> Result<(), DatabaseError> {
+        let query = "DELETE FROM supplier_catalogue WHERE supplier = ? AND product = ?";
+        let connection = self.pool.get()?;
+        let before = connection.query_row("SELECT article_number, price FROM supplier_catalogue WHERE supplier = ? AND product = ?", (supplier, product), |r| Ok(serde_json::json!({"product": product, "article_number": r.get::<_, String>(0)?, "price": r.get::<_, u32>(1)?}))).optional()?;
+        let removed = connection.execute(query, (supplier, product))?;
+        if removed > 0 {
+            audit_log(&connection, actor, "catalogue_remove", "supplier", supplier as i64, before, None)?;
+        }
         Ok(())
     }
 
-    fn cashbox_history(&mut self) -> Result<Vec<CashboxDiff>, DatabaseError> {
-        let query = "SELECT user, amount, timestamp FROM cashbox_diff ORDER BY timestamp DESC LIMIT 10";
+    fn supplier_deposit_return(&mut self, actor: i32, supplier: i32, amount: u32, comment: &str) -> Result<i64, DatabaseError> {
+        let query = "INSERT INTO supplier_deposits ('supplier', 'user', 'timestamp', 'amount', 'comment') VALUES (?, ?, ?, ?, ?)";
+
+        if amount == 0 {
+            return Err(DatabaseError::InvalidArgument("refunded deposit must not be zero".to_string()));
+        }
+        if self.get_supplier(supplier)?.id == 0 {
+            return Err(DatabaseError::InvalidArgument(format!("unknown supplier {}", supplier)));
+        }
+
+        let timestamp = get_unix_time();
+        let connection = self.pool.get()?;
+        connection.execute(query, (supplier, actor, timestamp, -(amount as i32), comment))?;
+        let id = connection.last_insert_rowid();
+        let after = serde_json::json!({"supplier": supplier, "amount": -(amount as i32), "timestamp": timestamp, "comment": comment});
+        audit_log(&connection, actor, "create", "supplier_deposit", id, None, Some(after))?;
+        Ok(id)
+    }
+
+    /* supplier 0 lists the bookings of all suppliers */
+    fn get_supplier_deposits(&mut self, supplier: i32, from: i64, to: i64) -> Result<Vec<SupplierDeposit>, DatabaseError> {
+        let query = "SELECT id, supplier, user, COALESCE(product, 0), timestamp, amount, comment FROM supplier_deposits WHERE (?1 = 0 OR supplier = ?1) AND timestamp >= ?2 AND timestamp <= ?3 ORDER BY timestamp DESC";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let rows = statement.query_map((supplier, from, to), |row| Ok(SupplierDeposit {
+            id: row.get(0)?,
+            supplier: row.get(1)?,
+            user: row.get(2)?,
+            product: row.get(3)?,
+            timestamp: row.get(4)?,
+            amount: row.get(5)?,
+            comment: row.get(6)?,
+        }))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /* deposit paid to suppliers and not yet refunded, the shop's asset */
+    fn get_deposit_balances(&mut self) -> Result<Vec<DepositBalance>, DatabaseError> {
+        let query = "SELECT supplier.id, supplier.name, SUM(CASE WHEN amount > 0 THEN amount ELSE 0 END), -SUM(CASE WHEN amount < 0 THEN amount ELSE 0 END), SUM(amount) FROM supplier_deposits INNER JOIN supplier ON supplier.id = supplier_deposits.supplier GROUP BY supplier.id ORDER BY supplier.name ASC";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let rows = statement.query_map([], |row| Ok(DepositBalance {
+            supplier: row.get(0)?,
+            name: row.get(1)?,
+            paid: row.get(2)?,
+            refunded: row.get(3)?,
+            balance: row.get(4)?,
+        }))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /* deposit paid by members and guests for bottles, which have not been returned yet */
+    fn get_customer_deposit(&mut self) -> Result<i32, DatabaseError> {
+        let query = "SELECT COALESCE(SUM(amount), 0) FROM deposits";
+        let connection = self.pool.get()?;
+        let deposit = connection.query_row(query, [], |r| r.get(0))?;
+        Ok(deposit)
+    }
+
+    fn get_users_with_sales(&mut self, timestamp_from: i64, timestamp_to: i64) -> Result<Vec<i32>, DatabaseError> {
+        /* users, who only returned deposit, are credited as well */
+        let query = "SELECT user FROM sales WHERE timestamp > ?1 AND timestamp < ?2 UNION SELECT user FROM deposits WHERE timestamp > ?1 AND timestamp < ?2";
 		let mut result = Vec::new();
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([])?;
+        let mut rows = statement.query([timestamp_from, timestamp_to])?;
 
         while let Some(row) = rows.next()? {
-            result.push(CashboxDiff {
-                user: row.get(0)?,
-                amount: row.get(1)?,
-                timestamp: row.get(2)?,
-            });
+            result.push(row.get(0)?);
         }
 
 		Ok(result)
     }
 
-    fn cashbox_changes(&mut self, start: i64, stop: i64) -> Result<Vec<CashboxDiff>, DatabaseError> {
-        let query = "SELECT user, amount, timestamp FROM cashbox_diff WHERE timestamp >= ? and timestamp < ? ORDER BY timestamp ASC";
+    /* sum of the invoice lines, so that system users are charged with the same stock valuation */
+    fn get_user_invoice_sum(&mut self, user: i32, timestamp_from: i64, timestamp_to: i64) -> Result<i32, DatabaseError> {
+        let entries = self.get_invoice(user, timestamp_from, timestamp_to)?;
+        Ok(entries.iter().map(|entry| entry.price).sum())
+    }
+
+    /* stored invoices are never replaced, the invoice ID must be unique and the mailed PDF is kept unchanged */
+    fn invoice_add(&mut self, invoice: InvoiceInfo, pdf: Vec<u8>) -> Result<(), DatabaseError> {
+        let query = "INSERT INTO invoices ('id', 'user', 'timestamp', 'period_from', 'period_to', 'amount') VALUES (?, ?, ?, ?, ?, ?)";
+        let query_pdf = "INSERT INTO invoice_pdfs ('invoice', 'pdf') VALUES (?, ?)";
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+        let exists = transaction.query_row("SELECT 1 FROM invoices WHERE id = ?", [&invoice.id], |r| r.get::<_, i32>(0)).optional()?;
+        if exists.is_some() {
+            return Err(DatabaseError::InvalidArgument(format!("invoice {} already exists", invoice.id)));
+        }
+        if pdf.is_empty() {
+            return Err(DatabaseError::InvalidArgument(format!("invoice {} has no PDF", invoice.id)));
+        }
+
+        transaction.execute(query, (&invoice.id, invoice.user, invoice.timestamp, invoice.period_from, invoice.period_to, invoice.amount))?;
+        let rowid = transaction.last_insert_rowid();
+        transaction.execute(query_pdf, (&invoice.id, pdf))?;
+
+        /* invoices are created by the invoicer without a logged in user, so they are logged for the invoiced user */
+        let after = serde_json::json!({"id": invoice.id, "period_from": invoice.period_from, "period_to": invoice.period_to, "amount": invoice.amount});
+        audit_log(&transaction, invoice.user, "create", "invoice", rowid, None, Some(after))?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /* empty for invoices recorded before the PDFs were stored */
+    fn get_invoice_pdf(&mut self, id: &str) -> Result<Vec<u8>, DatabaseError> {
+        let query = "SELECT pdf FROM invoice_pdfs WHERE invoice = ?";
+        let connection = self.pool.get()?;
+        let pdf = connection.query_row(query, [id], |r| r.get(0)).optional()?;
+        Ok(pdf.unwrap_or_default())
+    }
+
+    fn get_user_invoices(&mut self, user: i32) -> Result<Vec<InvoiceInfo>, DatabaseError> {
+        let query = "SELECT id, user, timestamp, period_from, period_to, amount FROM invoices WHERE user = ? ORDER BY period_from DESC";
 		let mut result = Vec::new();
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let mut rows = statement.query([start, stop])?;
+        let mut rows = statement.query([user])?;
 
         while let Some(row) = rows.next()? {
-            result.push(CashboxDiff {
-                user: row.get(0)?,
-                amount: row.get(1)?,
+            result.push(InvoiceInfo {
+                id: row.get(0)?,
+                user: row.get(1)?,
                 timestamp: row.get(2)?,
+                period_from: row.get(3)?,
+                period_to: row.get(4)?,
+                amount: row.get(5)?,
             });
         }
 
 		Ok(result)
     }
 
-    fn ean_alias_add(&mut self, ean: i64, real_ean: i64) -> Result<(), DatabaseError> {
+    fn get_invoice_info(&mut self, id: &str) -> Result<InvoiceInfo, DatabaseError> {
+        let query = "SELECT id, user, timestamp, period_from, period_to, amount FROM invoices WHERE id = ?";
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let invoice = statement.query_row([id], |r| Ok(InvoiceInfo {
+            id: r.get(0)?,
+            user: r.get(1)?,
+            timestamp: r.get(2)?,
+            period_from: r.get(3)?,
+            period_to: r.get(4)?,
+            amount: r.get(5)?,
+        }))?;
+        Ok(invoice)
+    }
+
+    fn dispute_create(&mut self, user: i32, timestamp: i64, product: i64, comment: &str) -> Result<i64, DatabaseError> {
+        /*
+         * sales have no stable id (VACUUM may renumber the rowid), so disputes reference them by
+         * user, product and timestamp. Identical sales are interchangeable, but each of them can
+         * only be reported once.
+         */
+        let query = "SELECT (SELECT COUNT(*) FROM sales WHERE user = ?1 AND product = ?2 AND timestamp = ?3) - (SELECT COUNT(*) FROM disputes WHERE user = ?1 AND product = ?2 AND timestamp = ?3 AND state = 'open')";
+        let connection = self.pool.get()?;
+        let unreported: i64 = connection.query_row(query, (user, product, timestamp), |r| r.get(0))?;
+        if unreported <= 0 {
+            return Err(DatabaseError::InvalidArgument("no matching sale, which has not yet been reported".to_string()));
+        }
+
+        let query = "INSERT INTO disputes (user, product, timestamp, comment, created) VALUES (?, ?, ?, ?, ?)";
+        let mut statement = connection.prepare(query)?;
+        let _inserted_row_count = statement.execute((user, product, timestamp, comment, get_unix_time()))?;
+        let id = connection.last_insert_rowid();
+
+        let after = serde_json::json!({"product": product, "timestamp": timestamp, "comment": comment});
+        audit_log(&connection, user, "create", "dispute", id, None, Some(after))?;
+        Ok(id)
+    }
+
+    fn get_user_disputes(&mut self, user: i32) -> Result<Vec<DisputeInfo>, DatabaseError> {
+        let query = format!("{} WHERE user = ? ORDER BY created DESC", DISPUTE_QUERY);
+        let mut result = Vec::new();
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(&query)?;
+        let mut rows = statement.query([user])?;
+
+        while let Some(row) = rows.next()? {
+            result.push(dispute_from_row(row)?);
+        }
+
+        Ok(result)
+    }
+
+    fn get_disputes(&mut self, only_open: bool) -> Result<Vec<DisputeInfo>, DatabaseError> {
+        let query = if only_open {
+            format!("{} WHERE state = 'open' ORDER BY created ASC", DISPUTE_QUERY)
+        } else {
+            format!("{} ORDER BY state = 'open' DESC, created DESC", DISPUTE_QUERY)
+        };
+        let mut result = Vec::new();
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(&query)?;
+        let mut rows = statement.query([])?;
+
+        while let Some(row) = rows.next()? {
+            result.push(dispute_from_row(row)?);
+        }
+
+        Ok(result)
+    }
+
+    /* actor = 0 and entity = "" disable the respective filter */
+    fn get_audit_log(&mut self, actor: i32, entity: &str, from: i64, to: i64) -> Result<Vec<AuditEntry>, DatabaseError> {
+        let query = "SELECT id, timestamp, actor, action, entity, entity_id, before, after FROM audit_log WHERE (?1 = 0 OR actor = ?1) AND (?2 = '' OR entity = ?2) AND timestamp >= ?3 AND timestamp < ?4 ORDER BY timestamp DESC, id DESC";
+        let mut result = Vec::new();
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(query)?;
+        let mut rows = statement.query((actor, entity, from, to))?;
+
+        while let Some(row) = rows.next()? {
+            result.push(AuditEntry {
+                id: row.get(0)?,
+                timestamp: row.get(1)?,
+                actor: row.get(2)?,
+                action: row.get(3)?,
+                entity: row.get(4)?,
+                entity_id: row.get(5)?,
+                before: row.get::<_, Option<String>>(6)?.unwrap_or_default(),
+                after: row.get::<_, Option<String>>(7)?.unwrap_or_default(),
+            });
+        }
+
+        Ok(result)
+    }
+
+    /* action is one of 'reject', 'remove' (delete the sale) or 'reassign' (move the sale to another user) */
+    fn dispute_resolve(&mut self, dispute: i64, admin: i32, action: &str, reassign_to: i32, resolution: &str) -> Result<(), DatabaseError> {
+        let state = match action {
+            "reject" => "rejected",
+            "remove" => "removed",
+            "reassign" => "reassigned",
+            _ => { return Err(DatabaseError::InvalidArgument(format!("unknown action: {}", action))); },
+        };
+
+        if action == "reassign" && !self.user_exists(reassign_to)? {
+            return Err(DatabaseError::InvalidArgument(format!("unknown user: {}", reassign_to)));
+        }
+
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+
+        let (user, product, timestamp, current_state): (i32, i64, i64, String) = transaction.query_row("SELECT user, product, timestamp, state FROM disputes WHERE id = ?", [dispute], |r| Ok((r.get(0)?, r.get(1)?, r.get(2)?, r.get(3)?)))?;
+        if current_state != "open" {
+            return Err(DatabaseError::InvalidArgument("dispute has already been resolved".to_string()));
+        }
+
+        /* any of the identical sales can be used, the rowid is only used within a single statement */
+        let query_remove = "DELETE FROM sales WHERE rowid = (SELECT rowid FROM sales WHERE user = ? AND product = ? AND timestamp = ? LIMIT 1)";
+        let query_reassign = "UPDATE sales SET user = ? WHERE rowid = (SELECT rowid FROM sales WHERE user = ? AND product = ? AND timestamp = ? LIMIT 1)";
+        let changed = match action {
+            "remove" => transaction.execute(query_remove, (user, product, timestamp))?,
+            "reassign" => transaction.execute(query_reassign, (reassign_to, user, product, timestamp))?,
+            _ => 1,
+        };
+        if changed == 0 {
+            return Err(DatabaseError::InvalidArgument("the disputed sale no longer exists".to_string()));
+        }
+
+        /* the deposit charged with the sale follows it */
+        match action {
+            "remove" => deposit_remove(&transaction, user, product, timestamp)?,
+            "reassign" => deposit_reassign(&transaction, user, product, timestamp, reassign_to)?,
+            _ => {},
+        };
+
+        let reassigned_to = if action == "reassign" { Some(reassign_to) } else { None };
+        let query = "UPDATE disputes SET state = ?, resolved_by = ?, resolved_at = ?, reassigned_to = ?, resolution = ? WHERE id = ?";
+        let _updated_row_count = transaction.execute(query, (state, admin, get_unix_time(), reassigned_to, resolution, dispute))?;
+
+        let after = serde_json::json!({"state": state, "reassigned_to": reassigned_to, "resolution": resolution});
+        audit_log(&transaction, admin, action, "dispute", dispute, Some(serde_json::json!({"state": current_state})), Some(after))?;
+
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn cashbox_status(&mut self) -> Result<i32, DatabaseError> {
+        let connection = self.pool.get()?;
+        cashbox_balance(&connection)
+    }
+
+    /* the sign of the amount is given by the entry type */
+    fn cashbox_add(&mut self, actor: i32, user: i32, entry_type: &str, amount: i32, timestamp: i64) -> Result<(), DatabaseError> {
+        let query = "INSERT INTO cashbox_diff ('user', 'amount', 'timestamp') VALUES (?, ?, ?)";
+        let query_type = "INSERT INTO cashbox_diff_types ('diff', 'type') VALUES (?, ?)";
+        let amount = match entry_type {
+            "withdrawal" | "loss" => -amount.abs(),
+            "deposit" | "donation" => amount.abs(),
+            _ => { return Err(DatabaseError::InvalidArgument(format!("unsupported cashbox entry type: {}", entry_type))); },
+        };
+
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+        transaction.execute(query, (user, amount, timestamp))?;
+        let id = transaction.last_insert_rowid();
+        transaction.execute(query_type, (id, entry_type))?;
+        let after = serde_json::json!({"user": user, "type": entry_type, "amount": amount, "timestamp": timestamp});
+        audit_log(&transaction, actor, "add", "cashbox", id, None, Some(after))?;
+        transaction.commit()?;
+        Ok(())
+    }
+
+    /* newest entries first together with the number of all matching entries, limit 0 returns everything */
+    fn get_cashbox_ledger(&mut self, from: i64, to: i64, entry_type: &str, offset: u32, limit: u32) -> Result<(u32, Vec<CashboxLedgerEntry>), DatabaseError> {
+        if !entry_type.is_empty() && !CASHBOX_ENTRY_TYPES.contains(&entry_type) {
+            return Err(DatabaseError::InvalidArgument(format!("unsupported cashbox entry type: {}", entry_type)));
+        }
+
+        let connection = self.pool.get()?;
+        let count = connection.query_row(&format!("SELECT COUNT(*) FROM ({})", CASHBOX_LEDGER_QUERY), (to, from, entry_type), |r| r.get(0))?;
+
+        /* a negative limit is no limit for sqlite */
+        let limit = match limit {
+            0 => -1,
+            limit => limit as i64,
+        };
+        let query = format!("{} ORDER BY timestamp DESC, id DESC LIMIT ?4 OFFSET ?5", CASHBOX_LEDGER_QUERY);
+        let mut statement = connection.prepare(&query)?;
+        let mut rows = statement.query((to, from, entry_type, limit, offset))?;
+        let mut entries = Vec::new();
+
+        while let Some(row) = rows.next()? {
+            entries.push(CashboxLedgerEntry {
+                id: row.get(0)?,
+                timestamp: row.get(1)?,
+                entry_type: row.get(2)?,
+                user: row.get(3)?,
+                amount: row.get(4)?,
+                balance: row.get(5)?,
+            });
+        }
+
+        Ok((count, entries))
+    }
+
+    /* store a physical cash count and book the discrepancy as count entry */
+    fn cashbox_count(&mut self, actor: i32, counted: i32, comment: &str) -> Result<i64, DatabaseError> {
+        let query_diff = "INSERT INTO cashbox_diff ('user', 'amount', 'timestamp') VALUES (?, ?, ?)";
+        let query_type = "INSERT INTO cashbox_diff_types ('diff', 'type') VALUES (?, 'count')";
+        let query = "INSERT INTO cashbox_counts ('user', 'timestamp', 'expected', 'counted', 'correction', 'comment') VALUES (?, ?, ?, ?, ?, ?)";
+        let timestamp = get_unix_time();
+
+        if counted < 0 {
+            return Err(DatabaseError::InvalidArgument("counted amount must not be negative".to_string()));
+        }
+
+        let mut connection = self.pool.get()?;
+        let transaction = connection.transaction()?;
+
+        let expected = cashbox_balance(&transaction)?;
+        let difference = counted - expected;
+
+        let correction = if difference != 0 {
+            transaction.execute(query_diff, (actor, difference, timestamp))?;
+            let correction = transaction.last_insert_rowid();
+            transaction.execute(query_type, [correction])?;
+            let after = serde_json::json!({"user": actor, "type": "count", "amount": difference, "timestamp": timestamp});
+            audit_log(&transaction, actor, "add", "cashbox", correction, None, Some(after))?;
+            Some(correction)
+        } else {
+            None
+        };
+
+        transaction.execute(query, (actor, timestamp, expected, counted, correction, comment))?;
+        let id = transaction.last_insert_rowid();
+        let after = serde_json::json!({"expected": expected, "counted": counted, "correction": correction, "comment": comment});
+        audit_log(&transaction, actor, "count", "cashbox", id, None, Some(after))?;
+
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    fn get_cashbox_counts(&mut self, from: i64, to: i64) -> Result<Vec<CashboxCount>, DatabaseError> {
+        let query = format!("{} WHERE timestamp >= ? AND timestamp <= ? ORDER BY timestamp DESC", CASHBOX_COUNT_QUERY);
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(&query)?;
+        let rows = statement.query_map((from, to), cashbox_count_from_row)?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    fn get_cashbox_count(&mut self, id: i64) -> Result<CashboxCount, DatabaseError> {
+        let query = format!("{} WHERE id = ?", CASHBOX_COUNT_QUERY);
+        let connection = self.pool.get()?;
+        let mut statement = connection.prepare(&query)?;
+        Ok(statement.query_row([id], cashbox_count_from_row)?)
+    }
+
+    fn ean_alias_add(&mut self, actor: i32, ean: i64, real_ean: i64) -> Result<(), DatabaseError> {
         let query = "INSERT OR IGNORE INTO ean_aliases (id, real_ean) VALUES (?, ?)";
         let connection = self.pool.get()?;
         let mut statement = connection.prepare(query)?;
-        let _inserted_row_count = statement.execute([ean, real_ean])?;
+        let inserted_row_count = statement.execute([ean, real_ean])?;
+        if inserted_row_count > 0 {
+            audit_log(&connection, actor, "create", "alias", ean, None, Some(serde_json::json!({"real_ean": real_ean})))?;
+        }
         Ok(())
     }
 
     fn bestbeforelist(&mut self, ) -> Result<Vec<BestBeforeEntry>, DatabaseError> {
         let mut bbdlist = Vec::new();
+        let now = get_unix_time();
 
 		for product in self.get_stock()? {
-			let mut amount = product.amount;
-			let pid = product.ean;
-
-			if amount <= 0 {
+			if product.amount <= 0 {
 				continue;
             }
 
-			for restock in self.get_restocks(pid, true)? {
-				if (restock.amount as i32) > amount {
-					bbdlist.push(BestBeforeEntry {
-                        ean: pid,
-                        name: product.name.clone(),
-                        amount: amount,
-                        best_before_date: restock.best_before_date,
-                    });
-				} else {
-					bbdlist.push(BestBeforeEntry {
-                        ean: pid,
-                        name: product.name.clone(),
-                        amount: restock.amount as i32,
-                        best_before_date: restock.best_before_date,
-                    });
-				}
-
-				amount -= restock.amount as i32;
-				if amount <= 0 {
-					break;
+            let connection = self.pool.get()?;
+			for lot in stock_valuation(&connection, product.ean, now, self.valuation)?.lots {
+				if lot.remaining == 0 {
+					continue;
                 }
+
+				bbdlist.push(BestBeforeEntry {
+                    ean: product.ean,
+                    name: product.name.clone(),
+                    amount: lot.remaining as i32,
+                    best_before_date: lot.best_before_date,
+                });
 			}
 		}
 
@@ -1239,6 +2773,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     let db = Database {
         pool: pool,
+        valuation: Valuation::from_config(cfg.get("DATABASE", "valuation")),
+        order_coverage: cfg.getuint("DATABASE", "order_coverage").ok().flatten().unwrap_or(14) as u32,
+        order_history: cfg.getuint("DATABASE", "order_history").ok().flatten().unwrap_or(56) as u32,
     };
 
     let _connection = connection::Builder::system()?
@@ -1251,3 +2788,204 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valuation_fifo_consumes_oldest_lot_first() {
+        let mut valuation = StockValuation::default();
+        valuation.add_lot(100, 2, 50, 0);
+        valuation.add_lot(200, 2, 80, 0);
+
+        valuation.consume(1, Valuation::Fifo);
+        valuation.consume(2, Valuation::Fifo);
+        valuation.consume(3, Valuation::Fifo);
+
+        assert_eq!(valuation.sale_costs[&1], 50);
+        assert_eq!(valuation.sale_costs[&2], 50);
+        assert_eq!(valuation.sale_costs[&3], 80);
+        assert_eq!(valuation.value(Valuation::Fifo), 80);
+    }
+
+    #[test]
+    fn valuation_average_uses_all_restocks() {
+        let mut valuation = StockValuation::default();
+        valuation.add_lot(100, 2, 50, 0);
+        valuation.add_lot(200, 2, 80, 0);
+
+        valuation.consume(1, Valuation::Average);
+
+        assert_eq!(valuation.sale_costs[&1], 65);
+        assert_eq!(valuation.value(Valuation::Average), 3 * 65);
+        /* the lots are still tracked for the best before dates */
+        assert_eq!(valuation.value(Valuation::Fifo), 50 + 2 * 80);
+    }
+
+    #[test]
+    fn valuation_zero_priced_lot_uses_last_price() {
+        let mut valuation = StockValuation::default();
+        valuation.add_lot(100, 1, 70, 0);
+        valuation.add_lot(200, 2, 0, 0);
+
+        valuation.consume(1, Valuation::Fifo);
+        valuation.consume(2, Valuation::Fifo);
+
+        assert_eq!(valuation.sale_costs[&1], 70);
+        assert_eq!(valuation.sale_costs[&2], 70);
+        assert_eq!(valuation.value(Valuation::Fifo), 70);
+        /* the average only includes what has actually been paid */
+        assert_eq!(valuation.average(), 70 / 3);
+    }
+
+    #[test]
+    fn valuation_zero_priced_lot_without_purchase_price() {
+        let mut valuation = StockValuation::default();
+        valuation.add_lot(100, 3, 0, 0);
+
+        valuation.consume(1, Valuation::Fifo);
+
+        assert_eq!(valuation.sale_costs[&1], 0);
+        assert_eq!(valuation.value(Valuation::Fifo), 0);
+        assert_eq!(valuation.value(Valuation::Average), 0);
+    }
+
+    #[test]
+    fn valuation_oversold_stock() {
+        let mut valuation = StockValuation::default();
+        valuation.add_lot(100, 1, 60, 0);
+
+        valuation.consume(1, Valuation::Fifo);
+        valuation.consume(2, Valuation::Fifo);
+        valuation.consume(3, Valuation::Average);
+
+        assert_eq!(valuation.sale_costs[&1], 60);
+        assert_eq!(valuation.sale_costs[&2], 60);
+        assert_eq!(valuation.sale_costs[&3], 60);
+        assert_eq!(valuation.amount, -2);
+        assert_eq!(valuation.value(Valuation::Fifo), 0);
+        assert_eq!(valuation.value(Valuation::Average), 0);
+    }
+
+    #[test]
+    fn valuation_sale_before_any_restock() {
+        let mut valuation = StockValuation::default();
+        valuation.consume(1, Valuation::Fifo);
+        valuation.add_lot(100, 2, 40, 0);
+
+        assert_eq!(valuation.sale_costs[&1], 0);
+        assert_eq!(valuation.value(Valuation::Fifo), 80);
+    }
+
+    /* in-memory database with the real schema, a single connection keeps the data */
+    fn test_database() -> Database {
+        let manager = SqliteConnectionManager::memory();
+        let pool = r2d2::Pool::builder().max_size(1).build(manager).expect("database pool");
+        pool.get().unwrap().execute_batch(include_str!("../../data/sql/tables.sql")).expect("database schema");
+
+        Database {
+            pool: pool,
+            valuation: Valuation::Fifo,
+            order_coverage: 14,
+            order_history: 56,
+        }
+    }
+
+    #[test]
+    fn cashbox_ledger_filters_and_balance() {
+        let mut db = test_database();
+        let connection = db.pool.get().unwrap();
+        connection.execute_batch("INSERT INTO users (id, firstname, lastname) VALUES (1, 'Test', 'User'), (-3, 'Loss', 'Donation');").unwrap();
+        for (user, amount, timestamp, entry_type) in [(1, 1000, 100, "deposit"), (1, -200, 200, "withdrawal"), (-3, -50, 300, "loss"), (1, 500, 400, "deposit"), (1, -100, 500, "withdrawal")] {
+            connection.execute("INSERT INTO cashbox_diff ('user', 'amount', 'timestamp') VALUES (?, ?, ?)", (user, amount, timestamp)).unwrap();
+            connection.execute("INSERT INTO cashbox_diff_types ('diff', 'type') VALUES (?, ?)", (connection.last_insert_rowid(), entry_type)).unwrap();
+        }
+        drop(connection);
+
+        let (count, entries) = db.get_cashbox_ledger(0, 1000, "", 0, 0).unwrap();
+        assert_eq!(count, 5);
+        assert_eq!(entries.iter().map(|e| e.balance).collect::<Vec<_>>(), vec![1150, 1250, 750, 800, 1000]);
+
+        /* the balance still includes the filtered entries and everything before the period */
+        let (count, entries) = db.get_cashbox_ledger(150, 450, "deposit", 0, 0).unwrap();
+        assert_eq!(count, 1);
+        assert_eq!((entries[0].timestamp, entries[0].amount, entries[0].balance), (400, 500, 1250));
+
+        let (count, entries) = db.get_cashbox_ledger(0, 1000, "withdrawal", 1, 1).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].timestamp, entries[0].balance), (200, 800));
+
+        assert!(db.get_cashbox_ledger(0, 1000, "invalid", 0, 0).is_err());
+    }
+
+    #[test]
+    fn profit_report_groups_products_with_the_same_name() {
+        let mut db = test_database();
+        db.pool.get().unwrap().execute_batch("
+            INSERT INTO users (id, firstname, lastname) VALUES (1, 'Test', 'User');
+            INSERT INTO categories (id, name) VALUES (1, 'Drinks');
+            INSERT INTO products (id, name, category) VALUES (4029764001807, 'Mate', 1), (4029764001814, 'Mate', 1);
+            INSERT INTO prices (product, valid_from, memberprice, guestprice) VALUES (4029764001807, 0, 100, 120), (4029764001814, 0, 150, 170);
+            INSERT INTO restock (user, product, amount, timestamp, price) VALUES (1, 4029764001807, 10, 10, 50), (1, 4029764001814, 10, 10, 80);
+            INSERT INTO sales (user, product, timestamp) VALUES (1, 4029764001807, 100), (1, 4029764001814, 200), (1, 4029764001807, 300);
+        ").unwrap();
+
+        let report = db.get_profit_report(0, 1000).unwrap();
+        let rows: Vec<_> = report.iter().map(|e| (e.product.ean, e.units, e.revenue, e.cost)).collect();
+        assert_eq!(rows, vec![(4029764001807, 2, 200, 100), (4029764001814, 1, 150, 80)]);
+    }
+
+    #[test]
+    fn disabled_users_are_logged_out() {
+        let mut db = test_database();
+        db.pool.get().unwrap().execute_batch("INSERT INTO users (id, email, firstname, lastname, gender, street, plz, city, pgp, sound_theme) VALUES (1, '', 'Test', 'User', '', '', 12345, '', '', ''), (2, '', 'Admin', 'User', '', '', 12345, '', '', '');").unwrap();
+        db.session_create(1, "session", "csrf", "test", 3600).unwrap();
+        db.api_token_create(1, 1, "token", "secret", vec!["products".to_string()]).unwrap();
+        assert_eq!(db.get_user_by_sessionid("session").unwrap(), 1);
+
+        db.user_disable(2, 1, true).unwrap();
+        assert!(db.get_user_by_sessionid("session").is_err());
+        assert!(db.get_api_token("secret").is_err());
+
+        /* sessions which survived the disabling are rejected as well */
+        db.user_disable(2, 1, false).unwrap();
+        db.session_create(1, "session", "csrf", "test", 3600).unwrap();
+        db.pool.get().unwrap().execute("UPDATE users SET disabled = 1 WHERE id = 1", []).unwrap();
+        assert!(db.get_user_by_sessionid("session").is_err());
+    }
+
+    #[test]
+    fn logins_and_logouts_are_audited() {
+        let mut db = test_database();
+        db.pool.get().unwrap().execute_batch("INSERT INTO users (id, firstname, lastname) VALUES (1, 'Test', 'User');").unwrap();
+        db.session_create(1, "session", "csrf", "test", 3600).unwrap();
+        db.session_delete("session").unwrap();
+        /* unknown sessions are not logged */
+        db.session_delete("session").unwrap();
+
+        let entries = db.get_audit_log(1, "session", 0, i64::MAX).unwrap();
+        let actions: Vec<_> = entries.iter().map(|e| e.action.as_str()).collect();
+        assert_eq!(actions, vec!["delete", "create"]);
+        assert_eq!(entries[0].entity_id, entries[1].entity_id);
+        assert!(!entries[1].after.contains("csrf"));
+    }
+
+    #[test]
+    fn legacy_password_hashes_are_upgraded() {
+        let mut db = test_database();
+        let connection = db.pool.get().unwrap();
+        connection.execute_batch("INSERT INTO users (id, firstname, lastname) VALUES (1, 'Test', 'User');").unwrap();
+        connection.execute("INSERT INTO authentication (user, password) VALUES (1, ?)", [sha256("secret")]).unwrap();
+        drop(connection);
+
+        assert!(!db.check_user_password(1, "wrong").unwrap());
+        assert!(db.check_user_password(1, "secret").unwrap());
+
+        let pwhash: String = db.pool.get().unwrap().query_row("SELECT password FROM authentication WHERE user = 1", [], |r| r.get(0)).unwrap();
+        assert!(!password_is_legacy(&pwhash));
+        assert!(db.check_user_password(1, "secret").unwrap());
+        assert!(db.get_audit_log(0, "", 0, i64::MAX).unwrap().is_empty());
+    }
+}
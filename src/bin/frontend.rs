@@ -27,7 +27,8 @@ use crossterm::{
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
 };
-use zbus::{self, Connection, proxy};
+use zbus::{self, Connection, proxy, zvariant::Type};
+use serde::Deserialize;
 use async_recursion::async_recursion;
 
 static ZERO: [&str; 3] = [
@@ -444,6 +445,7 @@ enum ShopInstruction {
     Login,
     Logout,
     Revert,
+    DepositReturn,
     EAN,
     RFID,
 }
@@ -483,6 +485,8 @@ impl ShopCommand {
             ShopCommand { instruction: ShopInstruction::Logout, userid: None, productid: None, rfiddata: None }
         } else if line == "UNDO" {
             ShopCommand { instruction: ShopInstruction::Revert, userid: None, productid: None, rfiddata: None }
+        } else if line == "DEPOSIT" {
+            ShopCommand { instruction: ShopInstruction::DepositReturn, userid: None, productid: None, rfiddata: None }
         } else if ean.is_some() {
             ShopCommand { instruction: ShopInstruction::EAN, userid: None, productid: ean, rfiddata: None }
         } else if line.len() == 10 {
@@ -529,8 +533,13 @@ trait ShopDB {
     async fn ean_alias_get(&self, ean: i64) -> zbus::Result<i64>;
     async fn get_product_name(&self, ean: i64) -> zbus::Result<String>;
     async fn get_product_price(&self, user: i32, article: i64) -> zbus::Result<i32>;
+    async fn get_product_deposit(&self, article: i64) -> zbus::Result<u32>;
+    async fn product_metadata_get(&self, ean: i64) -> zbus::Result<ProductMetadata>;
 
 	async fn buy(&self, user: i32, article: i64) -> zbus::Result<()>;
+	async fn deposit_return(&self, user: i32, article: i64) -> zbus::Result<u32>;
+
+    async fn get_price_changes(&self, from: i64, to: i64) -> zbus::Result<Vec<PriceChange>>;
 }
 
 async fn get_username(uid: i32) -> zbus::Result<String> {
@@ -558,6 +567,8 @@ struct Product {
     name: String,
     price: i32,
     guest_price: i32,
+    /// deposit per unit, negative for returned bottles
+    deposit: i32,
 }
 
 async fn get_product_info(ean: i64) -> zbus::Result<Product> {
@@ -570,6 +581,7 @@ async fn get_product_info(ean: i64) -> zbus::Result<Product> {
         name: proxy.get_product_name(ean).await?,
         price: proxy.get_product_price(1, ean).await?,
         guest_price: proxy.get_product_price(0, ean).await?,
+        deposit: proxy.get_product_deposit(ean).await? as i32,
     })
 }
 
@@ -583,15 +595,96 @@ async fn get_product_info_for_user(ean: i64, user: i32) -> zbus::Result<Product>
         name: proxy.get_product_name(ean).await?,
         price: proxy.get_product_price(user, ean).await?,
         guest_price: 0,
+        deposit: if user >= 0 { proxy.get_product_deposit(ean).await? as i32 } else { 0 },
+    })
+}
+
+/* bottle to be returned, which is credited with the deposit on logout */
+async fn get_deposit_return_info(ean: i64) -> zbus::Result<Product> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    let ean = proxy.ean_alias_get(ean).await?;
+
+    Ok(Product {
+        ean: ean,
+        name: proxy.get_product_name(ean).await?,
+        price: 0,
+        guest_price: 0,
+        deposit: -(proxy.get_product_deposit(ean).await? as i32),
     })
 }
 
+#[derive(Deserialize, Type)]
+#[allow(dead_code)]
+struct ProductMetadata {
+    product_size: u32,
+    product_size_is_weight: bool,
+    container_size: u32,
+    calories: u32,
+    carbohydrates: u32,
+    fats: u32,
+    proteins: u32,
+    deposit: u32,
+    container_deposit: u32,
+    allergens: String,
+    ingredients: String,
+}
+
+/* nutrition facts per 100g/ml, None if no metadata is known for the product */
+async fn get_nutrition_info(ean: i64) -> Option<String> {
+    let connection = Connection::system().await.ok()?;
+    let proxy = ShopDBProxy::new(&connection).await.ok()?;
+    let metadata = proxy.product_metadata_get(ean).await.ok()?;
+    let unit = if metadata.product_size_is_weight { "g" } else { "ml" };
+
+    let mut info = format!("Per 100{}: {} kcal, Carbohydrates {}g, Fats {}g, Proteins {}g", unit, metadata.calories, metadata.carbohydrates, metadata.fats, metadata.proteins);
+    if !metadata.allergens.is_empty() {
+        info.push_str(&format!(" - Allergens: {}", metadata.allergens));
+    }
+    Some(info)
+}
+
 async fn buy(user: i32, article: i64) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
     proxy.buy(user, article).await
 }
 
+async fn deposit_return(user: i32, article: i64) -> zbus::Result<u32> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.deposit_return(user, article).await
+}
+
+#[derive(Deserialize, Type)]
+#[allow(dead_code)]
+struct ProductInfo {
+    ean: i64,
+    name: String,
+}
+
+#[derive(Deserialize, Type)]
+#[allow(dead_code)]
+struct PriceChange {
+    product: ProductInfo,
+    valid_from: i64,
+    memberprice: i32,
+    guestprice: i32,
+    previous_memberprice: i32,
+    previous_guestprice: i32,
+}
+
+/* price changes becoming effective on the day after the given date */
+async fn get_price_changes_next_day(date: chrono::NaiveDate) -> zbus::Result<Vec<PriceChange>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    let tomorrow = date.succ_opt().unwrap_or(date);
+    let dayafter = tomorrow.succ_opt().unwrap_or(tomorrow);
+    let from = tomorrow.and_hms_opt(0, 0, 0).unwrap().and_local_timezone(chrono::Local).earliest().map_or(0, |t| t.timestamp());
+    let to = dayafter.and_hms_opt(0, 0, 0).unwrap().and_local_timezone(chrono::Local).earliest().map_or(i64::MAX, |t| t.timestamp() - 1);
+    proxy.get_price_changes(from, to).await
+}
+
 struct ShopState {
     /// TUI log
     logdata: Vec<LogEntry>,
@@ -601,13 +694,37 @@ struct ShopState {
     audiotheme: Option<String>,
     /// List of product ids user currently has in his shopping cart
     cart: Vec<Product>,
+    /// The next scanned product is a returned bottle instead of a purchase
+    deposit_return: bool,
 }
 
 fn price2str(price: i32) -> String {
-    format!("{}.{:02}€", price/100, price%100)
+    let sign = if price < 0 { "-" } else { "" };
+    format!("{}{}.{:02}€", sign, price.abs()/100, price.abs()%100)
+}
+
+fn deposit2str(deposit: i32) -> String {
+    if deposit == 0 { String::new() } else { format!(" + {} deposit", price2str(deposit)) }
 }
 
 impl ShopState {
+    async fn announce_price_changes(&mut self) {
+        let time = chrono::Local::now();
+        let changes = match get_price_changes_next_day(time.date_naive()).await {
+            Ok(changes) => changes,
+            Err(_) => { return; },
+        };
+
+        if changes.is_empty() {
+            return;
+        }
+
+        let list: Vec<String> = changes.iter().map(|change| {
+            format!("{} {} -> {}", change.product.name, price2str(change.previous_memberprice), price2str(change.memberprice))
+        }).collect();
+        self.logdata.push(LogEntry{time: time, logtype: LogType::Warning, msg: format!("Price changes tomorrow: {}", list.join(", "))});
+    }
+
     #[async_recursion]
     async fn execute(&mut self, cmd: ShopCommand) {
         let time = chrono::Local::now();
@@ -634,9 +751,18 @@ impl ShopState {
                 },
                 ShopInstruction::Logout => {
                     let mut sum = 0;
+                    let mut deposit = 0;
+                    let mut returned = 0;
                     for product in &self.cart {
                         sum += product.price;
-                        match buy(userid, product.ean).await {
+                        deposit += product.deposit;
+                        let result = if product.deposit < 0 {
+                            returned += 1;
+                            deposit_return(userid, product.ean).await.map(|_| ())
+                        } else {
+                            buy(userid, product.ean).await
+                        };
+                        match result {
                             Ok(_) => {},
                             Err(err) => {
                                 self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Error: {}", err)});
@@ -645,14 +771,17 @@ impl ShopState {
                         }
                     }
                     
-                    if userid >= 0 {
-                        self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Logout, bought {} articles for {}", self.cart.len(), price2str(sum))});
+                    if userid >= 0 && returned > 0 {
+                        self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Logout, bought {} articles for {}, returned {} bottles, deposit {}", self.cart.len() - returned, price2str(sum), returned, price2str(deposit))});
+                    } else if userid >= 0 {
+                        self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Logout, bought {} articles for {}{}", self.cart.len(), price2str(sum), deposit2str(deposit))});
                     } else {
                         self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Logout, bought {} articles", self.cart.len())});
                     }
                     let _ = play_user(&self.audiotheme.as_ref().unwrap(), "logout").await;
                     self.user = None;
                     self.cart.clear();
+                    self.deposit_return = false;
                 },
                 ShopInstruction::Revert => {
                     if self.cart.is_empty() {
@@ -664,13 +793,41 @@ impl ShopState {
                         let _ = play_user(&self.audiotheme.as_ref().unwrap(), "purchase").await;
                     }
                 },
+                ShopInstruction::DepositReturn => {
+                    if userid < 0 {
+                        self.logdata.push(LogEntry{time: time, logtype: LogType::Error, msg: "System users do not pay deposit".to_string()});
+                        let _ = play_user(&self.audiotheme.as_ref().unwrap(), "error").await;
+                    } else {
+                        self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: "Deposit return: scan the returned bottle".to_string()});
+                        self.deposit_return = true;
+                    }
+                },
+                ShopInstruction::EAN if self.deposit_return => {
+                    let productid = cmd.productid.unwrap();
+                    self.deposit_return = false;
+                    match get_deposit_return_info(productid).await {
+                        Ok(product) if product.deposit < 0 => {
+                            self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Deposit return: {} - {}", product.name, price2str(product.deposit))});
+                            self.cart.push(product);
+                            let _ = play_user(&self.audiotheme.as_ref().unwrap(), "purchase").await;
+                        },
+                        Ok(product) => {
+                            self.logdata.push(LogEntry{time: time, logtype: LogType::Error, msg: format!("No deposit on {}", product.name)});
+                            let _ = play_user(&self.audiotheme.as_ref().unwrap(), "error").await;
+                        },
+                        Err(_error) => {
+                            self.logdata.push(LogEntry{time: time, logtype: LogType::Error, msg: format!("Unknown product: {productid}")});
+                            let _ = play_user(&self.audiotheme.as_ref().unwrap(), "error").await;
+                        }
+                    }
+                },
                 ShopInstruction::EAN => {
                     let productid = cmd.productid.unwrap();
                     let product = get_product_info_for_user(productid, userid).await;
                     match product {
                         Ok(product) => {
                             if userid >= 0 {
-                                self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Buy: {} - {}", product.name, price2str(product.price))});
+                                self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Buy: {} - {}{}", product.name, price2str(product.price), deposit2str(product.deposit))});
                             } else {
                                 self.logdata.push(LogEntry{time: time, logtype: LogType::Info, msg: format!("Buy: {}", product.name)});
                             }
@@ -724,7 +881,7 @@ impl ShopState {
                     self.logdata.push(LogEntry{time: time, logtype: LogType::Error, msg: "No active session".to_string()});
                     let _ = play_system("error.opus").await;
                 },
-                ShopInstruction::Revert => {
+                ShopInstruction::Revert | ShopInstruction::DepositReturn => {
                     self.logdata.push(LogEntry{time: time, logtype: LogType::Error, msg: "No active session".to_string()});
                     let _ = play_system("error.opus").await;
                 },
@@ -733,7 +890,10 @@ impl ShopState {
                     let product = get_product_info(productid).await;
                     match product {
                         Ok(product) => {
-                            self.logdata.push(LogEntry{time: time, logtype: LogType::Warning, msg: format!("Price Info: {} - Member {} Guest {}", product.name, price2str(product.price), price2str(product.guest_price))});
+                            self.logdata.push(LogEntry{time: time, logtype: LogType::Warning, msg: format!("Price Info: {} - Member {} Guest {}{}", product.name, price2str(product.price), price2str(product.guest_price), deposit2str(product.deposit))});
+                            if let Some(info) = get_nutrition_info(product.ean).await {
+                                self.logdata.push(LogEntry{time: time, logtype: LogType::Warning, msg: info});
+                            }
                             let _ = play_system("error.opus").await;
                         },
                         Err(_error) => {
@@ -775,13 +935,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let (timer_sender, mut timer_receiver) = tokio::sync::watch::channel(false);
     thread_timer(timer_sender);
 
-    let mut state = ShopState { logdata: Vec::new(), user: None, audiotheme: None, cart: Vec::new() };
+    let mut state = ShopState { logdata: Vec::new(), user: None, audiotheme: None, cart: Vec::new(), deposit_return: false };
     let mut draw_dots = true;
     let mut last_date = chrono::Local::now();
 
     let _ = play_system("startup.opus").await;
 
     state.logdata.push(LogEntry{time: chrono::Local::now(), logtype: LogType::Info, msg: "System started up".to_string()});
+    state.announce_price_changes().await;
 
     loop {
         terminal.draw(|f| ui(f, draw_dots, &state.logdata))?;
@@ -801,6 +962,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 if last_date.date_naive() != now.date_naive() {
                     last_date = now;
                     state.logdata.push(LogEntry{time: now, logtype: LogType::DateChange, msg: "".to_string()});
+                    state.announce_price_changes().await;
                 }
                 draw_dots = !draw_dots;
             },
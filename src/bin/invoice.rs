@@ -18,6 +18,7 @@ use zbus::{Connection, proxy, zvariant::Type};
 use serde::{Serialize, Deserialize};
 use chrono::{Datelike, offset::TimeZone, prelude::*};
 use unicode_segmentation::UnicodeSegmentation;
+use ktt_shopsystem::vat::{VatBreakdown, vat_breakdown};
 use configparser::ini::Ini;
 
 #[derive(Debug)]
@@ -146,6 +147,41 @@ pub struct InvoiceEntry {
 	timestamp: i64,
 	product: Product,
 	price: i32,
+	vat: i32,
+}
+
+#[derive(Deserialize, Serialize, zbus::zvariant::Type)]
+pub struct DisputeInfo {
+	id: i64,
+	user: i32,
+	product: Product,
+	timestamp: i64,
+	comment: String,
+	created: i64,
+	state: String,
+	resolved_by: i32,
+	resolved_at: i64,
+	reassigned_to: i32,
+	resolution: String,
+}
+
+#[derive(Deserialize, Serialize, zbus::zvariant::Type)]
+pub struct InvoiceRequest {
+	id: String,
+	date: i64,
+	recipient: InvoiceRecipient,
+	entries: Vec<InvoiceEntry>,
+	facturx: bool,
+}
+
+#[derive(Deserialize, Serialize, zbus::zvariant::Type)]
+pub struct InvoiceInfo {
+	id: String,
+	user: i32,
+	timestamp: i64,
+	period_from: i64,
+	period_to: i64,
+	amount: i32,
 }
 
 #[derive(Deserialize, Serialize, PartialEq, Copy, Clone, zbus::zvariant::Type)]
@@ -164,6 +200,10 @@ trait ShopDB {
     async fn get_invoice(&self, userid: i32, from: i64, to: i64) -> zbus::Result<Vec<InvoiceEntry>>;
     async fn get_user_invoice_sum(&self, userid: i32, from: i64, to: i64) -> zbus::Result<i32>;
     async fn get_users_with_sales(&self, timestamp_from: i64, timestamp_to: i64) -> zbus::Result<Vec<i32>>;
+    async fn invoice_add(&self, invoice: InvoiceInfo, pdf: Vec<u8>) -> zbus::Result<()>;
+    async fn get_invoice_info(&self, id: &str) -> zbus::Result<InvoiceInfo>;
+    async fn get_invoice_pdf(&self, id: &str) -> zbus::Result<Vec<u8>>;
+    async fn get_user_disputes(&self, userid: i32) -> zbus::Result<Vec<DisputeInfo>>;
 }
 
 async fn get_user_info(uid: i32) -> zbus::Result<UserInfo> {
@@ -190,23 +230,54 @@ async fn get_users_with_sales(start: i64, stop: i64) -> zbus::Result<Vec<i32>> {
     proxy.get_users_with_sales(start, stop).await
 }
 
+async fn invoice_add(invoice: InvoiceInfo, pdf: Vec<u8>) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.invoice_add(invoice, pdf).await
+}
+
+async fn get_invoice_info(id: &str) -> zbus::Result<InvoiceInfo> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_invoice_info(id).await
+}
+
+async fn get_invoice_pdf(id: &str) -> zbus::Result<Vec<u8>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_invoice_pdf(id).await
+}
+
+/* amount in cents, e.g. -15 -> "-0,15" */
+fn cent2str(amount: i32) -> String {
+    let sign = if amount < 0 { "-" } else { "" };
+    format!("{}{},{:02}", sign, amount.abs() / 100, amount.abs() % 100)
+}
+
+/* deposit lines are added by the database with EAN 0 */
+fn deposit_sum(entries: &Vec<InvoiceEntry>) -> Option<i32> {
+    let deposit: Vec<i32> = entries.iter().filter(|e| e.product.ean == 0).map(|e| e.price).collect();
+    if deposit.is_empty() { None } else { Some(deposit.iter().sum()) }
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+async fn get_open_disputes(uid: i32) -> zbus::Result<Vec<DisputeInfo>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    let disputes = proxy.get_user_disputes(uid).await?;
+    Ok(disputes.into_iter().filter(|d| d.state == "open").collect())
+}
+
 #[proxy(
     interface = "io.mainframe.shopsystem.InvoicePDF",
     default_service = "io.mainframe.shopsystem.InvoicePDF",
     default_path = "/io/mainframe/shopsystem/invoicepdf"
 )]
 trait ShopPDF {
-    #[zbus(property)]
-    fn set_invoice_id(&self, id: &str) -> zbus::Result<()>;
-    #[zbus(property)]
-    fn set_invoice_date(&self, date: i64) -> zbus::Result<()>;
-    #[zbus(property)]
-    fn set_invoice_recipient(&self, recipient: InvoiceRecipient) -> zbus::Result<()>;
-    #[zbus(property)]
-    fn set_invoice_entries(&self, invoice_entries: Vec<InvoiceEntry>) -> zbus::Result<()>;
-
-    fn generate(&self) -> zbus::Result<Vec<u8>>;
-    fn clear(&self) -> zbus::Result<()>;
+    fn render(&self, request: InvoiceRequest) -> zbus::Result<Vec<u8>>;
 }
 
 #[proxy(
@@ -255,6 +326,9 @@ struct Invoicer {
 	shortname: String,
 	spacename: String,
 	jverein_membership_number: String,
+	vat: bool,
+	taxid: String,
+	facturx: bool,
 }
 
 impl Invoicer {
@@ -316,6 +390,8 @@ impl Invoicer {
 			"Mitglieds_Nr;Betrag;Buchungstext;Fälligkeit;Intervall;Endedatum\n".to_string()
 		};
 
+		let mut unrecorded = Vec::new();
+
 		for userid in users {
 			number += 1;
 
@@ -325,31 +401,66 @@ impl Invoicer {
 			let total_sum = get_user_invoice_sum(userid, tst.from, tst.to).await?;
 
             /*
-             * Even when limited to one user we need to process all for two reasons:
-             *  1. The Invoice ID will incorrectly be 0001 otherwise
-             *  2. The CSV for the treasurer should always have all entries
+             * Even when limited to one user we need to process all, since the
+             * Invoice ID will incorrectly be 0001 otherwise.
              */
+            let mut recorded = false;
             if limit_to_user.is_none() || limit_to_user.unwrap() == userid {
                 println!("{} ({} {})...", userdata.id, &userdata.firstname, &userdata.lastname);
 
-                let mail_path = mailer.create_mail().await?;
-                let mail = ShopMailProxy::builder(&dbus_connection).path(mail_path.clone())?.build().await?;
-                mail.set_from(MailContact {name: sendername.clone(), email: self.mailfromaddress.clone()}).await?;
-                mail.set_subject(mailtitle.clone()).await?;
-                let recipientname = format!("{} {}", &userdata.firstname, &userdata.lastname);
-                mail.add_recipient(MailContact {name: recipientname, email: userdata.email.clone()}, RecipientType::To).await?;
-
-                if !temporary {
-                    mail.add_attachment(invoicedata.pdffilename.clone(), "application/pdf".to_string(), invoicedata.pdfdata.clone()).await?;
-                    treasurer_mail.add_attachment(invoicedata.pdffilename, "application/pdf".to_string(), invoicedata.pdfdata).await?;
+                /*
+                 * Remember the invoice before it is sent, so that exactly the mailed PDF can be
+                 * downloaded later. An invoice recorded by a previous run is sent again with the
+                 * stored PDF. A failure must not stop the other invoices and the treasurer mail.
+                 */
+                let pdfdata = if temporary {
+                    Ok(invoicedata.pdfdata)
+                } else if get_invoice_info(&invoiceid).await.is_ok() {
+                    println!("Invoice {} has already been recorded, sending the stored invoice", invoiceid);
+                    get_invoice_pdf(&invoiceid).await.map(|pdf| if pdf.is_empty() { invoicedata.pdfdata } else { pdf })
+                } else {
+                    let info = InvoiceInfo {
+                        id: invoiceid.clone(),
+                        user: userid,
+                        timestamp: timestamp,
+                        period_from: ts.from,
+                        period_to: ts.to,
+                        amount: total_sum,
+                    };
+                    recorded = true;
+                    invoice_add(info, invoicedata.pdfdata.clone()).await.map(|_| invoicedata.pdfdata)
+                };
+
+                match pdfdata {
+                    Err(err) => {
+                        eprintln!("Failed to record invoice {}, not sending it: {}", invoiceid, err);
+                        unrecorded.push(invoiceid.clone());
+                        recorded = false;
+                    },
+                    Ok(pdfdata) => {
+                        let mail_path = mailer.create_mail().await?;
+                        let mail = ShopMailProxy::builder(&dbus_connection).path(mail_path.clone())?.build().await?;
+                        mail.set_from(MailContact {name: sendername.clone(), email: self.mailfromaddress.clone()}).await?;
+                        mail.set_subject(mailtitle.clone()).await?;
+                        let recipientname = format!("{} {}", &userdata.firstname, &userdata.lastname);
+                        mail.add_recipient(MailContact {name: recipientname, email: userdata.email.clone()}, RecipientType::To).await?;
+
+                        if !temporary {
+                            mail.add_attachment(invoicedata.pdffilename.clone(), "application/pdf".to_string(), pdfdata.clone()).await?;
+                        }
+                        if recorded {
+                            treasurer_mail.add_attachment(invoicedata.pdffilename, "application/pdf".to_string(), pdfdata).await?;
+                        }
+
+                        mail.set_main_part(invoicedata.plain, MessageType::Plain).await?;
+                        mail.set_main_part(invoicedata.html, MessageType::Html).await?;
+                        mailer.send_mail(mail_path.clone()).await?;
+                    },
                 }
-
-                mail.set_main_part(invoicedata.plain, MessageType::Plain).await?;
-                mail.set_main_part(invoicedata.html, MessageType::Html).await?;
-                mailer.send_mail(mail_path.clone()).await?;
             }
 
-			if !temporary {
+			/* only newly recorded invoices are billed, so that a second run does not bill them twice */
+			if recorded {
                 let tmp = format!("{0},{1},{2},{invoiceid},{total_sum}\n", userdata.id, userdata.lastname, userdata.firstname);
                 csvinvoicedata.push_str(&tmp);
 
@@ -358,7 +469,8 @@ impl Invoicer {
 			}
 		}
 
-		if !temporary {
+		/* invoices sent again to a single user are already known to the treasurer */
+		if !temporary && limit_to_user.is_none() {
             let text = self.get_treasurer_text()?;
 			treasurer_mail.set_main_part(text, MessageType::Plain).await?;
 			treasurer_mail.add_attachment("invoice.csv".to_string(), "text/csv; charset=utf-8".to_string(), csvinvoicedata.into()).await?;
@@ -368,6 +480,10 @@ impl Invoicer {
 			mailer.delete_mail(treasurer_path).await?;
         }
 
+        if !unrecorded.is_empty() {
+            return Err(InvoicerError::DBusError(format!("invoices have not been recorded and sent: {}", unrecorded.join(", "))));
+        }
+
         Ok(())
 	}
 
@@ -391,32 +507,33 @@ impl Invoicer {
 
         let invoiceentries = get_invoice(userid, ts.from, ts.to).await?;
         let total_sum = get_user_invoice_sum(userid, tst.from, tst.to).await?;
+        let disputes = get_open_disputes(userid).await?;
 
         /* invoice id */
         let pdffilename = format!("{}_{}_{}.pdf", invoiceid, &userdata.firstname, &userdata.lastname);
 
-        let htmlmsg = self.generate_invoice_message(MessageType::Html, temporary, Self::get_address(&userdata.gender), &userdata.lastname, &invoiceentries, total_sum)?;
-        let plainmsg = self.generate_invoice_message(MessageType::Plain, temporary, Self::get_address(&userdata.gender), &userdata.lastname, &invoiceentries, total_sum)?;
+        let htmlmsg = self.generate_invoice_message(MessageType::Html, temporary, Self::get_address(&userdata.gender), &userdata.lastname, &invoiceentries, total_sum, &disputes)?;
+        let plainmsg = self.generate_invoice_message(MessageType::Plain, temporary, Self::get_address(&userdata.gender), &userdata.lastname, &invoiceentries, total_sum, &disputes)?;
 
         /* pdf generation */
         let pdfdata = if !temporary {
             let dbus_connection = Connection::system().await?;
             let pdf = ShopPDFProxy::new(&dbus_connection).await?;
 
-            pdf.set_invoice_id(invoiceid).await?;
-            pdf.set_invoice_date(timestamp).await?;
-            pdf.set_invoice_recipient(InvoiceRecipient {
-                firstname: userdata.firstname.clone(),
-                lastname: userdata.lastname.clone(),
-                street: userdata.street.clone(),
-                postal_code: userdata.postal_code.clone(),
-                city: userdata.city.clone(),
-                gender: userdata.gender.clone(),
-            }).await?;
-            pdf.set_invoice_entries(invoiceentries).await?;
-            let pdfdata = pdf.generate().await?;
-            pdf.clear().await?;
-            pdfdata
+            pdf.render(InvoiceRequest {
+                id: invoiceid.to_string(),
+                date: timestamp,
+                recipient: InvoiceRecipient {
+                    firstname: userdata.firstname.clone(),
+                    lastname: userdata.lastname.clone(),
+                    street: userdata.street.clone(),
+                    postal_code: userdata.postal_code.clone(),
+                    city: userdata.city.clone(),
+                    gender: userdata.gender.clone(),
+                },
+                entries: invoiceentries,
+                facturx: self.facturx,
+            }).await?
         } else {
             Vec::new()
         };
@@ -476,7 +593,7 @@ impl Invoicer {
 		}
 	}
 
-	fn generate_invoice_message(&self, msgtype: MessageType, temporary: bool, address: &str, name: &str, entries: &Vec<InvoiceEntry>, total_sum: i32) -> Result<String, std::io::Error> {
+	fn generate_invoice_message(&self, msgtype: MessageType, temporary: bool, address: &str, name: &str, entries: &Vec<InvoiceEntry>, total_sum: i32, disputes: &Vec<DisputeInfo>) -> Result<String, std::io::Error> {
         let filename = match (msgtype, temporary) {
             (MessageType::Html, true) => "invoice.temporary.html",
             (MessageType::Plain, true) => "invoice.temporary.txt",
@@ -485,18 +602,12 @@ impl Invoicer {
         };
         let filename = format!("{}/{}", self.datadir, filename);
 
-        let vatfile = match msgtype {
-            MessageType::Plain => "vat.txt",
-            MessageType::Html => "vat.html",
-        };
-        let vatfile = format!("{}/{}", self.datadir, vatfile);
-
         let table = match msgtype {
-            MessageType::Plain => Self::generate_invoice_table_text(entries),
-            MessageType::Html => Self::generate_invoice_table_html(entries),
+            MessageType::Plain => Self::generate_invoice_table_text(entries, self.vat),
+            MessageType::Html => Self::generate_invoice_table_html(entries, self.vat),
         };
 
-        let sum_month_str = format!("{},{:02}", total_sum / 100, total_sum % 100);
+        let sum_month_str = cent2str(total_sum);
 
         let text = std::fs::read_to_string(filename)?;
 		let text = text.replace("{{{ADDRESS}}}", &address);
@@ -505,13 +616,70 @@ impl Invoicer {
 		let text = text.replace("{{{INVOICE_TABLE}}}", &table);
 		let text = text.replace("{{{SUM_MONTH}}}", &sum_month_str);
 
-        let vatinfotext = std::fs::read_to_string(vatfile)?;
+        let vatinfotext = if self.vat {
+            match msgtype {
+                MessageType::Plain => format!("Steuernummer: {}\n", self.taxid),
+                MessageType::Html => format!("<p>Steuernummer: {}</p>\n", self.taxid),
+            }
+        } else {
+            let vatfile = match msgtype {
+                MessageType::Plain => "vat.txt",
+                MessageType::Html => "vat.html",
+            };
+            let vatfile = format!("{}/{}", self.datadir, vatfile);
+            std::fs::read_to_string(vatfile)?
+        };
         let text = text.replace("{{{VAT}}}", &vatinfotext);
 
+        let disputetext = match msgtype {
+            MessageType::Plain => Self::generate_disputes_text(disputes),
+            MessageType::Html => Self::generate_disputes_html(disputes),
+        };
+        let text = text.replace("{{{DISPUTES}}}", &disputetext);
+
 		Ok(text)
 	}
 
-	fn generate_invoice_table_text(entries: &Vec<InvoiceEntry>) -> String {
+	fn generate_disputes_text(disputes: &Vec<DisputeInfo>) -> String {
+		let mut result = String::new();
+
+		if disputes.len() == 0 {
+			return result;
+		}
+
+		result.push_str("Folgende von Ihnen gemeldete Buchungen werden noch geprüft:\n\n");
+		for dispute in disputes {
+            let dt: chrono::DateTime<Utc> = chrono::DateTime::<Utc>::from_timestamp(dispute.timestamp, 0).expect("invalid timestamp");
+            let dt: chrono::DateTime<Local> = chrono::DateTime::from(dt);
+			result.push_str(&format!(" * {} {} ({})\n", dt.format("%Y-%m-%d %H:%M:%S"), dispute.product.name, dispute.comment));
+		}
+
+		result
+	}
+
+	fn generate_disputes_html(disputes: &Vec<DisputeInfo>) -> String {
+		let mut result = String::new();
+
+		if disputes.len() == 0 {
+			return result;
+		}
+
+		result.push_str("<p>Folgende von Ihnen gemeldete Buchungen werden noch geprüft:</p>\n<ul>\n");
+		for dispute in disputes {
+            let dt: chrono::DateTime<Utc> = chrono::DateTime::<Utc>::from_timestamp(dispute.timestamp, 0).expect("invalid timestamp");
+            let dt: chrono::DateTime<Local> = chrono::DateTime::from(dt);
+			result.push_str(&format!("\t<li>{} {} ({})</li>\n", dt.format("%Y-%m-%d %H:%M:%S"), html_escape(&dispute.product.name), html_escape(&dispute.comment)));
+		}
+		result.push_str("</ul>\n");
+
+		result
+	}
+
+	fn get_vat_breakdown(entries: &Vec<InvoiceEntry>) -> Vec<VatBreakdown> {
+		vat_breakdown(entries.iter().map(|e| (e.vat, e.price)))
+	}
+
+	fn generate_invoice_table_text(entries: &Vec<InvoiceEntry>, vat: bool) -> String {
 		let mut result = String::new();
 
 		let article_minsize = "Artikel".graphemes(true).count();
@@ -531,10 +699,13 @@ impl Invoicer {
 			total += entry.price;
 		}
 
+		// add tax rate column for VAT liable invoices
+		let (vatline, vatheader) = if vat { ("-----+", " USt |") } else { ("", "") };
+
 		// generate table header
-        result.push_str(&format!(" +------------+----------+-{}-+----------+\n", "-".repeat(maxnamelength)));
-        result.push_str(&format!(" | Datum      | Uhrzeit  | Artikel{} | Preis    |\n", " ".repeat(maxnamelength - article_minsize)));
-        result.push_str(&format!(" +------------+----------+-{}-+----------+\n", "-".repeat(maxnamelength)));
+        result.push_str(&format!(" +------------+----------+-{}-+{}----------+\n", "-".repeat(maxnamelength), vatline));
+        result.push_str(&format!(" | Datum      | Uhrzeit  | Artikel{} |{} Preis    |\n", " ".repeat(maxnamelength - article_minsize), vatheader));
+        result.push_str(&format!(" +------------+----------+-{}-+{}----------+\n", "-".repeat(maxnamelength), vatline));
 
 		// generate table data
 		let mut lastdate = String::new();
@@ -546,18 +717,32 @@ impl Invoicer {
             let date = if lastdate == newdate { "          ".to_string() } else { lastdate = newdate.clone(); newdate };
             let namelength = entry.product.name.graphemes(true).count();
 
-            result.push_str(&format!(" | {} | {} | {}{} | {:>3},{:02} € |\n", date, time, entry.product.name, " ".repeat(maxnamelength-namelength), entry.price / 100, entry.price % 100));
+            let rate = if vat { format!(" {:>2}% |", entry.vat) } else { String::new() };
+
+            result.push_str(&format!(" | {} | {} | {}{} |{} {:>6} € |\n", date, time, entry.product.name, " ".repeat(maxnamelength-namelength), rate, cent2str(entry.price)));
 		}
 
 		// generate table footer
-        result.push_str(&format!(" +------------+----------+-{}-+----------+\n", "-".repeat(maxnamelength)));
-        result.push_str(&format!(" | Summe:                  {} | {:>3},{:02} € |\n", " ".repeat(maxnamelength), total / 100, total % 100));
-        result.push_str(&format!(" +-------------------------{}-+----------+\n", "-".repeat(maxnamelength)));
+		let vatspace = if vat { "      " } else { "" };
+        result.push_str(&format!(" +------------+----------+-{}-+{}----------+\n", "-".repeat(maxnamelength), vatline));
+        result.push_str(&format!(" | Summe:                  {}{} | {:>6} € |\n", " ".repeat(maxnamelength), vatspace, cent2str(total)));
+        if let Some(deposit) = deposit_sum(entries) {
+            result.push_str(&format!(" | davon Pfand:            {}{} | {:>6} € |\n", " ".repeat(maxnamelength), vatspace, cent2str(deposit)));
+        }
+        result.push_str(&format!(" +-------------------------{}{}-+----------+\n", "-".repeat(maxnamelength), "-".repeat(vatspace.len())));
+
+		// generate tax breakdown
+		if vat {
+			result.push_str("\n Im Gesamtbetrag enthaltene Umsatzsteuer:\n");
+			for b in Self::get_vat_breakdown(entries) {
+				result.push_str(&format!("  {:>2}% USt auf {:>7} € netto: {:>6} € (brutto {:>7} €)\n", b.rate, cent2str(b.net), cent2str(b.tax), cent2str(b.gross)));
+			}
+		}
 
 		result
 	}
 
-	fn generate_invoice_table_html(entries: &Vec<InvoiceEntry>) -> String {
+	fn generate_invoice_table_html(entries: &Vec<InvoiceEntry>, vat: bool) -> String {
         let mut result = String::new();
         let mut lastdate = String::new();
         let mut total = 0;
@@ -567,6 +752,9 @@ impl Invoicer {
         result.push_str("\t\t<th style=\"border: 1px solid black;\">Datum</th>\n");
         result.push_str("\t\t<th style=\"border: 1px solid black;\">Zeit</th>\n");
         result.push_str("\t\t<th style=\"border: 1px solid black;\">Artikel</th>\n");
+        if vat {
+            result.push_str("\t\t<th style=\"border: 1px solid black;\">USt</th>\n");
+        }
         result.push_str("\t\t<th style=\"border: 1px solid black;\">Preis</th>\n");
         result.push_str("\t</tr>\n");
 
@@ -584,16 +772,47 @@ impl Invoicer {
             result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\">{}</td>\n", date));
             result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\">{}</td>\n", time));
             result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\">{}</td>\n", entry.product.name));
-            result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{},{:02} €</tt></td>\n", entry.price / 100, entry.price % 100));
+            if vat {
+                result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\">{}%</td>\n", entry.vat));
+            }
+            result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{} €</tt></td>\n", cent2str(entry.price)));
             result.push_str("\t</tr>\n");
         }
 
         result.push_str("\t<tr>\n");
-        result.push_str("\t\t<th style=\"border: 1px solid black;\" colspan=\"3\" align=\"left\">Summe:</th>\n");
-        result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{},{:02} €</tt></td>\n", total / 100, total % 100));
+        result.push_str(&format!("\t\t<th style=\"border: 1px solid black;\" colspan=\"{}\" align=\"left\">Summe:</th>\n", if vat { 4 } else { 3 }));
+        result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{} €</tt></td>\n", cent2str(total)));
         result.push_str("\t</tr>\n");
+        if let Some(deposit) = deposit_sum(entries) {
+            result.push_str("\t<tr>\n");
+            result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" colspan=\"{}\" align=\"left\">davon Pfand:</td>\n", if vat { 4 } else { 3 }));
+            result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{} €</tt></td>\n", cent2str(deposit)));
+            result.push_str("\t</tr>\n");
+        }
 
         result.push_str("</table>\n");
+
+        if vat {
+            result.push_str("<table cellpadding=\"5\" style=\"border-collapse:collapse; margin-top: 1em;\">\n");
+            result.push_str("\t<tr>\n");
+            result.push_str("\t\t<th style=\"border: 1px solid black;\">USt-Satz</th>\n");
+            result.push_str("\t\t<th style=\"border: 1px solid black;\">Netto</th>\n");
+            result.push_str("\t\t<th style=\"border: 1px solid black;\">USt</th>\n");
+            result.push_str("\t\t<th style=\"border: 1px solid black;\">Brutto</th>\n");
+            result.push_str("\t</tr>\n");
+
+            for b in Self::get_vat_breakdown(entries) {
+                result.push_str("\t<tr>\n");
+                result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\">{}%</td>\n", b.rate));
+                result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{} €</tt></td>\n", cent2str(b.net)));
+                result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{} €</tt></td>\n", cent2str(b.tax)));
+                result.push_str(&format!("\t\t<td style=\"border: 1px solid black;\" align=\"right\"><tt>{} €</tt></td>\n", cent2str(b.gross)));
+                result.push_str("\t</tr>\n");
+            }
+
+            result.push_str("</table>\n");
+        }
+
         result
 	}
 }
@@ -611,6 +830,12 @@ async fn main() -> Result<(), Box<dyn Error>> {
     let shortname = cfg.get("GENERAL", "shortname").expect("config does not specify GENERAL shortname");
     let spacename = cfg.get("GENERAL", "spacename").expect("config does not specify GENERAL spacename");
     let jverein_membership_number = cfg.get("JVEREIN", "membership_number").expect("config does not specify JVEREIN membership_number");
+    let vat = cfg.get("INVOICE", "vat").expect("config does not specify INVOICE vat") == "yes";
+    let taxid = cfg.get("INVOICE", "taxid").unwrap_or_default();
+    let facturx = cfg.get("INVOICE", "facturx").unwrap_or_default() == "yes";
+    if vat && taxid.is_empty() {
+        panic!("config does not specify INVOICE taxid, which is required for VAT liable invoices");
+    }
 
     let invoicer = Invoicer {
         datadir: datapath,
@@ -619,6 +844,9 @@ async fn main() -> Result<(), Box<dyn Error>> {
         shortname: shortname,
         spacename: spacename,
         jverein_membership_number: jverein_membership_number,
+        vat: vat,
+        taxid: taxid,
+        facturx: facturx,
     };
 
     let temporary = args.day;
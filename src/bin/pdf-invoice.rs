@@ -13,12 +13,15 @@
  * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
  */
 
-use std::{error::Error, future::pending};
+use std::{error::Error, future::pending, path::PathBuf};
+use clap::{Parser, Subcommand};
 use zbus::{connection, DBusError, interface, zvariant};
 use serde::{Serialize, Deserialize};
 use chrono::prelude::*;
 use chrono::Datelike;
 use configparser::ini::Ini;
+use lopdf::{dictionary, Object, Stream};
+use ktt_shopsystem::vat::{VatBreakdown, div_round, vat_breakdown};
 
 #[derive(DBusError, Debug)]
 enum PDFError {
@@ -33,6 +36,7 @@ enum PDFError {
     PriceTooHigh(String),
     TooFarInTheFuture(String),
     StreamError(String),
+    EInvoiceError(String),
 }
 
 impl From<rsvg::LoadingError> for PDFError {
@@ -53,6 +57,12 @@ impl From<cairo::Error> for PDFError {
     }
 }
 
+impl From<lopdf::Error> for PDFError {
+    fn from(err: lopdf::Error) -> PDFError {
+            PDFError::EInvoiceError(err.to_string())
+    }
+}
+
 impl From<std::io::Error> for PDFError {
     fn from(err: std::io::Error) -> PDFError {
             PDFError::IOError(err.to_string())
@@ -80,28 +90,112 @@ struct InvoiceEntry {
 	timestamp: i64,
 	product: Product,
 	price: i32,
+	vat: i32,
+}
+
+#[derive(Deserialize, Serialize, zvariant::Type, Clone)]
+struct InvoiceRequest {
+	id: String,
+	date: i64,
+	recipient: InvoiceRecipient,
+	entries: Vec<InvoiceEntry>,
+	#[serde(default)]
+	facturx: bool,
+}
+
+#[derive(Parser, Debug)]
+#[clap(about = "KtT Shopsystem PDF invoice renderer")]
+struct Cli {
+    /// Configuration file
+    #[clap(long, default_value = "/etc/shopsystem/config.ini")]
+    config: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Provide the renderer on the system bus (default)
+    Daemon,
+    /// Render a single invoice without D-Bus
+    Render {
+        /// Invoice request in JSON format
+        #[clap(long)]
+        json: PathBuf,
+        /// Output PDF file
+        #[clap(long)]
+        out: PathBuf,
+    },
 }
 
+#[derive(Clone)]
 struct PDFInvoiceRenderer {
     datapath: String,
     longname: String,
     vat: String,
+    taxid: String,
+    vat_exemption: String,
     addressrow: String,
     footer1: String,
     footer2: String,
     footer3: String,
     previous_tm: Option<chrono::DateTime<Local>>,
+    page: u32,
+    total_pages: u32,
     invoice_id: String,
     invoice_date: i64,
     invoice_recipient: InvoiceRecipient,
     invoice_entries: Vec<InvoiceEntry>,
+    facturx: bool,
+}
+
+struct InvoiceSummaryEntry {
+	ean: i64,
+	name: String,
+	count: u32,
+	price: i32,
+	vat: i32,
+}
+
+/* invoice line of the embedded e-invoice, all amounts are net amounts */
+struct EInvoiceLine {
+	ean: i64,
+	name: String,
+	quantity: i32, /* negative for credited lines, e.g. returned deposit */
+	unit_price: i64, /* 1/10000 € */
+	net: i32,
+	rate: i32,
+}
+
+struct EInvoiceTax {
+	rate: i32,
+	basis: i32,
+	tax: i32,
+}
+
+/* lowest y position for table entries, leaves space for the carried subtotal */
+const TABLE_END: f64 = 735.0;
+
+/* amount in cents formatted for XML, e.g. 1234 -> "12.34" */
+fn amount_to_str(amount: i64) -> String {
+	let sign = if amount < 0 { "-" } else { "" };
+	format!("{}{}.{:02}", sign, amount.abs() / 100, amount.abs() % 100)
+}
+
+fn xml_escape(text: &str) -> String {
+	text.replace('&', "&amp;")
+		.replace('<', "&lt;")
+		.replace('>', "&gt;")
+		.replace('"', "&quot;")
+		.replace('\'', "&apos;")
 }
 
 fn price_to_str(price: i32, with_euro: bool) -> String {
-    let euro = price / 100;
-    let cent = price % 100;
+    let sign = if price < 0 { "-" } else { "" };
+    let euro = price.abs() / 100;
+    let cent = price.abs() % 100;
     let symbol = if with_euro { "€" } else { "" };
-    format!("{euro},{cent:02}{symbol}")
+    format!("{sign}{euro},{cent:02}{symbol}")
 }
 
 impl PDFInvoiceRenderer {
@@ -366,6 +460,36 @@ impl PDFInvoiceRenderer {
 		sum
 	}
 
+	fn get_summary(&self) -> Vec<InvoiceSummaryEntry> {
+		let mut summary: Vec<InvoiceSummaryEntry> = Vec::new();
+
+		/* group by product and price, since the price may change within the invoice period */
+		for e in &self.invoice_entries {
+			/* deposit lines share EAN 0, so the name is compared as well */
+			match summary.iter_mut().find(|s| s.ean == e.product.ean && s.name == e.product.name && s.price == e.price && s.vat == e.vat) {
+				Some(s) => s.count += 1,
+				None => summary.push(InvoiceSummaryEntry {
+					ean: e.product.ean,
+					name: e.product.name.clone(),
+					count: 1,
+					price: e.price,
+					vat: e.vat,
+				}),
+			}
+		}
+
+		summary.sort_by(|a, b| a.name.cmp(&b.name).then(a.price.cmp(&b.price)));
+		summary
+	}
+
+	fn get_vat_breakdown(&self) -> Vec<VatBreakdown> {
+		vat_breakdown(self.invoice_entries.iter().map(|e| (e.vat, e.price)))
+	}
+
+	fn vat_liable(&self) -> bool {
+		self.vat == "yes"
+	}
+
 	fn get_address(&self) -> &'static str {
 		if self.invoice_recipient.gender == "masculinum" {
 			"Sehr geehrter Herr"
@@ -411,8 +535,13 @@ impl PDFInvoiceRenderer {
         let text = text.replace("{{{SUM}}}", &sum);
         let text = text.replace("{{{ORGANIZATION}}}", &self.longname);
 
-        let text = if self.vat == "yes" {
-            text.replace("{{{VAT}}}", "")
+        let text = if self.vat_liable() {
+            let mut vattext = "Im Gesamtbetrag ist folgende Umsatzsteuer enthalten:\n".to_string();
+            for b in self.get_vat_breakdown() {
+                vattext.push_str(&format!("{}% USt auf {}€ netto: <b>{}€</b>\n", b.rate, price_to_str(b.net, false), price_to_str(b.tax, false)));
+            }
+            vattext.push_str(&format!("Steuernummer: {}", self.taxid));
+            text.replace("{{{VAT}}}", &vattext)
         } else {
             let template = format!("{}/vat.txt", self.datapath);
             let vattext = std::fs::read_to_string(template)?;
@@ -452,6 +581,10 @@ impl PDFInvoiceRenderer {
 		ctx.line_to(120.0, 65.0);
 		ctx.move_to(180.0, 50.0);
 		ctx.line_to(180.0, 65.0);
+		if self.vat_liable() {
+			ctx.move_to(440.0, 50.0);
+			ctx.line_to(440.0, 65.0);
+		}
 		ctx.move_to(480.0, 50.0);
 		ctx.line_to(480.0, 65.0);
 		ctx.stroke()?;
@@ -463,6 +596,10 @@ impl PDFInvoiceRenderer {
 		ctx.show_text("Uhrzeit")?;
 		ctx.move_to(184.0, 61.5);
 		ctx.show_text("Artikel")?;
+		if self.vat_liable() {
+			ctx.move_to(444.0, 61.5);
+			ctx.show_text("USt")?;
+		}
 		ctx.move_to(484.0, 61.5);
 		ctx.show_text("Preis")?;
 
@@ -541,8 +678,9 @@ impl PDFInvoiceRenderer {
 		/* set line spacing */
 		layout.set_spacing(-2 * pango::SCALE);
 
-		/* set page width */
-		layout.set_width(290 * pango::SCALE);
+		/* set page width, leave space for the tax rate column */
+		let width = if self.vat_liable() { 250 } else { 290 };
+		layout.set_width(width * pango::SCALE);
 
 		/* write invoice date */
 		layout.set_text(&e.product.name);
@@ -552,7 +690,7 @@ impl PDFInvoiceRenderer {
 		let height = (h / pango::SCALE) as f64;
 
 		/* verify that the text fits on the page */
-		if 750.0 < y + height {
+		if TABLE_END < y + height {
 			return Ok(None);
         }
 
@@ -568,6 +706,18 @@ impl PDFInvoiceRenderer {
 		ctx.move_to(124.0, y+12.0);
 		ctx.show_text(&time)?;
 
+		/* render tax rate */
+		if self.vat_liable() {
+			ctx.move_to(444.0, y);
+			let vatlayout = pangocairo::functions::create_layout(ctx);
+			vatlayout.set_font_description(Some(&font));
+			vatlayout.set_alignment(pango::Alignment::Right);
+			vatlayout.set_width(32 * pango::SCALE);
+			vatlayout.set_text(&format!("{}%", e.vat));
+			pangocairo::functions::update_layout(ctx, &vatlayout);
+			pangocairo::functions::show_layout(ctx, &vatlayout);
+		}
+
 		/* render price */
 		ctx.move_to(484.0, y);
 		let pricelayout = pangocairo::functions::create_layout(ctx);
@@ -586,6 +736,10 @@ impl PDFInvoiceRenderer {
 		ctx.line_to(120.0, y+height);
 		ctx.move_to(180.0, y);
 		ctx.line_to(180.0, y+height);
+		if self.vat_liable() {
+			ctx.move_to(440.0, y);
+			ctx.line_to(440.0, y+height);
+		}
 		ctx.move_to(480.0, y);
 		ctx.line_to(480.0, y+height);
 		ctx.move_to(530.0, y);
@@ -599,14 +753,328 @@ impl PDFInvoiceRenderer {
 		Ok(Some((newy, tm)))
 	}
 
+	fn draw_section_title(&self, ctx: &cairo::Context, title: &str) -> Result<(), PDFError> {
+		ctx.save()?;
+
+		/* actually LMRoman12 */
+		ctx.set_source_rgb(0.0, 0.0, 0.0);
+		ctx.select_font_face("LMSans10", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+		ctx.set_font_size(12.9);
+
+		ctx.move_to(56.5, 40.0);
+		ctx.show_text(title)?;
+
+		ctx.restore()?;
+        Ok(())
+	}
+
+	fn draw_page_number(&self, ctx: &cairo::Context) -> Result<(), PDFError> {
+		ctx.save()?;
+		ctx.move_to(58.0, 800.0);
+		ctx.set_source_rgb(0.0, 0.0, 0.0);
+
+		/* get pango layout */
+		let layout = pangocairo::functions::create_layout(ctx);
+
+		/* setup font */
+		let mut font = pango::FontDescription::new();
+		font.set_family("LMSans10");
+		font.set_size(7 * pango::SCALE);
+		layout.set_font_description(Some(&font));
+
+		/* right alignment */
+		layout.set_alignment(pango::Alignment::Right);
+
+		/* set page width */
+		layout.set_width(472 * pango::SCALE);
+
+		/* write page number, total is unknown during the first pass */
+		let text = if self.total_pages > 0 {
+			format!("Seite {} von {}", self.page, self.total_pages)
+		} else {
+			format!("Seite {}", self.page)
+		};
+		layout.set_text(&text);
+
+		/* render text */
+		pangocairo::functions::update_layout(ctx, &layout);
+		pangocairo::functions::show_layout(ctx, &layout);
+
+		ctx.restore()?;
+        Ok(())
+	}
+
+	fn finish_page(&mut self, ctx: &cairo::Context) -> Result<(), PDFError> {
+		self.page += 1;
+		self.draw_page_number(ctx)?;
+		ctx.show_page()?;
+        Ok(())
+	}
+
+	fn draw_sum_row(&self, ctx: &cairo::Context, y: f64, label: &str, sum: i32) -> Result<f64, PDFError> {
+		ctx.save()?;
+
+		/* border & font color */
+		ctx.set_source_rgb(0.0, 0.0, 0.0);
+
+		/* line width of the border */
+		ctx.set_line_width(0.8);
+
+		/* borders */
+		ctx.move_to(58.0, y);
+		ctx.line_to(530.0, y);
+		ctx.line_to(530.0, y+15.0);
+		ctx.line_to(58.0, y+15.0);
+		ctx.line_to(58.0, y);
+		ctx.move_to(480.0, y);
+		ctx.line_to(480.0, y+15.0);
+		ctx.stroke()?;
+
+		/* label */
+		ctx.select_font_face("LMSans10", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+		ctx.set_font_size(10.0);
+		ctx.move_to(62.0, y+11.5);
+		ctx.show_text(label)?;
+
+		/* sum */
+		let mut font = pango::FontDescription::new();
+		font.set_family("LMSans10");
+		font.set_weight(pango::Weight::Bold);
+		font.set_size(8 * pango::SCALE);
+		ctx.move_to(484.0, y+1.0);
+		let pricelayout = pangocairo::functions::create_layout(ctx);
+		pricelayout.set_font_description(Some(&font));
+		pricelayout.set_alignment(pango::Alignment::Right);
+		pricelayout.set_width(42 * pango::SCALE);
+		pricelayout.set_text(&price_to_str(sum, true));
+		pangocairo::functions::update_layout(ctx, &pricelayout);
+		pangocairo::functions::show_layout(ctx, &pricelayout);
+
+		ctx.restore()?;
+        Ok(y+15.0)
+	}
+
+	fn draw_summary_table_header(&self, ctx: &cairo::Context) -> Result<(), PDFError> {
+		ctx.save()?;
+
+		/* border & font color */
+		ctx.set_source_rgb(0.0, 0.0, 0.0);
+
+		/* line width of the border */
+		ctx.set_line_width(0.8);
+
+		/* header font */
+		ctx.select_font_face("LMSans10", cairo::FontSlant::Normal, cairo::FontWeight::Bold);
+		ctx.set_font_size(12.0);
+
+		/* borders */
+		ctx.move_to(58.0, 50.0);
+		ctx.line_to(530.0, 50.0);
+		ctx.line_to(530.0, 65.0);
+		ctx.line_to(58.0, 65.0);
+		ctx.line_to(58.0, 50.0);
+		if self.vat_liable() {
+			ctx.move_to(310.0, 50.0);
+			ctx.line_to(310.0, 65.0);
+		}
+		ctx.move_to(350.0, 50.0);
+		ctx.line_to(350.0, 65.0);
+		ctx.move_to(410.0, 50.0);
+		ctx.line_to(410.0, 65.0);
+		ctx.move_to(480.0, 50.0);
+		ctx.line_to(480.0, 65.0);
+		ctx.stroke()?;
+
+		/* header text */
+		ctx.move_to(62.0, 61.5);
+		ctx.show_text("Artikel")?;
+		if self.vat_liable() {
+			ctx.move_to(314.0, 61.5);
+			ctx.show_text("USt")?;
+		}
+		ctx.move_to(354.0, 61.5);
+		ctx.show_text("Anzahl")?;
+		ctx.move_to(414.0, 61.5);
+		ctx.show_text("Preis")?;
+		ctx.move_to(484.0, 61.5);
+		ctx.show_text("Summe")?;
+
+		ctx.restore()?;
+        Ok(())
+	}
+
+	fn draw_summary_table_entry(&self, ctx: &cairo::Context, y: f64, e: &InvoiceSummaryEntry) -> Result<Option<f64>, PDFError> {
+		ctx.save()?;
+
+		/* border & font color */
+		ctx.set_source_rgb(0.0, 0.0, 0.0);
+
+		let linesum = e.price * e.count as i32;
+		if linesum > 999999 {
+            let msg = "Prices > 9999.99€ are not supported!".to_string();
+            return Err(PDFError::PriceTooHigh(msg));
+		}
+
+		/* move to position for article text */
+		ctx.move_to(62.0, y);
+
+		/* get pango layout */
+		let layout = pangocairo::functions::create_layout(ctx);
+
+		/* setup font */
+		let mut font = pango::FontDescription::new();
+		font.set_family("LMSans10");
+		font.set_size(8 * pango::SCALE);
+		layout.set_font_description(Some(&font));
+
+		/* left alignment */
+		layout.set_alignment(pango::Alignment::Left);
+		layout.set_wrap(pango::WrapMode::WordChar);
+
+		/* set line spacing */
+		layout.set_spacing(-2 * pango::SCALE);
+
+		/* set page width, leave space for the tax rate column */
+		let width = if self.vat_liable() { 244 } else { 284 };
+		layout.set_width(width * pango::SCALE);
+
+		/* write article name */
+		layout.set_text(&e.name);
+
+		/* get height of text */
+		let (_w, h) = layout.size();
+		let height = (h / pango::SCALE) as f64;
+
+		/* verify that the text fits on the page */
+		if TABLE_END < y + height {
+			return Ok(None);
+		}
+
+		/* render article text */
+		pangocairo::functions::update_layout(ctx, &layout);
+		pangocairo::functions::show_layout(ctx, &layout);
+
+		/* render tax rate, count, unit price and line sum */
+		let mut columns = vec![(350.0, 56, format!("{}", e.count)), (410.0, 66, price_to_str(e.price, true)), (480.0, 46, price_to_str(linesum, true))];
+		if self.vat_liable() {
+			columns.insert(0, (310.0, 36, format!("{}%", e.vat)));
+		}
+		for (x, width, text) in columns {
+			ctx.move_to(x, y);
+			let numlayout = pangocairo::functions::create_layout(ctx);
+			numlayout.set_font_description(Some(&font));
+			numlayout.set_alignment(pango::Alignment::Right);
+			numlayout.set_width(width * pango::SCALE);
+			numlayout.set_text(&text);
+			pangocairo::functions::update_layout(ctx, &numlayout);
+			pangocairo::functions::show_layout(ctx, &numlayout);
+		}
+
+		/* add borders */
+		ctx.set_line_width(0.8);
+		ctx.move_to(58.0, y);
+		ctx.line_to(58.0, y+height);
+		if self.vat_liable() {
+			ctx.move_to(310.0, y);
+			ctx.line_to(310.0, y+height);
+		}
+		ctx.move_to(350.0, y);
+		ctx.line_to(350.0, y+height);
+		ctx.move_to(410.0, y);
+		ctx.line_to(410.0, y+height);
+		ctx.move_to(480.0, y);
+		ctx.line_to(480.0, y+height);
+		ctx.move_to(530.0, y);
+		ctx.line_to(530.0, y+height);
+		ctx.stroke()?;
+
+		ctx.restore()?;
+
+		Ok(Some(y + height))
+	}
+
+	fn draw_summary_table(&mut self, ctx: &cairo::Context) -> Result<(), PDFError> {
+		ctx.save()?;
+
+		self.draw_footer(ctx)?;
+		self.draw_section_title(ctx, "Übersicht")?;
+		self.draw_summary_table_header(ctx)?;
+
+		/* initial position for entries */
+		let mut y = 65.0_f64;
+		let mut sum = 0;
+
+		for entry in self.get_summary() {
+			let result = self.draw_summary_table_entry(ctx, y, &entry)?;
+			y = match result {
+				Some(new_y) => new_y,
+				None => {
+					/* entry could not be added, because end of page has been reached */
+					self.draw_sum_row(ctx, y, "Übertrag", sum)?;
+					self.finish_page(ctx)?;
+
+					/* draw page footer, table header and carried subtotal on new page */
+					self.draw_footer(ctx)?;
+					self.draw_section_title(ctx, "Übersicht")?;
+					self.draw_summary_table_header(ctx)?;
+					y = self.draw_sum_row(ctx, 65.0, "Übertrag", sum)?;
+
+					/* retry adding the entry */
+					match self.draw_summary_table_entry(ctx, y, &entry)? {
+						Some(new_y) => new_y,
+						None => {
+							let msg = format!("Article name \"{}\" does not fit on a single page!", entry.name);
+							return Err(PDFError::ArticleNameTooLong(msg));
+						}
+					}
+				}
+			};
+			sum += entry.price * entry.count as i32;
+		}
+
+		let mut y = self.draw_sum_row(ctx, y, "Summe", sum)?;
+
+		/* deposit lines are added by the database with EAN 0 */
+		let deposit: Vec<i32> = self.invoice_entries.iter().filter(|e| e.product.ean == 0).map(|e| e.price).collect();
+		if !deposit.is_empty() {
+			y = self.draw_sum_row(ctx, y, "davon Pfand", deposit.iter().sum())?;
+		}
+
+		/* tax breakdown per rate */
+		if self.vat_liable() {
+			let breakdown = self.get_vat_breakdown();
+
+			/* keep the breakdown on a single page */
+			if TABLE_END < y + 15.0 + 30.0 * breakdown.len() as f64 {
+				self.finish_page(ctx)?;
+				self.draw_footer(ctx)?;
+				self.draw_section_title(ctx, "Übersicht")?;
+				y = 35.0;
+			}
+
+			y += 15.0;
+			for b in breakdown {
+				y = self.draw_sum_row(ctx, y, &format!("Nettobetrag {}%", b.rate), b.net)?;
+				y = self.draw_sum_row(ctx, y, &format!("Umsatzsteuer {}%", b.rate), b.tax)?;
+			}
+		}
+
+		self.finish_page(ctx)?;
+
+		ctx.restore()?;
+        Ok(())
+	}
+
 	fn draw_invoice_table(&mut self, ctx: &cairo::Context) -> Result<(), PDFError> {
 		ctx.save()?;
 
 		self.draw_footer(ctx)?;
+		self.draw_section_title(ctx, "Einzelaufstellung")?;
 		self.draw_invoice_table_header(ctx)?;
 
 		/* initial position for entries */
 		let mut y = 65.0_f64;
+		let mut sum = 0;
 
 		for entry in &self.invoice_entries {
             let result = self.draw_invoice_table_entry(ctx, y, entry)?;
@@ -617,15 +1085,14 @@ impl PDFInvoiceRenderer {
                 },
                 None => {
                     /* entry could not be added, because end of page has been reached */
-                    self.draw_invoice_table_footer(ctx, y)?;
-                    ctx.show_page()?;
+                    self.draw_sum_row(ctx, y, "Übertrag", sum)?;
+                    self.finish_page(ctx)?;
 
-                    /* draw page footer & table header on new page */
+                    /* draw page footer, table header and carried subtotal on new page */
                     self.draw_footer(ctx)?;
+                    self.draw_section_title(ctx, "Einzelaufstellung")?;
                     self.draw_invoice_table_header(ctx)?;
-
-                    /* reset position */
-                    y = 65.0_f64;
+                    y = self.draw_sum_row(ctx, 65.0, "Übertrag", sum)?;
 
                     /* always print date on new pages */
                     self.previous_tm = None;
@@ -644,24 +1111,47 @@ impl PDFInvoiceRenderer {
                     }
                 }
 			}
+			sum += entry.price;
 		}
 
 		self.draw_invoice_table_footer(ctx, y)?;
-		ctx.show_page()?;
+		self.draw_sum_row(ctx, y, "Summe", sum)?;
+		self.finish_page(ctx)?;
 
 		ctx.restore()?;
         Ok(())
 	}
 
+	fn render(&mut self, ctx: &cairo::Context) -> Result<(), PDFError> {
+		self.page = 0;
+		self.previous_tm = None;
+
+        self.draw_logo(ctx)?;
+        self.draw_address(ctx)?;
+        self.draw_folding_marks(ctx)?;
+        self.draw_footer(ctx)?;
+		self.draw_footer_text_left(ctx)?;
+		self.draw_footer_text_middle(ctx)?;
+		self.draw_footer_text_right(ctx)?;
+		self.draw_date(ctx)?;
+		self.draw_title(ctx)?;
+		self.draw_first_page_text(ctx)?;
+		self.finish_page(ctx)?;
+
+		/* following pages: summary grouped by product */
+		self.draw_summary_table(ctx)?;
+
+		/* following pages: detailed invoice table */
+		self.draw_invoice_table(ctx)?;
+
+        Ok(())
+	}
+
     fn generate(&mut self) -> Result<Vec<u8>, PDFError> {
         /* A4 sizes (in points, 72 DPI) */
         let width  = 595.27559; /* 210mm */
         let height = 841.88976; /* 297mm */
 
-        let buffer: std::io::Cursor<Vec<u8>> = Default::default();
-        let document = cairo::PdfSurface::for_stream(width, height, buffer)?;
-        let ctx = cairo::Context::new(&document)?;
-
 		if self.invoice_id.is_empty() {
             return Err(PDFError::MissingData("No invoice ID given!".to_string()));
         }
@@ -678,119 +1168,392 @@ impl PDFInvoiceRenderer {
             return Err(PDFError::MissingData("No invoice recipient given!".to_string()));
         }
 
-        self.draw_logo(&ctx)?;
-        self.draw_address(&ctx)?;
-        self.draw_folding_marks(&ctx)?;
-        self.draw_footer(&ctx)?;
-		self.draw_footer_text_left(&ctx)?;
-		self.draw_footer_text_middle(&ctx)?;
-		self.draw_footer_text_right(&ctx)?;
-		self.draw_date(&ctx)?;
-		self.draw_title(&ctx)?;
-		self.draw_first_page_text(&ctx)?;
-		ctx.show_page()?;
+		if self.vat_liable() && self.taxid.is_empty() {
+            return Err(PDFError::MissingData("No tax number configured!".to_string()));
+        }
+
+		/* first pass: get number of pages */
+		self.total_pages = 0;
+        let document = cairo::PdfSurface::for_stream(width, height, std::io::sink())?;
+        let ctx = cairo::Context::new(&document)?;
+		self.render(&ctx)?;
+		document.finish();
+		self.total_pages = self.page;
 
-		/* following pages: invoice table */
-		self.draw_invoice_table(&ctx)?;
+		/* second pass: render document with "page X of Y" */
+        let buffer: std::io::Cursor<Vec<u8>> = Default::default();
+        let document = cairo::PdfSurface::for_stream(width, height, buffer)?;
+        let ctx = cairo::Context::new(&document)?;
+		self.render(&ctx)?;
 
 		document.flush();
 		let result = document.finish_output_stream();
-        match result {
+        let pdf = match result {
             Ok(boxedstream) => {
                 let buffer = boxedstream.downcast::<std::io::Cursor<Vec<u8>>>();
                 match buffer {
                     Ok(buffer) => {
-                        Ok(buffer.into_inner())
+                        buffer.into_inner()
                     },
                     Err(_) => {
-                        Err(PDFError::StreamError("Failed to unbox stream".to_string()))
+                        return Err(PDFError::StreamError("Failed to unbox stream".to_string()));
                     }
                 }
             },
             Err(swe) => {
-                Err(PDFError::StreamError(swe.error.to_string()))
+                return Err(PDFError::StreamError(swe.error.to_string()));
             },
+        };
+
+        if !self.facturx {
+            return Ok(pdf);
         }
-    }
 
-    fn clear(&mut self) {
-        self.invoice_date = 0;
-        self.invoice_id = String::new();
-        self.invoice_recipient = InvoiceRecipient::default();
-        self.invoice_entries.clear();
+        let xml = self.generate_facturx_xml()?;
+        self.embed_facturx(pdf, &xml)
     }
-}
 
-struct PDFInvoice {
-    renderer: PDFInvoiceRenderer,
-}
+	/* seller address is taken from the address row, e.g. "Name, Street 1, 12345 City" */
+	fn get_seller_address(&self) -> (String, String, String) {
+		let parts: Vec<&str> = self.addressrow.split(", ").collect();
+		if parts.len() < 3 {
+			return (String::new(), String::new(), String::new());
+		}
 
-#[interface(name = "io.mainframe.shopsystem.InvoicePDF")]
-impl PDFInvoice {
-    #[zbus(property)]
-    async fn invoice_id(&self) -> &str {
-        &self.renderer.invoice_id
-    }
+		let street = parts[parts.len()-2].trim().to_string();
+		let (postal_code, city) = parts[parts.len()-1].trim().split_once(' ').unwrap_or(("", parts[parts.len()-1]));
+		(street, postal_code.to_string(), city.trim().to_string())
+	}
 
-    #[zbus(property)]
-    async fn set_invoice_id(&mut self, id: &str) {
-        self.renderer.invoice_id = id.to_string();
-    }
+	fn get_einvoice_data(&self) -> (Vec<EInvoiceLine>, Vec<EInvoiceTax>) {
+		let mut lines: Vec<EInvoiceLine> = Vec::new();
+		let mut taxes: Vec<EInvoiceTax> = Vec::new();
+
+		/* the net unit price is kept with four decimals and the line amount is derived from it, so that
+		 * BR-LIN-04 holds; quantities carry the sign of credited lines, e.g. returned deposit */
+		for e in self.get_summary() {
+			let rate = if self.vat_liable() { e.vat } else { 0 };
+			let price = e.price.abs() as i64;
+			let unit_price = if self.vat_liable() { div_round(price * 10000, 100 + rate as i64) } else { price * 100 };
+			let quantity = if e.price < 0 { -(e.count as i32) } else { e.count as i32 };
+			let net = div_round(unit_price * quantity as i64, 100) as i32;
+			lines.push(EInvoiceLine { ean: e.ean, name: e.name, quantity: quantity, unit_price: unit_price, net: net, rate: rate });
+		}
 
-    #[zbus(property)]
-    async fn invoice_date(&self) -> i64 {
-        self.renderer.invoice_date
-    }
+		/* the tax is the printed gross sum minus the line amounts, so rounding differences end up in the tax only */
+		if self.vat_liable() {
+			for b in self.get_vat_breakdown() {
+				let basis: i32 = lines.iter().filter(|l| l.rate == b.rate).map(|l| l.net).sum();
+				taxes.push(EInvoiceTax { rate: b.rate, basis: basis, tax: b.gross - basis });
+			}
+		} else {
+			taxes.push(EInvoiceTax { rate: 0, basis: self.get_sum(), tax: 0 });
+		}
 
-    #[zbus(property)]
-    async fn set_invoice_date(&mut self, date: i64) {
-        self.renderer.invoice_date = date;
-    }
+		(lines, taxes)
+	}
 
-    #[zbus(property)]
-    async fn invoice_recipient(&self) -> InvoiceRecipient {
-        self.renderer.invoice_recipient.clone()
-    }
+	/* offline check of the EN 16931 business rules, which apply to the generated data */
+	fn validate_einvoice(&self, lines: &Vec<EInvoiceLine>, taxes: &Vec<EInvoiceTax>) -> Result<(), PDFError> {
+		let fail = |rule: &str, msg: &str| Err(PDFError::EInvoiceError(format!("{}: {}", rule, msg)));
+		let (street, postal_code, city) = self.get_seller_address();
 
-    #[zbus(property)]
-    async fn set_invoice_recipient(&mut self, recipient: InvoiceRecipient) {
-        self.renderer.invoice_recipient = recipient.clone();
-    }
+		if self.invoice_id.is_empty() {
+			return fail("BR-02", "invoice number missing");
+		}
+		if self.invoice_date == 0 {
+			return fail("BR-03", "invoice issue date missing");
+		}
+		if self.longname.is_empty() {
+			return fail("BR-06", "seller name missing");
+		}
+		if street.is_empty() || postal_code.is_empty() || city.is_empty() {
+			return fail("BR-08", "seller postal address can not be parsed from INVOICE addressrow");
+		}
+		if self.invoice_recipient.firstname.is_empty() && self.invoice_recipient.lastname.is_empty() {
+			return fail("BR-07", "buyer name missing");
+		}
+		if lines.is_empty() {
+			return fail("BR-16", "invoice has no lines");
+		}
+		if taxes.iter().any(|t| t.rate > 0) && self.taxid.is_empty() {
+			return fail("BR-S-02", "seller tax registration missing");
+		}
+		if !self.vat_liable() && self.vat_exemption.is_empty() {
+			return fail("BR-E-10", "VAT exemption reason missing, see INVOICE vat_exemption");
+		}
 
-    #[zbus(property)]
-    async fn set_invoice_entries(&mut self, invoice_entries: Vec<InvoiceEntry>) {
-        self.renderer.invoice_entries = invoice_entries.clone();
-    }
+		let line_total: i32 = lines.iter().map(|l| l.net).sum();
+		let basis_total: i32 = taxes.iter().map(|t| t.basis).sum();
+		let tax_total: i32 = taxes.iter().map(|t| t.tax).sum();
+
+		for t in taxes {
+			let linesum: i32 = lines.iter().filter(|l| l.rate == t.rate).map(|l| l.net).sum();
+			if linesum != t.basis {
+				return fail("BR-S-08", &format!("line amounts do not match taxable amount for {}%", t.rate));
+			}
+
+			/* every line amount may be off by half a cent */
+			let lines_with_rate = lines.iter().filter(|l| l.rate == t.rate).count() as i64;
+			let expected = div_round(t.basis as i64 * t.rate as i64, 100);
+			if (t.tax as i64 - expected).abs() > lines_with_rate / 2 + 1 {
+				return fail("BR-S-09", &format!("tax amount for {}% does not match taxable amount", t.rate));
+			}
+		}
+		if line_total != basis_total {
+			return fail("BR-CO-13", "sum of line amounts does not match tax basis total");
+		}
+		if line_total + tax_total != self.get_sum() {
+			return fail("BR-CO-15", "invoice total does not match the printed invoice sum");
+		}
+
+		Ok(())
+	}
+
+	fn generate_facturx_xml(&self) -> Result<String, PDFError> {
+		let (lines, taxes) = self.get_einvoice_data();
+		self.validate_einvoice(&lines, &taxes)?;
+
+		let (street, postal_code, city) = self.get_seller_address();
+		let date = chrono::DateTime::<Utc>::from_timestamp(self.invoice_date, 0).expect("invalid timestamp");
+		let date: chrono::DateTime<Local> = chrono::DateTime::from(date);
+		let duedate = date + chrono::Duration::days(10);
+		let first = self.invoice_entries.iter().map(|e| e.timestamp).min().unwrap_or(self.invoice_date);
+		let last = self.invoice_entries.iter().map(|e| e.timestamp).max().unwrap_or(self.invoice_date);
+		let first: chrono::DateTime<Local> = chrono::DateTime::from(chrono::DateTime::<Utc>::from_timestamp(first, 0).expect("invalid timestamp"));
+		let last: chrono::DateTime<Local> = chrono::DateTime::from(chrono::DateTime::<Utc>::from_timestamp(last, 0).expect("invalid timestamp"));
+		let buyer = format!("{} {}", self.invoice_recipient.firstname, self.invoice_recipient.lastname);
+		let category = if self.vat_liable() { "S" } else { "E" };
+
+		let mut xml = String::new();
+		xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+		xml.push_str("<rsm:CrossIndustryInvoice xmlns:rsm=\"urn:un:unece:uncefact:data:standard:CrossIndustryInvoice:100\" xmlns:ram=\"urn:un:unece:uncefact:data:standard:ReusableAggregateBusinessInformationEntity:100\" xmlns:qdt=\"urn:un:unece:uncefact:data:standard:QualifiedDataType:100\" xmlns:udt=\"urn:un:unece:uncefact:data:standard:UnqualifiedDataType:100\">\n");
+
+		/* document */
+		xml.push_str("\t<rsm:ExchangedDocumentContext>\n");
+		xml.push_str("\t\t<ram:GuidelineSpecifiedDocumentContextParameter><ram:ID>urn:cen.eu:en16931:2017</ram:ID></ram:GuidelineSpecifiedDocumentContextParameter>\n");
+		xml.push_str("\t</rsm:ExchangedDocumentContext>\n");
+		xml.push_str("\t<rsm:ExchangedDocument>\n");
+		xml.push_str(&format!("\t\t<ram:ID>{}</ram:ID>\n", xml_escape(&self.invoice_id)));
+		xml.push_str("\t\t<ram:TypeCode>380</ram:TypeCode>\n");
+		xml.push_str(&format!("\t\t<ram:IssueDateTime><udt:DateTimeString format=\"102\">{}</udt:DateTimeString></ram:IssueDateTime>\n", date.format("%Y%m%d")));
+		xml.push_str("\t</rsm:ExchangedDocument>\n");
+
+		xml.push_str("\t<rsm:SupplyChainTradeTransaction>\n");
+
+		/* lines */
+		for (i, l) in lines.iter().enumerate() {
+			xml.push_str("\t\t<ram:IncludedSupplyChainTradeLineItem>\n");
+			xml.push_str(&format!("\t\t\t<ram:AssociatedDocumentLineDocument><ram:LineID>{}</ram:LineID></ram:AssociatedDocumentLineDocument>\n", i+1));
+			xml.push_str("\t\t\t<ram:SpecifiedTradeProduct>\n");
+			if l.ean > 0 {
+				xml.push_str(&format!("\t\t\t\t<ram:GlobalID schemeID=\"0160\">{:013}</ram:GlobalID>\n", l.ean));
+			}
+			xml.push_str(&format!("\t\t\t\t<ram:Name>{}</ram:Name>\n", xml_escape(&l.name)));
+			xml.push_str("\t\t\t</ram:SpecifiedTradeProduct>\n");
+			xml.push_str(&format!("\t\t\t<ram:SpecifiedLineTradeAgreement><ram:NetPriceProductTradePrice><ram:ChargeAmount>{}.{:04}</ram:ChargeAmount></ram:NetPriceProductTradePrice></ram:SpecifiedLineTradeAgreement>\n", l.unit_price / 10000, l.unit_price % 10000));
+			xml.push_str(&format!("\t\t\t<ram:SpecifiedLineTradeDelivery><ram:BilledQuantity unitCode=\"H87\">{}</ram:BilledQuantity></ram:SpecifiedLineTradeDelivery>\n", l.quantity));
+			xml.push_str("\t\t\t<ram:SpecifiedLineTradeSettlement>\n");
+			xml.push_str(&format!("\t\t\t\t<ram:ApplicableTradeTax><ram:TypeCode>VAT</ram:TypeCode><ram:CategoryCode>{}</ram:CategoryCode><ram:RateApplicablePercent>{}</ram:RateApplicablePercent></ram:ApplicableTradeTax>\n", category, l.rate));
+			xml.push_str(&format!("\t\t\t\t<ram:SpecifiedTradeSettlementLineMonetarySummation><ram:LineTotalAmount>{}</ram:LineTotalAmount></ram:SpecifiedTradeSettlementLineMonetarySummation>\n", amount_to_str(l.net as i64)));
+			xml.push_str("\t\t\t</ram:SpecifiedLineTradeSettlement>\n");
+			xml.push_str("\t\t</ram:IncludedSupplyChainTradeLineItem>\n");
+		}
+
+		/* seller and buyer */
+		xml.push_str("\t\t<ram:ApplicableHeaderTradeAgreement>\n");
+		xml.push_str("\t\t\t<ram:SellerTradeParty>\n");
+		xml.push_str(&format!("\t\t\t\t<ram:Name>{}</ram:Name>\n", xml_escape(&self.longname)));
+		xml.push_str(&format!("\t\t\t\t<ram:PostalTradeAddress><ram:PostcodeCode>{}</ram:PostcodeCode><ram:LineOne>{}</ram:LineOne><ram:CityName>{}</ram:CityName><ram:CountryID>DE</ram:CountryID></ram:PostalTradeAddress>\n", xml_escape(&postal_code), xml_escape(&street), xml_escape(&city)));
+		if !self.taxid.is_empty() {
+			xml.push_str(&format!("\t\t\t\t<ram:SpecifiedTaxRegistration><ram:ID schemeID=\"FC\">{}</ram:ID></ram:SpecifiedTaxRegistration>\n", xml_escape(&self.taxid)));
+		}
+		xml.push_str("\t\t\t</ram:SellerTradeParty>\n");
+		xml.push_str("\t\t\t<ram:BuyerTradeParty>\n");
+		xml.push_str(&format!("\t\t\t\t<ram:Name>{}</ram:Name>\n", xml_escape(buyer.trim())));
+		xml.push_str(&format!("\t\t\t\t<ram:PostalTradeAddress><ram:PostcodeCode>{}</ram:PostcodeCode><ram:LineOne>{}</ram:LineOne><ram:CityName>{}</ram:CityName><ram:CountryID>DE</ram:CountryID></ram:PostalTradeAddress>\n", xml_escape(&self.invoice_recipient.postal_code), xml_escape(&self.invoice_recipient.street), xml_escape(&self.invoice_recipient.city)));
+		xml.push_str("\t\t\t</ram:BuyerTradeParty>\n");
+		xml.push_str("\t\t</ram:ApplicableHeaderTradeAgreement>\n");
+		xml.push_str("\t\t<ram:ApplicableHeaderTradeDelivery/>\n");
+
+		/* taxes and totals */
+		let line_total: i32 = lines.iter().map(|l| l.net).sum();
+		let tax_total: i32 = taxes.iter().map(|t| t.tax).sum();
+		let grand_total = line_total + tax_total;
+
+		xml.push_str("\t\t<ram:ApplicableHeaderTradeSettlement>\n");
+		xml.push_str(&format!("\t\t\t<ram:PaymentReference>{}</ram:PaymentReference>\n", xml_escape(&self.invoice_id)));
+		xml.push_str("\t\t\t<ram:InvoiceCurrencyCode>EUR</ram:InvoiceCurrencyCode>\n");
+		for t in &taxes {
+			xml.push_str("\t\t\t<ram:ApplicableTradeTax>\n");
+			xml.push_str(&format!("\t\t\t\t<ram:CalculatedAmount>{}</ram:CalculatedAmount>\n", amount_to_str(t.tax as i64)));
+			xml.push_str("\t\t\t\t<ram:TypeCode>VAT</ram:TypeCode>\n");
+			if !self.vat_liable() {
+				xml.push_str(&format!("\t\t\t\t<ram:ExemptionReason>{}</ram:ExemptionReason>\n", xml_escape(&self.vat_exemption)));
+			}
+			xml.push_str(&format!("\t\t\t\t<ram:BasisAmount>{}</ram:BasisAmount>\n", amount_to_str(t.basis as i64)));
+			xml.push_str(&format!("\t\t\t\t<ram:CategoryCode>{}</ram:CategoryCode>\n", category));
+			xml.push_str(&format!("\t\t\t\t<ram:RateApplicablePercent>{}</ram:RateApplicablePercent>\n", t.rate));
+			xml.push_str("\t\t\t</ram:ApplicableTradeTax>\n");
+		}
+		xml.push_str("\t\t\t<ram:BillingSpecifiedPeriod>\n");
+		xml.push_str(&format!("\t\t\t\t<ram:StartDateTime><udt:DateTimeString format=\"102\">{}</udt:DateTimeString></ram:StartDateTime>\n", first.format("%Y%m%d")));
+		xml.push_str(&format!("\t\t\t\t<ram:EndDateTime><udt:DateTimeString format=\"102\">{}</udt:DateTimeString></ram:EndDateTime>\n", last.format("%Y%m%d")));
+		xml.push_str("\t\t\t</ram:BillingSpecifiedPeriod>\n");
+		xml.push_str("\t\t\t<ram:SpecifiedTradePaymentTerms>\n");
+		xml.push_str("\t\t\t\t<ram:Description>Der Gesamtbetrag wird in 10 Tagen von dem angegebenen Bankkonto eingezogen.</ram:Description>\n");
+		xml.push_str(&format!("\t\t\t\t<ram:DueDateDateTime><udt:DateTimeString format=\"102\">{}</udt:DateTimeString></ram:DueDateDateTime>\n", duedate.format("%Y%m%d")));
+		xml.push_str("\t\t\t</ram:SpecifiedTradePaymentTerms>\n");
+		xml.push_str("\t\t\t<ram:SpecifiedTradeSettlementHeaderMonetarySummation>\n");
+		xml.push_str(&format!("\t\t\t\t<ram:LineTotalAmount>{}</ram:LineTotalAmount>\n", amount_to_str(line_total as i64)));
+		xml.push_str(&format!("\t\t\t\t<ram:TaxBasisTotalAmount>{}</ram:TaxBasisTotalAmount>\n", amount_to_str(line_total as i64)));
+		xml.push_str(&format!("\t\t\t\t<ram:TaxTotalAmount currencyID=\"EUR\">{}</ram:TaxTotalAmount>\n", amount_to_str(tax_total as i64)));
+		xml.push_str(&format!("\t\t\t\t<ram:GrandTotalAmount>{}</ram:GrandTotalAmount>\n", amount_to_str(grand_total as i64)));
+		xml.push_str(&format!("\t\t\t\t<ram:DuePayableAmount>{}</ram:DuePayableAmount>\n", amount_to_str(grand_total as i64)));
+		xml.push_str("\t\t\t</ram:SpecifiedTradeSettlementHeaderMonetarySummation>\n");
+		xml.push_str("\t\t</ram:ApplicableHeaderTradeSettlement>\n");
+
+		xml.push_str("\t</rsm:SupplyChainTradeTransaction>\n");
+		xml.push_str("</rsm:CrossIndustryInvoice>\n");
+
+		Ok(xml)
+	}
 
-    #[zbus(property)]
-    async fn invoice_entries(&self) -> Vec<InvoiceEntry> {
-        self.renderer.invoice_entries.clone()
+	/*
+	 * Embed the XML as associated file and add the Factur-X XMP metadata. Cairo
+	 * does not create PDF/A-3 documents (e.g. the output intent is missing), so
+	 * no PDF/A conformance is claimed, but the XML is found by Factur-X readers.
+	 */
+	fn embed_facturx(&self, pdf: Vec<u8>, xml: &str) -> Result<Vec<u8>, PDFError> {
+		let mut doc = lopdf::Document::load_mem(&pdf)?;
+
+		let date = chrono::DateTime::<Utc>::from_timestamp(self.invoice_date, 0).expect("invalid timestamp");
+		let pdfdate = format!("D:{}", date.format("%Y%m%d%H%M%SZ"));
+		let xmpdate = date.format("%Y-%m-%dT%H:%M:%SZ").to_string();
+
+		let file = Stream::new(dictionary! {
+			"Type" => "EmbeddedFile",
+			"Subtype" => "text/xml",
+			"Params" => dictionary! {
+				"Size" => xml.len() as i64,
+				"ModDate" => Object::string_literal(pdfdate),
+			},
+		}, xml.as_bytes().to_vec());
+		let file_id = doc.add_object(file);
+
+		let filespec_id = doc.add_object(dictionary! {
+			"Type" => "Filespec",
+			"F" => Object::string_literal("factur-x.xml"),
+			"UF" => Object::string_literal("factur-x.xml"),
+			"Desc" => Object::string_literal("Factur-X Invoice"),
+			"AFRelationship" => "Alternative",
+			"EF" => dictionary! {
+				"F" => file_id,
+				"UF" => file_id,
+			},
+		});
+
+		let xmp = format!(r#"<?xpacket begin="" id="W5M0MpCehiHzreSzNTczkc9d"?>
+<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:dc="http://purl.org/dc/elements/1.1/">
+   <dc:title><rdf:Alt><rdf:li xml:lang="x-default">Rechnung {id}</rdf:li></rdf:Alt></dc:title>
+   <dc:creator><rdf:Seq><rdf:li>{seller}</rdf:li></rdf:Seq></dc:creator>
+  </rdf:Description>
+  <rdf:Description rdf:about="" xmlns:xmp="http://ns.adobe.com/xap/1.0/">
+   <xmp:CreateDate>{date}</xmp:CreateDate>
+  </rdf:Description>
+  <rdf:Description rdf:about="" xmlns:pdfaExtension="http://www.aiim.org/pdfa/ns/extension/" xmlns:pdfaSchema="http://www.aiim.org/pdfa/ns/schema#" xmlns:pdfaProperty="http://www.aiim.org/pdfa/ns/property#">
+   <pdfaExtension:schemas>
+    <rdf:Bag>
+     <rdf:li rdf:parseType="Resource">
+      <pdfaSchema:schema>Factur-X PDFA Extension Schema</pdfaSchema:schema>
+      <pdfaSchema:namespaceURI>urn:factur-x:pdfa:CrossIndustryDocument:invoice:1p0#</pdfaSchema:namespaceURI>
+      <pdfaSchema:prefix>fx</pdfaSchema:prefix>
+      <pdfaSchema:property>
+       <rdf:Seq>
+        <rdf:li rdf:parseType="Resource"><pdfaProperty:name>DocumentFileName</pdfaProperty:name><pdfaProperty:valueType>Text</pdfaProperty:valueType><pdfaProperty:category>external</pdfaProperty:category><pdfaProperty:description>name of the embedded XML invoice file</pdfaProperty:description></rdf:li>
+        <rdf:li rdf:parseType="Resource"><pdfaProperty:name>DocumentType</pdfaProperty:name><pdfaProperty:valueType>Text</pdfaProperty:valueType><pdfaProperty:category>external</pdfaProperty:category><pdfaProperty:description>INVOICE</pdfaProperty:description></rdf:li>
+        <rdf:li rdf:parseType="Resource"><pdfaProperty:name>Version</pdfaProperty:name><pdfaProperty:valueType>Text</pdfaProperty:valueType><pdfaProperty:category>external</pdfaProperty:category><pdfaProperty:description>The actual version of the Factur-X XML schema</pdfaProperty:description></rdf:li>
+        <rdf:li rdf:parseType="Resource"><pdfaProperty:name>ConformanceLevel</pdfaProperty:name><pdfaProperty:valueType>Text</pdfaProperty:valueType><pdfaProperty:category>external</pdfaProperty:category><pdfaProperty:description>The conformance level of the embedded Factur-X data</pdfaProperty:description></rdf:li>
+       </rdf:Seq>
+      </pdfaSchema:property>
+     </rdf:li>
+    </rdf:Bag>
+   </pdfaExtension:schemas>
+  </rdf:Description>
+  <rdf:Description rdf:about="" xmlns:fx="urn:factur-x:pdfa:CrossIndustryDocument:invoice:1p0#">
+   <fx:DocumentType>INVOICE</fx:DocumentType>
+   <fx:DocumentFileName>factur-x.xml</fx:DocumentFileName>
+   <fx:Version>1.0</fx:Version>
+   <fx:ConformanceLevel>EN 16931</fx:ConformanceLevel>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>
+<?xpacket end="w"?>"#, id = xml_escape(&self.invoice_id), seller = xml_escape(&self.longname), date = xmpdate);
+
+		let metadata_id = doc.add_object(Stream::new(dictionary! {
+			"Type" => "Metadata",
+			"Subtype" => "XML",
+		}, xmp.into_bytes()));
+
+		let catalog = doc.catalog_mut()?;
+		catalog.set("Names", dictionary! {
+			"EmbeddedFiles" => dictionary! {
+				"Names" => vec![Object::string_literal("factur-x.xml"), filespec_id.into()],
+			},
+		});
+		catalog.set("AF", vec![Object::Reference(filespec_id)]);
+		catalog.set("Metadata", metadata_id);
+
+		let mut buffer = Vec::new();
+		doc.save_to(&mut buffer)?;
+		Ok(buffer)
+	}
+
+    /* renders on a copy, so that concurrent requests do not share any state */
+    fn with_request(&self, request: InvoiceRequest) -> PDFInvoiceRenderer {
+        let mut renderer = self.clone();
+        renderer.invoice_id = request.id;
+        renderer.invoice_date = request.date;
+        renderer.invoice_recipient = request.recipient;
+        renderer.invoice_entries = request.entries;
+        renderer.facturx = request.facturx;
+        renderer
     }
+}
 
-    fn generate(&mut self) -> Result<Vec<u8>, PDFError> {
-        self.renderer.generate()
+struct PDFInvoice {
+    renderer: PDFInvoiceRenderer,
+}
+
+#[interface(name = "io.mainframe.shopsystem.InvoicePDF")]
+impl PDFInvoice {
+    fn render(&self, request: InvoiceRequest) -> Result<Vec<u8>, PDFError> {
+        self.renderer.with_request(request).generate()
     }
 
-    fn clear(&mut self) {
-        self.renderer.clear()
+    fn render_facturx_xml(&self, request: InvoiceRequest) -> Result<String, PDFError> {
+        self.renderer.with_request(request).generate_facturx_xml()
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
+fn load_renderer(config: &str) -> PDFInvoiceRenderer {
     let mut cfg = Ini::new();
-    cfg.load("/etc/shopsystem/config.ini").expect("failed to load config");
+    cfg.load(config).expect("failed to load config");
     let datapath = cfg.get("GENERAL", "datapath").unwrap_or("/usr/share/shopsystem/".to_string());
     let datapath = format!("{}/invoice", datapath);
 
     let longname = cfg.get("GENERAL", "longname").expect("config does not specify GENERAL longname");
     let vat = cfg.get("INVOICE", "vat").expect("config does not specify INVOICE vat");
+    let taxid = cfg.get("INVOICE", "taxid").unwrap_or_default();
+    let vat_exemption = cfg.get("INVOICE", "vat_exemption").unwrap_or_default();
     let addressrow = cfg.get("INVOICE", "addressrow").expect("config does not specify INVOICE addressrow");
     let footer1 = cfg.get("INVOICE", "footer1").expect("config does not specify INVOICE footer1").replace("\\n", "\n");
     let footer2 = cfg.get("INVOICE", "footer2").expect("config does not specify INVOICE footer2").replace("\\n", "\n");
     let footer3 = cfg.get("INVOICE", "footer3").expect("config does not specify INVOICE footer3").replace("\\n", "\n");
 
-    let renderer = PDFInvoiceRenderer {
+    PDFInvoiceRenderer {
         datapath: datapath,
         longname: longname,
         addressrow: addressrow,
@@ -798,12 +1561,35 @@ async fn main() -> Result<(), Box<dyn Error>> {
         footer2: footer2,
         footer3: footer3,
         vat: vat,
+        taxid: taxid,
+        vat_exemption: vat_exemption,
         previous_tm: None,
+        page: 0,
+        total_pages: 0,
         invoice_id: String::new(),
         invoice_date: 0,
         invoice_recipient: InvoiceRecipient::default(),
         invoice_entries: Vec::new(),
-    };
+        facturx: false,
+    }
+}
+
+fn render_json(renderer: &PDFInvoiceRenderer, json: &PathBuf, out: &PathBuf) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read_to_string(json)?;
+    let request: InvoiceRequest = serde_json::from_str(&data)?;
+    let pdf = renderer.with_request(request).generate()?;
+    std::fs::write(out, pdf)?;
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error>> {
+    let args = Cli::parse();
+    let renderer = load_renderer(&args.config);
+
+    if let Some(Command::Render { json, out }) = &args.command {
+        return render_json(&renderer, json, out);
+    }
 
     let pdf = PDFInvoice { renderer: renderer };
 
@@ -817,3 +1603,179 @@ async fn main() -> Result<(), Box<dyn Error>> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_renderer(vat: &str) -> PDFInvoiceRenderer {
+        let mut renderer = load_renderer(concat!(env!("CARGO_MANIFEST_DIR"), "/data/config/config.ini"));
+        renderer.datapath = concat!(env!("CARGO_MANIFEST_DIR"), "/data/invoice").to_string();
+        renderer.vat = vat.to_string();
+        renderer.vat_exemption = "Kleinunternehmer gemäß § 19 UStG".to_string();
+        renderer
+    }
+
+    fn sample_entry(timestamp: i64, ean: i64, name: &str, price: i32, vat: i32) -> InvoiceEntry {
+        InvoiceEntry {
+            timestamp: timestamp,
+            product: Product { ean: ean, name: name.to_string() },
+            price: price,
+            vat: vat,
+        }
+    }
+
+    fn sample_request() -> InvoiceRequest {
+        InvoiceRequest {
+            id: "202609001".to_string(),
+            date: 1790812800,
+            recipient: InvoiceRecipient {
+                firstname: "Erika".to_string(),
+                lastname: "Mustermann".to_string(),
+                street: "Heidestraße 17".to_string(),
+                postal_code: "51147".to_string(),
+                city: "Köln".to_string(),
+                gender: String::new(),
+            },
+            entries: vec![
+                sample_entry(1788220800, 4029764001807, "Club Mate", 150, 19),
+                sample_entry(1788220800, 0, "Pfand: Club Mate", 8, 19),
+                sample_entry(1788307200, 4029764001807, "Club Mate", 150, 19),
+                sample_entry(1788307200, 0, "Pfand: Club Mate", 8, 19),
+                sample_entry(1788393600, 40111445, "Snickers & Twix", 100, 7),
+                sample_entry(1788480000, 0, "Pfandrückgabe: Club Mate", -8, 19),
+            ],
+            facturx: true,
+        }
+    }
+
+    /* check that every opened element is closed in the right order */
+    fn assert_well_formed(xml: &str) {
+        let mut stack: Vec<String> = Vec::new();
+
+        for tag in xml.split('<').skip(1) {
+            let tag = tag.split('>').next().expect("unterminated tag");
+            if tag.starts_with('?') || tag.ends_with('/') {
+                continue;
+            }
+
+            let name = tag.trim_start_matches('/').split_whitespace().next().expect("empty tag").to_string();
+            if tag.starts_with('/') {
+                assert_eq!(stack.pop(), Some(name));
+            } else {
+                stack.push(name);
+            }
+        }
+
+        assert!(stack.is_empty(), "unclosed elements: {:?}", stack);
+    }
+
+    fn einvoice_error(result: Result<String, PDFError>) -> String {
+        match result {
+            Err(PDFError::EInvoiceError(msg)) => msg,
+            Err(err) => panic!("unexpected error: {:?}", err),
+            Ok(_) => panic!("invalid e-invoice has been accepted"),
+        }
+    }
+
+    #[test]
+    fn render_without_dbus() {
+        let pdf = PDFInvoice { renderer: sample_renderer("yes") };
+
+        let mut request = sample_request();
+        request.facturx = false;
+        let data = pdf.render(request).expect("rendering failed");
+        let doc = lopdf::Document::load_mem(&data).expect("invalid PDF");
+        assert!(doc.get_pages().len() >= 2);
+        assert!(doc.catalog().expect("catalog missing").get(b"AF").is_err());
+
+        /* the e-invoice variant carries the XML as associated file */
+        let data = pdf.render(sample_request()).expect("rendering failed");
+        let doc = lopdf::Document::load_mem(&data).expect("invalid PDF");
+        assert!(doc.catalog().expect("catalog missing").get(b"AF").is_ok());
+
+        /* requests without entries are rejected instead of rendering an empty invoice */
+        let mut request = sample_request();
+        request.entries.clear();
+        assert!(matches!(pdf.render(request), Err(PDFError::MissingData(_))));
+    }
+
+    #[test]
+    fn facturx_xml_with_vat() {
+        let renderer = sample_renderer("yes").with_request(sample_request());
+        let xml = renderer.generate_facturx_xml().expect("e-invoice validation failed");
+
+        assert_well_formed(&xml);
+        assert!(xml.contains("<ram:ID>202609001</ram:ID>"));
+        assert!(xml.contains("<ram:GlobalID schemeID=\"0160\">4029764001807</ram:GlobalID>"));
+        assert!(xml.contains("<ram:Name>Snickers &amp; Twix</ram:Name>"));
+        assert!(xml.contains("<ram:RateApplicablePercent>7</ram:RateApplicablePercent>"));
+        assert!(xml.contains("<ram:DuePayableAmount>4.08</ram:DuePayableAmount>"));
+        assert!(!xml.contains("ExemptionReason"));
+    }
+
+    #[test]
+    fn facturx_xml_without_vat() {
+        let renderer = sample_renderer("no").with_request(sample_request());
+        let xml = renderer.generate_facturx_xml().expect("e-invoice validation failed");
+
+        assert_well_formed(&xml);
+        assert!(xml.contains("<ram:CategoryCode>E</ram:CategoryCode>"));
+        assert!(xml.contains("<ram:ExemptionReason>Kleinunternehmer gemäß § 19 UStG</ram:ExemptionReason>"));
+        assert!(xml.contains("<ram:TaxTotalAmount currencyID=\"EUR\">0.00</ram:TaxTotalAmount>"));
+        assert!(xml.contains("<ram:DuePayableAmount>4.08</ram:DuePayableAmount>"));
+    }
+
+    #[test]
+    fn facturx_lines_with_credits() {
+        let mut request = sample_request();
+        request.entries = vec![
+            sample_entry(1788220800, 40111445, "Snickers & Twix", 95, 7),
+            sample_entry(1788220800, 40111445, "Snickers & Twix", 95, 7),
+            sample_entry(1788220800, 40111445, "Snickers & Twix", 95, 7),
+            sample_entry(1788307200, 0, "Pfandrückgabe: Club Mate", -15, 19),
+            sample_entry(1788307200, 0, "Pfandrückgabe: Club Mate", -15, 19),
+            sample_entry(1788307200, 0, "Pfandrückgabe: Club Mate", -15, 19),
+            sample_entry(1788393600, 0, "Pfandrückgabe: Flasche", -8, 19),
+        ];
+        let renderer = sample_renderer("yes").with_request(request);
+        let (lines, taxes) = renderer.get_einvoice_data();
+
+        for l in &lines {
+            assert!(l.unit_price >= 0);
+            assert_eq!(l.net as i64, div_round(l.unit_price * l.quantity as i64, 100), "{}", l.name);
+        }
+        assert!(lines.iter().any(|l| l.quantity < 0));
+
+        let credit = taxes.iter().find(|t| t.rate == 19).expect("19% missing");
+        assert!(credit.basis < 0 && credit.tax < 0);
+
+        let total: i32 = taxes.iter().map(|t| t.basis + t.tax).sum();
+        assert_eq!(total, renderer.get_sum());
+        renderer.validate_einvoice(&lines, &taxes).expect("e-invoice validation failed");
+        assert_well_formed(&renderer.generate_facturx_xml().expect("e-invoice validation failed"));
+    }
+
+    #[test]
+    fn facturx_xml_rejects_invalid_invoices() {
+        let mut renderer = sample_renderer("no");
+        renderer.vat_exemption = String::new();
+        let msg = einvoice_error(renderer.with_request(sample_request()).generate_facturx_xml());
+        assert!(msg.starts_with("BR-E-10"), "{}", msg);
+
+        let mut renderer = sample_renderer("yes");
+        renderer.taxid = String::new();
+        let msg = einvoice_error(renderer.with_request(sample_request()).generate_facturx_xml());
+        assert!(msg.starts_with("BR-S-02"), "{}", msg);
+
+        let mut renderer = sample_renderer("yes");
+        renderer.addressrow = "Kreativität trifft Technik e.V.".to_string();
+        let msg = einvoice_error(renderer.with_request(sample_request()).generate_facturx_xml());
+        assert!(msg.starts_with("BR-08"), "{}", msg);
+
+        let mut request = sample_request();
+        request.entries.clear();
+        let msg = einvoice_error(sample_renderer("yes").with_request(request).generate_facturx_xml());
+        assert!(msg.starts_with("BR-16"), "{}", msg);
+    }
+}
@@ -21,7 +21,9 @@ use rocket::response::status::Forbidden;
 use serde::{Deserialize, Serialize};
 use rocket::form::Form;
 use rocket::data::Capped;
-use rocket::http::{Cookie, CookieJar};
+use rocket::http::{Cookie, CookieJar, SameSite, Status};
+use rocket::request::{self, FromRequest, Request};
+use rocket::State;
 use std::{collections::HashMap, hash::BuildHasher};
 use zbus;
 use zbus::{Connection, proxy, zvariant::Type};
@@ -29,10 +31,11 @@ use rand::{RngExt, distr::Alphanumeric};
 use chrono;
 use chrono::prelude::*;
 use std::num::ParseIntError;
-use rocket::data::{Data, ToByteUnit};
 use rocket::response::Responder;
 use configparser::ini::Ini;
 use barcoders::sym::code39::*;
+use barcoders::sym::ean13::EAN13;
+use barcoders::sym::ean8::EAN8;
 use barcoders::generators::svg::*;
 use rocket::http::ContentType;
 use pangocairo::glib::Bytes;
@@ -48,6 +51,7 @@ enum WebShopError {
     RSVGRenderingError(rsvg::RenderingError),
     UTF8Error(std::string::FromUtf8Error),
     UnboxError(std::string::String),
+    CSVError(csv::Error),
 }
 
 impl From<zbus::Error> for WebShopError {
@@ -56,6 +60,12 @@ impl From<zbus::Error> for WebShopError {
     }
 }
 
+impl From<csv::Error> for WebShopError {
+    fn from(err: csv::Error) -> WebShopError {
+            WebShopError::CSVError(err)
+    }
+}
+
 impl From<std::io::Error> for WebShopError {
     fn from(err: std::io::Error) -> WebShopError {
             WebShopError::IOError(err)
@@ -111,6 +121,7 @@ impl<'r> Responder<'r, 'r> for WebShopError {
             WebShopError::RSVGRenderingError(e) => Template::render("error", context! { page: "error", errmsg: e.to_string() }).respond_to(req),
             WebShopError::UTF8Error(e) => Template::render("error", context! { page: "error", errmsg: e.to_string() }).respond_to(req),
             WebShopError::UnboxError(e) => Template::render("error", context! { page: "error", errmsg: e.to_string() }).respond_to(req),
+            WebShopError::CSVError(e) => Template::render("error", context! { page: "error", errmsg: e.to_string() }).respond_to(req),
         }
     }
 }
@@ -165,6 +176,7 @@ struct UserChange {
 #[derive(FromForm)]
 struct FileUpload<'r> {
     file: Capped<TempFile<'r>>,
+    csrf_token: String,
 }
 
 #[derive(Serialize)]
@@ -172,6 +184,206 @@ struct UserInfoList {
     data: Vec<UserInfo>,
 }
 
+const PRODUCT_CSV_HEADER: [&str; 18] = [
+    "ean", "name", "category", "memberprice", "guestprice", "deprecated",
+    "product_size", "product_size_is_weight", "container_size", "calories", "carbohydrates",
+    "fats", "proteins", "deposit", "container_deposit", "allergens", "ingredients", "aliases",
+];
+
+/* exports created before allergens and ingredients were added lack these two columns */
+const PRODUCT_CSV_LEGACY_COLUMNS: usize = 16;
+
+/* one line of the product CSV, prices are given in cent and aliases are separated by spaces */
+#[derive(Clone, PartialEq, Deserialize, Serialize)]
+struct ProductCsvEntry {
+    ean: i64,
+    name: String,
+    category: String,
+    memberprice: i32,
+    guestprice: i32,
+    deprecated: bool,
+    metadata: Option<ProductMetadata>,
+    aliases: Vec<i64>,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ProductChange {
+    old: Option<ProductCsvEntry>,
+    new: ProductCsvEntry,
+}
+
+fn csv_field<T: std::str::FromStr>(record: &csv::StringRecord, i: usize) -> Result<T, String> {
+    let value = record[i].trim();
+    value.parse::<T>().map_err(|_| format!("invalid {} '{}'", PRODUCT_CSV_HEADER[i], value))
+}
+
+fn csv_flag(record: &csv::StringRecord, i: usize) -> Result<bool, String> {
+    match record[i].trim() {
+        "1" | "true" | "yes" => Ok(true),
+        "" | "0" | "false" | "no" => Ok(false),
+        value => Err(format!("invalid {} '{}'", PRODUCT_CSV_HEADER[i], value)),
+    }
+}
+
+impl ProductCsvEntry {
+    fn from_record(record: &csv::StringRecord) -> Result<Self, String> {
+        if record.len() == PRODUCT_CSV_LEGACY_COLUMNS {
+            let mut fields: Vec<&str> = record.iter().collect();
+            fields.splice(15..15, ["", ""]);
+            return Self::from_record(&csv::StringRecord::from(fields));
+        }
+
+        if record.len() != PRODUCT_CSV_HEADER.len() {
+            return Err(format!("expected {} columns (or {} columns of old exports), found {}", PRODUCT_CSV_HEADER.len(), PRODUCT_CSV_LEGACY_COLUMNS, record.len()));
+        }
+
+        /* empty metadata columns mean that no metadata is known */
+        let metadata = if (6..17).all(|i| record[i].trim().is_empty()) {
+            None
+        } else {
+            let number = |i: usize| if record[i].trim().is_empty() { Ok(0) } else { csv_field::<u32>(record, i) };
+            Some(ProductMetadata {
+                product_size: number(6)?,
+                product_size_is_weight: csv_flag(record, 7)?,
+                container_size: number(8)?,
+                calories: number(9)?,
+                carbohydrates: number(10)?,
+                fats: number(11)?,
+                proteins: number(12)?,
+                deposit: number(13)?,
+                container_deposit: number(14)?,
+                allergens: record[15].trim().to_string(),
+                ingredients: record[16].trim().to_string(),
+            })
+        };
+
+        let mut aliases = Vec::new();
+        for alias in record[17].split_whitespace() {
+            aliases.push(alias.parse::<i64>().map_err(|_| format!("invalid alias '{}'", alias))?);
+        }
+        aliases.sort();
+        aliases.dedup();
+
+        let entry = ProductCsvEntry {
+            ean: csv_field(record, 0)?,
+            name: record[1].trim().to_string(),
+            category: record[2].trim().to_string(),
+            memberprice: csv_field(record, 3)?,
+            guestprice: csv_field(record, 4)?,
+            deprecated: csv_flag(record, 5)?,
+            metadata: metadata,
+            aliases: aliases,
+        };
+        entry.validate()?;
+        Ok(entry)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        for ean in std::iter::once(&self.ean).chain(self.aliases.iter()) {
+            if !check_valid_gtin(*ean, 8) && !check_valid_gtin(*ean, 13) {
+                return Err(format!("invalid EAN {}", ean));
+            }
+        }
+        if self.name.is_empty() {
+            return Err(format!("missing name for {}", self.ean));
+        }
+        if self.category.is_empty() {
+            return Err(format!("missing category for {}", self.ean));
+        }
+        if self.memberprice < 0 || self.guestprice < 0 {
+            return Err(format!("negative price for {}", self.ean));
+        }
+        Ok(())
+    }
+
+    fn to_record(&self) -> Vec<String> {
+        let mut record = vec![
+            self.ean.to_string(),
+            self.name.clone(),
+            self.category.clone(),
+            self.memberprice.to_string(),
+            self.guestprice.to_string(),
+            (self.deprecated as u8).to_string(),
+        ];
+
+        match &self.metadata {
+            Some(m) => record.extend([
+                m.product_size.to_string(),
+                (m.product_size_is_weight as u8).to_string(),
+                m.container_size.to_string(),
+                m.calories.to_string(),
+                m.carbohydrates.to_string(),
+                m.fats.to_string(),
+                m.proteins.to_string(),
+                m.deposit.to_string(),
+                m.container_deposit.to_string(),
+                m.allergens.clone(),
+                m.ingredients.clone(),
+            ]),
+            None => record.extend(std::iter::repeat(String::new()).take(11)),
+        };
+
+        record.push(self.aliases.iter().map(|a| a.to_string()).collect::<Vec<_>>().join(" "));
+        record
+    }
+}
+
+const PROFIT_CSV_HEADER: [&str; 8] = [
+    "category", "ean", "product", "units", "revenue", "cost", "margin", "margin_percent",
+];
+
+/* aggregated profit of a product, a category or the whole period, amounts in cent */
+#[derive(Serialize)]
+struct ProfitReportLine {
+    ean: i64,
+    name: String,
+    category: String,
+    units: u32,
+    revenue: i64,
+    cost: i64,
+    margin: i64,
+    margin_percent: f64,
+}
+
+impl ProfitReportLine {
+    fn new(ean: i64, name: &str, category: &str) -> Self {
+        ProfitReportLine {
+            ean: ean,
+            name: name.to_string(),
+            category: category.to_string(),
+            units: 0,
+            revenue: 0,
+            cost: 0,
+            margin: 0,
+            margin_percent: 0.0,
+        }
+    }
+
+    fn add(&mut self, units: u32, revenue: i64, cost: i64) {
+        self.units += units;
+        self.revenue += revenue;
+        self.cost += cost;
+        self.margin = self.revenue - self.cost;
+        self.margin_percent = match self.revenue {
+            0 => 0.0,
+            revenue => self.margin as f64 * 100.0 / revenue as f64,
+        };
+    }
+
+    fn to_record(&self) -> Vec<String> {
+        vec![
+            self.category.clone(),
+            if self.ean != 0 { self.ean.to_string() } else { String::new() },
+            self.name.clone(),
+            self.units.to_string(),
+            format_cent(self.revenue),
+            format_cent(self.cost),
+            format_cent(self.margin),
+            format!("{:.1}", self.margin_percent),
+        ]
+    }
+}
+
 #[derive(Serialize)]
 struct DropdownEntry {
     id: usize,
@@ -244,7 +456,7 @@ struct SystemUser {
     name: String,
 }
 
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 struct Session {
     uid: i32,
     name: String,
@@ -252,6 +464,346 @@ struct Session {
     auth_cashbox: bool,
     auth_products: bool,
     auth_users: bool,
+    csrf_token: String,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+struct SessionInfo {
+    id: i64,
+    created: i64,
+    last_seen: i64,
+    expires: i64,
+    user_agent: String,
+    current: bool,
+}
+
+struct WebConfig {
+    secure_cookies: bool,
+    session_lifetime: i64,
+    /* downloaded invoices must match the mailed ones */
+    facturx: bool,
+}
+
+struct UserAgent(String);
+
+/* request guard for state changing routes, the token is expected in the X-CSRF-Token header */
+struct CsrfChecked;
+
+/* plain HTML forms can not set headers, they send the token in a hidden csrf_token field */
+#[derive(FromForm)]
+struct CsrfToken {
+    csrf_token: String,
+}
+
+/* constant time comparison, so that the token can not be guessed byte by byte */
+fn csrf_token_matches(expected: &str, token: &str) -> bool {
+    let (expected, token) = (expected.as_bytes(), token.as_bytes());
+    if expected.is_empty() || expected.len() != token.len() {
+        return false;
+    }
+    expected.iter().zip(token).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+fn csrf_form_check(session: &Session, token: &str) -> Result<(), WebShopError> {
+    match csrf_token_matches(&session.csrf_token, token) {
+        true => Ok(()),
+        false => Err(WebShopError::PermissionDenied()),
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for CsrfChecked {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let sessionid = request.cookies().get("sessionid").map(|c| c.value().to_string());
+        let token = request.headers().get_one("X-CSRF-Token").map(|t| t.to_string());
+
+        let (sessionid, token) = match (sessionid, token) {
+            (Some(sessionid), Some(token)) => (sessionid, token),
+            _ => { return request::Outcome::Error((Status::Forbidden, "Missing CSRF token".to_string())); },
+        };
+
+        match get_session_csrf_token(&sessionid).await {
+            Ok(expected) if csrf_token_matches(&expected, &token) => request::Outcome::Success(CsrfChecked),
+            _ => request::Outcome::Error((Status::Forbidden, "Invalid CSRF token".to_string())),
+        }
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for UserAgent {
+    type Error = std::convert::Infallible;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let user_agent = request.headers().get_one("User-Agent").unwrap_or("");
+        request::Outcome::Success(UserAgent(user_agent.to_string()))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Permission {
+    Login,
+    Products,
+    Cashbox,
+    Users,
+    Superuser,
+}
+
+impl Permission {
+    fn name(&self) -> &'static str {
+        match self {
+            Permission::Login => "login",
+            Permission::Products => "products",
+            Permission::Cashbox => "cashbox",
+            Permission::Users => "users",
+            Permission::Superuser => "superuser",
+        }
+    }
+
+    /* API token scopes map onto the auth_* permissions */
+    fn from_scope(scope: &str) -> Option<Permission> {
+        match scope {
+            "products" => Some(Permission::Products),
+            "cashbox" => Some(Permission::Cashbox),
+            "users" => Some(Permission::Users),
+            _ => None,
+        }
+    }
+
+    fn granted(&self, session: &Session) -> bool {
+        match self {
+            Permission::Login => session.uid != 0,
+            Permission::Products => session.auth_products,
+            Permission::Cashbox => session.auth_cashbox,
+            Permission::Users => session.auth_users,
+            Permission::Superuser => session.superuser,
+        }
+    }
+}
+
+impl Session {
+    /* users may always access their own data, user admins may access everything */
+    fn may_access_user(&self, uid: i32) -> bool {
+        self.uid == uid || self.auth_users
+    }
+}
+
+/* permission missing for the current request, used by the 403 catcher */
+struct MissingPermission(Option<Permission>);
+
+/* looks up the sessions of session cookies and API tokens, managed as state so that tests can replace it */
+#[rocket::async_trait]
+trait SessionLookup: Send + Sync {
+    async fn session(&self, sessionid: &str) -> zbus::Result<Session>;
+    async fn api_session(&self, token: &str) -> zbus::Result<Session>;
+}
+
+struct DBusSessionLookup;
+
+#[rocket::async_trait]
+impl SessionLookup for DBusSessionLookup {
+    async fn session(&self, sessionid: &str) -> zbus::Result<Session> {
+        get_session_with_sessionid(sessionid).await
+    }
+
+    async fn api_session(&self, token: &str) -> zbus::Result<Session> {
+        get_api_session(token).await
+    }
+}
+
+fn session_lookup<'r>(request: &'r Request<'_>) -> &'r dyn SessionLookup {
+    request.rocket().state::<Box<dyn SessionLookup>>().expect("session lookup is managed").as_ref()
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for Session {
+    type Error = String;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        let session = request.local_cache_async(async {
+            let sessionid = request.cookies().get("sessionid").map(|c| c.value().to_string()).unwrap_or("invalid".to_string());
+            session_lookup(request).session(&sessionid).await.map_err(|e| e.to_string())
+        }).await;
+
+        match session {
+            Ok(session) => request::Outcome::Success(session.clone()),
+            Err(error) => request::Outcome::Error((Status::InternalServerError, error.clone())),
+        }
+    }
+}
+
+async fn require_permission(request: &Request<'_>, permission: Permission) -> request::Outcome<Session, Permission> {
+    let session = match request.guard::<Session>().await {
+        request::Outcome::Success(session) => session,
+        _ => {
+            request.local_cache(|| MissingPermission(Some(permission)));
+            return request::Outcome::Error((Status::Forbidden, permission));
+        },
+    };
+
+    if !permission.granted(&session) {
+        request.local_cache(|| MissingPermission(Some(permission)));
+        return request::Outcome::Error((Status::Forbidden, permission));
+    }
+
+    request::Outcome::Success(session)
+}
+
+/* request guards for the route permissions, they wrap the session of the logged in user */
+struct RequireLogin(Session);
+struct RequireProducts(Session);
+struct RequireCashbox(Session);
+struct RequireUsers(Session);
+struct RequireSuperuser(Session);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequireLogin {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_permission(request, Permission::Login).await.map(RequireLogin)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequireProducts {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_permission(request, Permission::Products).await.map(RequireProducts)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequireCashbox {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_permission(request, Permission::Cashbox).await.map(RequireCashbox)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequireUsers {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_permission(request, Permission::Users).await.map(RequireUsers)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequireSuperuser {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_permission(request, Permission::Superuser).await.map(RequireSuperuser)
+    }
+}
+
+/* session of an API client, None if the Authorization header is missing or the token is invalid */
+struct ApiSession(Option<Session>);
+
+async fn require_api_permission(request: &Request<'_>, permission: Permission) -> request::Outcome<Session, Permission> {
+    let session = request.local_cache_async(async {
+        let token = request.headers().get_one("Authorization").and_then(|h| h.strip_prefix("Bearer "));
+        match token {
+            Some(token) => ApiSession(session_lookup(request).api_session(token.trim()).await.ok()),
+            None => ApiSession(None),
+        }
+    }).await;
+
+    let session = match &session.0 {
+        Some(session) => session.clone(),
+        None => { return request::Outcome::Error((Status::Unauthorized, permission)); },
+    };
+
+    if !permission.granted(&session) {
+        request.local_cache(|| MissingPermission(Some(permission)));
+        return request::Outcome::Error((Status::Forbidden, permission));
+    }
+
+    request::Outcome::Success(session)
+}
+
+/* request guards for the /api/v1 routes, they authenticate with "Authorization: Bearer <token>" */
+struct ApiLogin(Session);
+struct ApiProducts(Session);
+struct ApiCashbox(Session);
+struct ApiUsers(Session);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiLogin {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_api_permission(request, Permission::Login).await.map(ApiLogin)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiProducts {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_api_permission(request, Permission::Products).await.map(ApiProducts)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiCashbox {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_api_permission(request, Permission::Cashbox).await.map(ApiCashbox)
+    }
+}
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ApiUsers {
+    type Error = Permission;
+
+    async fn from_request(request: &'r Request<'_>) -> request::Outcome<Self, Self::Error> {
+        require_api_permission(request, Permission::Users).await.map(ApiUsers)
+    }
+}
+
+/* error of the /api/v1 routes, rendered as JSON body */
+struct ApiError {
+    status: Status,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct ApiErrorBody {
+    status: u16,
+    error: String,
+}
+
+impl ApiError {
+    fn new(status: Status, message: &str) -> ApiError {
+        ApiError { status: status, message: message.to_string() }
+    }
+}
+
+impl From<zbus::Error> for ApiError {
+    fn from(err: zbus::Error) -> ApiError {
+        let status = match &err {
+            zbus::Error::MethodError(name, _, _) if name.as_str() == "io.mainframe.shopsystem.Database.InvalidArgument" => Status::BadRequest,
+            /* unknown product, user, etc. */
+            zbus::Error::MethodError(_, Some(message), _) if message == "Query returned no rows" => Status::NotFound,
+            _ => Status::InternalServerError,
+        };
+        ApiError { status: status, message: err.to_string() }
+    }
+}
+
+impl<'r> Responder<'r, 'static> for ApiError {
+    fn respond_to(self, req: &'r Request<'_>) -> rocket::response::Result<'static> {
+        let body = Json(ApiErrorBody { status: self.status.code, error: self.message });
+        rocket::Response::build_from(body.respond_to(req)?).status(self.status).ok()
+    }
 }
 
 #[derive(Type, Deserialize, Serialize)]
@@ -302,6 +854,39 @@ pub struct PriceInfo {
     guestprice: i32,
 }
 
+#[derive(Type, Deserialize, Serialize)]
+pub struct PriceChange {
+    product: Product,
+    valid_from: i64,
+    memberprice: i32,
+    guestprice: i32,
+    previous_memberprice: i32,
+    previous_guestprice: i32,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct ProfitReportEntry {
+    product: Product,
+    category: String,
+    units: u32,
+    revenue: i64,
+    cost: i64,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct StockValueEntry {
+    product: Product,
+    category: String,
+    amount: i32,
+    value: i64,
+}
+
+#[derive(Clone, Copy, Deserialize, Serialize)]
+pub struct CategoryPriceChange {
+    valid_from: i64,
+    percent: i32,
+}
+
 #[derive(Type, Clone, Copy, Deserialize, Serialize)]
 pub enum CashboxUpdateType {
     Loss,
@@ -310,13 +895,36 @@ pub enum CashboxUpdateType {
     Deposit,
 }
 
+impl CashboxUpdateType {
+    fn entry_type(&self) -> &'static str {
+        match self {
+            CashboxUpdateType::Loss => "loss",
+            CashboxUpdateType::Withdrawal => "withdrawal",
+            CashboxUpdateType::Donation => "donation",
+            CashboxUpdateType::Deposit => "deposit",
+        }
+    }
+}
+
+fn cashbox_entry_type_name(entry_type: &str) -> &'static str {
+    match entry_type {
+        "withdrawal" => "Withdrawal",
+        "deposit" => "Deposit",
+        "loss" => "Loss",
+        "donation" => "Donation",
+        "count" => "Cash Count",
+        "guest_sale" => "Guest Sales",
+        _ => "Unknown",
+    }
+}
+
 #[derive(Type, Clone, Copy, Deserialize, Serialize)]
 pub struct CashboxUpdate {
     update_type: CashboxUpdateType,
     amount: i32,
 }
 
-#[derive(Type, Deserialize, Serialize)]
+#[derive(Type, Clone, PartialEq, Deserialize, Serialize)]
 pub struct ProductMetadata {
     product_size: u32,
     product_size_is_weight: bool,
@@ -327,6 +935,8 @@ pub struct ProductMetadata {
     proteins: u32,
     deposit: u32,
     container_deposit: u32,
+    allergens: String,
+    ingredients: String,
 }
 
 impl Default for ProductMetadata {
@@ -341,6 +951,8 @@ impl Default for ProductMetadata {
             proteins: 0,
             deposit: 0,
             container_deposit: 0,
+            allergens: String::new(),
+            ingredients: String::new(),
         }
     }
 }
@@ -354,6 +966,15 @@ pub struct RestockEntry {
 	best_before_date: i64,
 }
 
+#[derive(Type, Deserialize, Serialize)]
+pub struct StockLot {
+    timestamp: i64,
+    amount: u32,
+    remaining: u32,
+    price: i64,
+    best_before_date: i64,
+}
+
 #[derive(Type, Deserialize, Serialize)]
 pub struct RestockEntryNamedSupplier {
 	timestamp: i64,
@@ -380,6 +1001,70 @@ pub struct Supplier {
 	street: String,
 	phone: String,
 	website: String,
+	contact_person: String,
+	email: String,
+	customer_number: String,
+	minimum_order: u32,
+	archived: bool,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct SupplierCatalogueEntry {
+	supplier: i32,
+	product: Product,
+	article_number: String,
+	price: u32,
+	updated: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SupplierCatalogueUpdate {
+	ean: i64,
+	article_number: String,
+	price: u32,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct SupplierDeposit {
+	id: i64,
+	supplier: i32,
+	user: i32,
+	product: i64,
+	timestamp: i64,
+	amount: i32,
+	comment: String,
+}
+
+#[derive(Serialize)]
+pub struct NamedSupplierDeposit {
+	deposit: SupplierDeposit,
+	supplier_name: String,
+	product_name: String,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct DepositBalance {
+	supplier: i32,
+	name: String,
+	paid: i32,
+	refunded: i32,
+	balance: i32,
+}
+
+#[derive(Serialize)]
+pub struct DepositOverview {
+	/* deposit paid to suppliers, which has not been refunded yet */
+	supplier_deposit: i32,
+	/* deposit paid by customers for bottles, which have not been returned yet */
+	customer_deposit: i32,
+	suppliers: Vec<DepositBalance>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct SupplierDepositReturn {
+	supplier: i32,
+	amount: u32,
+	comment: String,
 }
 
 #[derive(Type, Deserialize, Serialize)]
@@ -402,6 +1087,7 @@ pub struct NewProduct {
 	category: i32,
     memberprice: String,
     guestprice: String,
+    csrf_token: String,
 }
 
 #[derive(FromForm, Type, Deserialize, Serialize)]
@@ -412,6 +1098,7 @@ pub struct NewSupplier {
     street: String,
     phone: String,
     website: String,
+    csrf_token: String,
 }
 
 #[derive(Type, Clone, Deserialize, Serialize)]
@@ -441,7 +1128,7 @@ pub struct UserAuth {
     auth_users: bool,
 }
 
-#[derive(Type, Deserialize, Serialize)]
+#[derive(Type, zbus::zvariant::Value, Deserialize, Serialize)]
 pub struct Product {
 	ean: i64,
 	name: String,
@@ -454,15 +1141,114 @@ struct UserBasicInfo {
 	lastname: String,
 }
 
-#[derive(Type, Deserialize, Serialize)]
+#[derive(Type, zbus::zvariant::Value, Deserialize, Serialize)]
 pub struct InvoiceEntry {
 	timestamp: i64,
 	product: Product,
 	price: i32,
+	vat: i32,
 }
 
 #[derive(Type, Deserialize, Serialize)]
-pub struct SalesEntry {
+pub struct DisputeInfo {
+	id: i64,
+	user: i32,
+	product: Product,
+	timestamp: i64,
+	comment: String,
+	created: i64,
+	state: String,
+	resolved_by: i32,
+	resolved_at: i64,
+	reassigned_to: i32,
+	resolution: String,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct ApiTokenInfo {
+	id: i64,
+	user: i32,
+	name: String,
+	scopes: Vec<String>,
+	created: i64,
+	last_used: i64,
+}
+
+#[derive(Deserialize)]
+struct NewApiToken {
+	name: String,
+	scopes: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct NewApiTokenResponse {
+	id: i64,
+	token: String,
+}
+
+#[derive(Serialize)]
+struct ApiCashbox {
+	status: i32,
+	history: Vec<NamedCashboxEntry>,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct AuditEntry {
+	id: i64,
+	timestamp: i64,
+	actor: i32,
+	action: String,
+	entity: String,
+	entity_id: i64,
+	before: String,
+	after: String,
+}
+
+#[derive(Deserialize)]
+struct DisputeResolution {
+	action: String,
+	reassign_to: i32,
+	resolution: String,
+}
+
+#[derive(Deserialize)]
+struct PurchaseReport {
+	timestamp: i64,
+	product: i64,
+	comment: String,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct InvoiceInfo {
+	id: String,
+	user: i32,
+	timestamp: i64,
+	period_from: i64,
+	period_to: i64,
+	amount: i32,
+}
+
+#[derive(Deserialize, Serialize, Type, zbus::zvariant::Value, Clone, Default)]
+pub struct InvoiceRecipient {
+	firstname: String,
+	lastname: String,
+	street: String,
+	postal_code: String,
+	city: String,
+	gender: String,
+}
+
+#[derive(Deserialize, Serialize, Type)]
+pub struct InvoiceRequest {
+	id: String,
+	date: i64,
+	recipient: InvoiceRecipient,
+	entries: Vec<InvoiceEntry>,
+	facturx: bool,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct SalesEntry {
 	timestamp: i64,
     user: UserBasicInfo,
 	product: Product,
@@ -475,23 +1261,153 @@ pub struct UserSaleStatsEntry {
 }
 
 #[derive(Type, Deserialize, Serialize)]
-pub struct CashboxDiff {
+pub struct CashboxLedgerEntry {
+	id: i64,
+	timestamp: i64,
+	entry_type: String,
 	user: i32,
 	amount: i32,
-	timestamp: i64,
+	balance: i64,
 }
 
 #[derive(Deserialize, Serialize)]
-pub struct NamedCashboxDiff {
+pub struct NamedCashboxEntry {
+	id: i64,
+	timestamp: i64,
+	entry_type: String,
+	type_name: String,
+	user: i32,
     username: String,
 	amount: i32,
+	balance: i64,
+}
+
+#[derive(Serialize)]
+pub struct CashboxLedgerPage {
+	total: u32,
+	entries: Vec<NamedCashboxEntry>,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct CashboxCount {
+	id: i64,
+	user: i32,
+	timestamp: i64,
+	expected: i32,
+	counted: i32,
+	correction: i64,
+	comment: String,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct NamedCashboxCount {
+	id: i64,
+	user: i32,
+	username: String,
 	timestamp: i64,
+	expected: i32,
+	counted: i32,
+	difference: i32,
+	correction: i64,
+	comment: String,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct OrderSuggestion {
+	product: Product,
+	stock: i32,
+	sold: u32,
+	selling_days: u32,
+	velocity: f64,
+	container_size: u32,
+	suggestion: u32,
+}
+
+#[derive(Serialize)]
+pub struct OrderSuggestionList {
+	supplier: i32,
+	coverage: u32,
+	history: u32,
+	products: Vec<OrderSuggestion>,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct PurchaseOrder {
+	id: i64,
+	supplier: i32,
+	user: i32,
+	created: i64,
+	ordered: i64,
+	received: i64,
+	status: String,
+	comment: String,
+	items: u32,
+	total: i64,
+	discrepancies: u32,
+}
+
+#[derive(Serialize)]
+pub struct NamedPurchaseOrder {
+	order: PurchaseOrder,
+	supplier_name: String,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct PurchaseOrderItem {
+	product: Product,
+	amount: u32,
+	price: u32,
+	received: i32,
+	best_before_date: i64,
+	article_number: String,
+}
+
+#[derive(Type, Deserialize, Serialize)]
+pub struct PurchaseOrderDelivery {
+	product: i64,
+	amount: u32,
+	price: u32,
+	best_before_date: i64,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PurchaseOrderRequestItem {
+	ean: i64,
+	amount: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PurchaseOrderRequest {
+	supplier: i32,
+	items: Vec<PurchaseOrderRequestItem>,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PurchaseOrderItemUpdate {
+	ean: i64,
+	amount: u32,
+	price: u32,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PurchaseOrderUpdate {
+	status: Option<String>,
+	comment: Option<String>,
+}
+
+const PURCHASE_ORDER_CSV_HEADER: [&str; 6] = ["article_number", "ean", "name", "amount", "price", "total"];
+
+#[derive(Deserialize, Serialize)]
+pub struct CashboxCountRequest {
+	counted: i32,
+	comment: String,
 }
 
 #[derive(Type, Deserialize, Serialize)]
 pub struct ProductCategory {
     id: i32,
     name: String,
+    vat: i32,
 }
 
 #[derive(Type, Deserialize, Serialize)]
@@ -509,14 +1425,25 @@ pub struct ProductDetails {
     default_path = "/io/mainframe/shopsystem/database"
 )]
 trait ShopDB {
-    async fn set_sessionid(&self, userid: i32, sessionid: &str) -> zbus::Result<()>;
+    async fn session_create(&self, userid: i32, sessionid: &str, csrf_token: &str, user_agent: &str, lifetime: i64) -> zbus::Result<()>;
+    async fn get_session_csrf_token(&self, sessionid: &str) -> zbus::Result<String>;
+    async fn session_delete(&self, sessionid: &str) -> zbus::Result<()>;
+    async fn session_revoke(&self, actor: i32, userid: i32, session: i64) -> zbus::Result<()>;
+    async fn get_user_sessions(&self, userid: i32, current_sessionid: &str) -> zbus::Result<Vec<SessionInfo>>;
+    async fn api_token_create(&self, actor: i32, userid: i32, name: &str, token: &str, scopes: Vec<String>) -> zbus::Result<i64>;
+    async fn api_token_revoke(&self, actor: i32, userid: i32, token: i64) -> zbus::Result<()>;
+    async fn get_api_token(&self, token: &str) -> zbus::Result<ApiTokenInfo>;
+    async fn get_user_api_tokens(&self, userid: i32) -> zbus::Result<Vec<ApiTokenInfo>>;
     async fn get_user_by_sessionid(&self, sessionid: &str) -> zbus::Result<i32>;
     async fn get_stock(&self) -> zbus::Result<Vec<StockItem>>;
     async fn get_productlist(&self) -> zbus::Result<Vec<DetailedProductInfo>>;
-    async fn restock(&self, user: i32, product: i64, amount: u32, price: u32, supplier: i32, best_before_date: i64) -> zbus::Result<()>;
+    async fn restock(&self, actor: i32, user: i32, product: i64, amount: u32, price: u32, supplier: i32, best_before_date: i64) -> zbus::Result<()>;
     async fn buy(&self, user: i32, product: i64) -> zbus::Result<()>;
-    async fn new_price(&self, product: i64, timestamp: i64, memberprice: i32, guestprice: i32) ->  zbus::Result<()>;
+    async fn new_price(&self, actor: i32, product: i64, timestamp: i64, memberprice: i32, guestprice: i32) ->  zbus::Result<()>;
     async fn get_prices(&self, ean: i64) -> zbus::Result<Vec<PriceInfo>>;
+    async fn price_cancel(&self, actor: i32, product: i64, valid_from: i64) -> zbus::Result<()>;
+    async fn category_price_change(&self, actor: i32, category: i32, valid_from: i64, percent: i32) -> zbus::Result<u32>;
+    async fn get_price_changes(&self, from: i64, to: i64) -> zbus::Result<Vec<PriceChange>>;
     async fn get_product_aliases(&self, ean: i64) -> zbus::Result<Vec<i64>>;
     async fn get_product_name(&self, ean: i64) -> zbus::Result<String>;
     async fn get_product_amount(&self, ean: i64) -> zbus::Result<i32>;
@@ -524,47 +1451,82 @@ trait ShopDB {
 	async fn get_product_sales_info(&self, ean: i64, since: i64) -> zbus::Result<u32>;
     async fn get_product_category(&self, ean: i64) -> zbus::Result<String>;
     async fn get_product_deprecated(&self, ean: i64) -> zbus::Result<bool>;
-    async fn product_deprecate(&self, ean: i64, deprecated: bool) -> zbus::Result<()>;
+    async fn product_deprecate(&self, actor: i32, ean: i64, deprecated: bool) -> zbus::Result<()>;
     async fn product_metadata_get(&self, ean: i64) -> zbus::Result<ProductMetadata>;
-    async fn product_metadata_set(&self, ean: i64, metadata: ProductMetadata) -> zbus::Result<()>;
+    async fn product_metadata_set(&self, actor: i32, ean: i64, metadata: ProductMetadata) -> zbus::Result<()>;
     async fn products_search(&self, search_query: &str) -> zbus::Result<Vec<Product>>;
     async fn get_restocks(&self, ean: i64, descending: bool) -> zbus::Result<Vec<RestockEntry>>;
     async fn get_last_restock(&self, ean: i64, min_price: u32) -> zbus::Result<RestockEntry>;
     async fn bestbeforelist(&self) -> zbus::Result<Vec<BestBeforeEntry>>;
-    async fn get_supplier_list(&self) -> zbus::Result<Vec<Supplier>>;
+    async fn get_supplier_list(&self, include_archived: bool) -> zbus::Result<Vec<Supplier>>;
     async fn get_supplier_product_list(&self, id: i32) -> zbus::Result<Vec<ProductInfo>>;
     async fn get_supplier_restock_dates(&self, id: i32) -> zbus::Result<Vec<i64>>;
-    async fn add_supplier(&self, name: &str, postal_code: &str, city: &str, street: &str, phone: &str, website: &str) -> zbus::Result<()>;
+    async fn add_supplier(&self, actor: i32, name: &str, postal_code: &str, city: &str, street: &str, phone: &str, website: &str) -> zbus::Result<()>;
     async fn get_supplier(&self, id: i32) -> zbus::Result<Supplier>;
+    async fn supplier_update(&self, actor: i32, supplier: &Supplier) -> zbus::Result<()>;
+    async fn get_supplier_catalogue(&self, supplier: i32) -> zbus::Result<Vec<SupplierCatalogueEntry>>;
+    async fn get_product_catalogue(&self, product: i64) -> zbus::Result<Vec<SupplierCatalogueEntry>>;
+    async fn supplier_catalogue_set(&self, actor: i32, supplier: i32, product: i64, article_number: &str, price: u32) -> zbus::Result<()>;
+    async fn supplier_catalogue_remove(&self, actor: i32, supplier: i32, product: i64) -> zbus::Result<()>;
+    async fn supplier_deposit_return(&self, actor: i32, supplier: i32, amount: u32, comment: &str) -> zbus::Result<i64>;
+    async fn get_supplier_deposits(&self, supplier: i32, from: i64, to: i64) -> zbus::Result<Vec<SupplierDeposit>>;
+    async fn get_deposit_balances(&self) -> zbus::Result<Vec<DepositBalance>>;
+    async fn get_customer_deposit(&self) -> zbus::Result<i32>;
     async fn ean_alias_list(&self) -> zbus::Result<Vec<EanAlias>>;
     async fn ean_alias_get(&self, ean: i64) -> zbus::Result<i64>;
-    async fn ean_alias_add(&self, ean: i64, real_ean: i64) -> zbus::Result<()>;
-    async fn new_product(&self, ean: i64, name: &str, category: i32, memberprice: i32, guestprice: i32) -> zbus::Result<()>;
+    async fn ean_alias_add(&self, actor: i32, ean: i64, real_ean: i64) -> zbus::Result<()>;
+    async fn product_update(&self, actor: i32, ean: i64, name: &str, category: i32) -> zbus::Result<()>;
+    async fn add_category(&self, actor: i32, name: &str) -> zbus::Result<()>;
+    async fn new_product(&self, actor: i32, ean: i64, name: &str, category: i32, memberprice: i32, guestprice: i32) -> zbus::Result<()>;
     async fn check_user_password(&self, userid: i32, password: &str) -> zbus::Result<bool>;
-    async fn set_user_password(&self, userid: i32, password: &str) -> zbus::Result<()>;
+    async fn set_user_password(&self, actor: i32, userid: i32, password: &str) -> zbus::Result<()>;
     async fn get_username(&self, userid: i32) -> zbus::Result<String>;
     async fn get_user_auth(&self, userid: i32) -> zbus::Result<UserAuth>;
-    async fn set_user_auth(&self, auth: UserAuth) -> zbus::Result<()>;
+    async fn set_user_auth(&self, actor: i32, auth: UserAuth) -> zbus::Result<()>;
     async fn get_member_ids(&self) -> zbus::Result<Vec<i32>>;
     async fn get_system_member_ids(&self) -> zbus::Result<Vec<i32>>;
     async fn get_user_info(&self, userid: i32) -> zbus::Result<UserInfo>;
     #[zbus(name="set_userTheme")]
-    async fn set_user_theme(&self, userid: i32, theme: &str) -> zbus::Result<()>;
+    async fn set_user_theme(&self, actor: i32, userid: i32, theme: &str) -> zbus::Result<()>;
     async fn get_invoice(&self, userid: i32, from: i64, to: i64) -> zbus::Result<Vec<InvoiceEntry>>;
     async fn get_user_sale_stats(&self, user: i32, timecode: &str) -> zbus::Result<Vec<UserSaleStatsEntry>>;
+    async fn get_user_invoices(&self, user: i32) -> zbus::Result<Vec<InvoiceInfo>>;
+    async fn get_invoice_info(&self, id: &str) -> zbus::Result<InvoiceInfo>;
+    async fn get_invoice_pdf(&self, id: &str) -> zbus::Result<Vec<u8>>;
+    async fn get_user_invoice_sum(&self, user: i32, timestamp_from: i64, timestamp_to: i64) -> zbus::Result<i32>;
+    async fn dispute_create(&self, user: i32, timestamp: i64, product: i64, comment: &str) -> zbus::Result<i64>;
+    async fn get_user_disputes(&self, user: i32) -> zbus::Result<Vec<DisputeInfo>>;
+    async fn get_disputes(&self, only_open: bool) -> zbus::Result<Vec<DisputeInfo>>;
+    async fn dispute_resolve(&self, dispute: i64, admin: i32, action: &str, reassign_to: i32, resolution: &str) -> zbus::Result<()>;
+    async fn get_audit_log(&self, actor: i32, entity: &str, from: i64, to: i64) -> zbus::Result<Vec<AuditEntry>>;
 	async fn get_first_purchase(&self, user: i32) -> zbus::Result<i64>;
 	async fn get_last_purchase(&self, user: i32) -> zbus::Result<i64>;
     async fn cashbox_status(&self) -> zbus::Result<i32>;
-    async fn cashbox_history(&self) -> zbus::Result<Vec<CashboxDiff>>;
-    async fn cashbox_changes(&self, start: i64, stop: i64) -> zbus::Result<Vec<CashboxDiff>>;
-    async fn cashbox_add(&self, user: i32, amount: i32, timestamp: i64) -> zbus::Result<()>;
+    async fn get_cashbox_ledger(&self, from: i64, to: i64, entry_type: &str, offset: u32, limit: u32) -> zbus::Result<(u32, Vec<CashboxLedgerEntry>)>;
+    async fn cashbox_add(&self, actor: i32, user: i32, entry_type: &str, amount: i32, timestamp: i64) -> zbus::Result<()>;
+    async fn cashbox_count(&self, actor: i32, counted: i32, comment: &str) -> zbus::Result<i64>;
+    async fn get_cashbox_counts(&self, from: i64, to: i64) -> zbus::Result<Vec<CashboxCount>>;
+    async fn get_cashbox_count(&self, id: i64) -> zbus::Result<CashboxCount>;
+    async fn get_order_suggestion(&self, supplier: i32, coverage: u32, history: u32) -> zbus::Result<(u32, u32, Vec<OrderSuggestion>)>;
+    async fn purchase_order_create(&self, actor: i32, supplier: i32, items: Vec<(i64, u32)>) -> zbus::Result<i64>;
+    async fn get_purchase_orders(&self, status: &str) -> zbus::Result<Vec<PurchaseOrder>>;
+    async fn get_purchase_order(&self, id: i64) -> zbus::Result<PurchaseOrder>;
+    async fn get_purchase_order_items(&self, id: i64) -> zbus::Result<Vec<PurchaseOrderItem>>;
+    async fn purchase_order_set_item(&self, actor: i32, id: i64, product: i64, amount: u32, price: u32) -> zbus::Result<()>;
+    async fn purchase_order_set_comment(&self, actor: i32, id: i64, comment: &str) -> zbus::Result<()>;
+    async fn purchase_order_set_status(&self, actor: i32, id: i64, status: &str) -> zbus::Result<()>;
+    async fn purchase_order_receive(&self, actor: i32, id: i64, deliveries: Vec<PurchaseOrderDelivery>) -> zbus::Result<()>;
     async fn get_category_list(&self) -> zbus::Result<Vec<ProductCategory>>;
+    async fn set_category_vat(&self, actor: i32, category: i32, vat: i32) -> zbus::Result<()>;
     async fn user_exists(&self, user: i32) -> zbus::Result<bool>;
     async fn user_equals(&self, info: &UserInfo) -> zbus::Result<bool>;
-    async fn user_disable(&self, user: i32, value: bool) -> zbus::Result<()>;
-    async fn user_replace(&self, info: &UserInfo) -> zbus::Result<()>;
+    async fn user_disable(&self, actor: i32, user: i32, value: bool) -> zbus::Result<()>;
+    async fn user_replace(&self, actor: i32, info: &UserInfo) -> zbus::Result<()>;
     async fn user_is_disabled(&self, user: i32) -> zbus::Result<bool>;
     async fn get_sales(&self, from: i64, to: i64) -> zbus::Result<Vec<SalesEntry>>;
+    async fn get_profit_report(&self, from: i64, to: i64) -> zbus::Result<Vec<ProfitReportEntry>>;
+    async fn get_stock_value(&self, timestamp: i64) -> zbus::Result<Vec<StockValueEntry>>;
+    async fn get_stock_lots(&self, ean: i64) -> zbus::Result<Vec<StockLot>>;
 }
 
 #[proxy(
@@ -599,10 +1561,55 @@ async fn import_archive(data: Vec<u8>) -> zbus::Result<Vec<String>> {
     proxy.import_archive(data).await
 }
 
-async fn set_sessionid(uid: i32, sessionid: &str) -> zbus::Result<()> {
+#[proxy(
+    interface = "io.mainframe.shopsystem.InvoicePDF",
+    default_service = "io.mainframe.shopsystem.InvoicePDF",
+    default_path = "/io/mainframe/shopsystem/invoicepdf"
+)]
+trait ShopPDF {
+    fn render(&self, request: InvoiceRequest) -> zbus::Result<Vec<u8>>;
+}
+
+async fn generate_invoice_pdf(id: &str, date: i64, recipient: InvoiceRecipient, entries: Vec<InvoiceEntry>, facturx: bool) -> zbus::Result<Vec<u8>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopPDFProxy::new(&connection).await?;
+    proxy.render(InvoiceRequest {
+        id: id.to_string(),
+        date: date,
+        recipient: recipient,
+        entries: entries,
+        facturx: facturx,
+    }).await
+}
+
+async fn session_create(uid: i32, sessionid: &str, csrf_token: &str, user_agent: &str, lifetime: i64) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.session_create(uid, sessionid, csrf_token, user_agent, lifetime).await
+}
+
+async fn get_session_csrf_token(sessionid: &str) -> zbus::Result<String> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_session_csrf_token(sessionid).await
+}
+
+async fn session_delete(sessionid: &str) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.session_delete(sessionid).await
+}
+
+async fn session_revoke(actor: i32, uid: i32, session: i64) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.session_revoke(actor, uid, session).await
+}
+
+async fn get_user_sessions(uid: i32, current_sessionid: &str) -> zbus::Result<Vec<SessionInfo>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.set_sessionid(uid, sessionid).await
+    proxy.get_user_sessions(uid, current_sessionid).await
 }
 
 async fn get_user_by_sessionid(sessionid: &str) -> zbus::Result<i32> {
@@ -647,16 +1654,16 @@ async fn ean_alias_get(ean: i64) -> zbus::Result<i64> {
     proxy.ean_alias_get(ean).await
 }
 
-async fn ean_alias_add(ean: i64, real_ean: i64) -> zbus::Result<()> {
+async fn ean_alias_add(actor: i32, ean: i64, real_ean: i64) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.ean_alias_add(ean, real_ean).await
+    proxy.ean_alias_add(actor, ean, real_ean).await
 }
 
-async fn new_product(ean: i64, name: &str, category: i32, memberprice: i32, guestprice: i32) -> zbus::Result<()> {
+async fn new_product(actor: i32, ean: i64, name: &str, category: i32, memberprice: i32, guestprice: i32) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.new_product(ean, name, category, memberprice, guestprice).await
+    proxy.new_product(actor, ean, name, category, memberprice, guestprice).await
 }
 
 async fn get_product_name(ean: i64) -> zbus::Result<String> {
@@ -695,10 +1702,22 @@ async fn get_product_deprecated(ean: i64) -> zbus::Result<bool> {
     proxy.get_product_deprecated(ean).await
 }
 
-async fn product_deprecate(ean: i64, deprecated: bool) -> zbus::Result<()> {
+async fn product_deprecate(actor: i32, ean: i64, deprecated: bool) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.product_deprecate(actor, ean, deprecated).await
+}
+
+async fn product_update(actor: i32, ean: i64, name: &str, category: i32) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.product_update(actor, ean, name, category).await
+}
+
+async fn add_category(actor: i32, name: &str) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.product_deprecate(ean, deprecated).await
+    proxy.add_category(actor, name).await
 }
 
 async fn product_metadata_get(ean: i64) -> zbus::Result<ProductMetadata> {
@@ -707,16 +1726,16 @@ async fn product_metadata_get(ean: i64) -> zbus::Result<ProductMetadata> {
     proxy.product_metadata_get(ean).await
 }
 
-async fn product_metadata_set(ean: i64, metadata: ProductMetadata) -> zbus::Result<()> {
+async fn product_metadata_set(actor: i32, ean: i64, metadata: ProductMetadata) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.product_metadata_set(ean, metadata).await
+    proxy.product_metadata_set(actor, ean, metadata).await
 }
 
-async fn restock(user: i32, product: i64, amount: u32, price: u32, supplier: i32, best_before_date: i64) -> zbus::Result<()> {
+async fn restock(actor: i32, user: i32, product: i64, amount: u32, price: u32, supplier: i32, best_before_date: i64) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.restock(user, product, amount, price, supplier, best_before_date).await
+    proxy.restock(actor, user, product, amount, price, supplier, best_before_date).await
 }
 
 async fn buy(user: i32, product: i64) -> zbus::Result<()> {
@@ -725,10 +1744,28 @@ async fn buy(user: i32, product: i64) -> zbus::Result<()> {
     proxy.buy(user, product).await
 }
 
-async fn new_price(product: i64, timestamp: i64, memberprice: i32, guestprice: i32) -> zbus::Result<()> {
+async fn new_price(actor: i32, product: i64, timestamp: i64, memberprice: i32, guestprice: i32) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.new_price(actor, product, timestamp, memberprice, guestprice).await
+}
+
+async fn price_cancel(actor: i32, product: i64, valid_from: i64) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.price_cancel(actor, product, valid_from).await
+}
+
+async fn category_price_change(actor: i32, category: i32, valid_from: i64, percent: i32) -> zbus::Result<u32> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.category_price_change(actor, category, valid_from, percent).await
+}
+
+async fn get_price_changes(from: i64, to: i64) -> zbus::Result<Vec<PriceChange>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.new_price(product, timestamp, memberprice, guestprice).await
+    proxy.get_price_changes(from, to).await
 }
 
 async fn products_search(search_query: &str) -> zbus::Result<Vec<Product>> {
@@ -761,34 +1798,137 @@ async fn cashbox_status() -> zbus::Result<i32> {
     proxy.cashbox_status().await
 }
 
-async fn cashbox_history() -> zbus::Result<Vec<CashboxDiff>> {
+async fn get_cashbox_ledger(from: i64, to: i64, entry_type: &str, offset: u32, limit: u32) -> zbus::Result<(u32, Vec<CashboxLedgerEntry>)> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.cashbox_history().await
+    proxy.get_cashbox_ledger(from, to, entry_type, offset, limit).await
 }
 
-async fn cashbox_history_named() -> zbus::Result<Vec<NamedCashboxDiff>> {
-    let mut history = Vec::new();
-    for change in cashbox_history().await? {
-        history.push(NamedCashboxDiff {
-            username: if change.user == -3 && change.amount < 0 { String::from("Loss") } else if change.user == -3 { String::from("Donation") } else { get_username(change.user).await.expect("failed to get username") },
-            amount: change.amount,
-            timestamp: change.timestamp,
+async fn cashbox_ledger_named(from: i64, to: i64, entry_type: &str, offset: u32, limit: u32) -> zbus::Result<(u32, Vec<NamedCashboxEntry>)> {
+    let (total, ledger) = get_cashbox_ledger(from, to, entry_type, offset, limit).await?;
+    let mut usernames: HashMap<i32, String> = HashMap::new();
+    let mut entries = Vec::new();
+
+    for entry in ledger {
+        if !usernames.contains_key(&entry.user) {
+            let username = match entry.entry_type.as_str() {
+                "guest_sale" => String::from("Guests"),
+                _ => get_username(entry.user).await.unwrap_or_else(|_| entry.user.to_string()),
+            };
+            usernames.insert(entry.user, username);
+        }
+
+        entries.push(NamedCashboxEntry {
+            id: entry.id,
+            timestamp: entry.timestamp,
+            type_name: cashbox_entry_type_name(&entry.entry_type).to_string(),
+            entry_type: entry.entry_type,
+            user: entry.user,
+            username: usernames[&entry.user].clone(),
+            amount: entry.amount,
+            balance: entry.balance,
         });
     }
+
+    Ok((total, entries))
+}
+
+async fn cashbox_history_named() -> zbus::Result<Vec<NamedCashboxEntry>> {
+    let (_, history) = cashbox_ledger_named(0, get_unix_time(), "", 0, 10).await?;
     Ok(history)
 }
 
-async fn cashbox_changes(start: i64, stop: i64) -> zbus::Result<Vec<CashboxDiff>> {
+async fn cashbox_add(actor: i32, user: i32, entry_type: &str, amount: i32, timestamp: i64) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.cashbox_add(actor, user, entry_type, amount, timestamp).await
+}
+
+async fn cashbox_count(actor: i32, counted: i32, comment: &str) -> zbus::Result<i64> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.cashbox_count(actor, counted, comment).await
+}
+
+async fn get_cashbox_counts(from: i64, to: i64) -> zbus::Result<Vec<CashboxCount>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_cashbox_counts(from, to).await
+}
+
+async fn get_cashbox_count(id: i64) -> zbus::Result<CashboxCount> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_cashbox_count(id).await
+}
+
+async fn get_order_suggestion(supplier: i32, coverage: u32, history: u32) -> zbus::Result<OrderSuggestionList> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    let (coverage, history, products) = proxy.get_order_suggestion(supplier, coverage, history).await?;
+    Ok(OrderSuggestionList { supplier, coverage, history, products })
+}
+
+async fn purchase_order_create(actor: i32, supplier: i32, items: Vec<(i64, u32)>) -> zbus::Result<i64> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.purchase_order_create(actor, supplier, items).await
+}
+
+async fn get_purchase_orders(status: &str) -> zbus::Result<Vec<PurchaseOrder>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_purchase_orders(status).await
+}
+
+async fn get_purchase_order(id: i64) -> zbus::Result<PurchaseOrder> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_purchase_order(id).await
+}
+
+async fn get_purchase_order_items(id: i64) -> zbus::Result<Vec<PurchaseOrderItem>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_purchase_order_items(id).await
+}
+
+async fn purchase_order_set_item(actor: i32, id: i64, product: i64, amount: u32, price: u32) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.purchase_order_set_item(actor, id, product, amount, price).await
+}
+
+async fn purchase_order_set_comment(actor: i32, id: i64, comment: &str) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.purchase_order_set_comment(actor, id, comment).await
+}
+
+async fn purchase_order_set_status(actor: i32, id: i64, status: &str) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.cashbox_changes(start, stop).await
+    proxy.purchase_order_set_status(actor, id, status).await
 }
 
-async fn cashbox_add(user: i32, amount: i32, timestamp: i64) -> zbus::Result<()> {
+async fn purchase_order_receive(actor: i32, id: i64, deliveries: Vec<PurchaseOrderDelivery>) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.cashbox_add(user, amount, timestamp).await
+    proxy.purchase_order_receive(actor, id, deliveries).await
+}
+
+async fn cashbox_count_named(count: CashboxCount) -> zbus::Result<NamedCashboxCount> {
+    Ok(NamedCashboxCount {
+        id: count.id,
+        user: count.user,
+        username: get_username(count.user).await?,
+        timestamp: count.timestamp,
+        expected: count.expected,
+        counted: count.counted,
+        difference: count.counted - count.expected,
+        correction: count.correction,
+        comment: count.comment,
+    })
 }
 
 async fn get_category_list() -> zbus::Result<Vec<ProductCategory>> {
@@ -797,6 +1937,12 @@ async fn get_category_list() -> zbus::Result<Vec<ProductCategory>> {
     proxy.get_category_list().await
 }
 
+async fn set_category_vat(actor: i32, category: i32, vat: i32) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.set_category_vat(actor, category, vat).await
+}
+
 async fn user_exists(user: i32) -> zbus::Result<bool> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
@@ -809,16 +1955,16 @@ async fn user_is_disabled(user: i32) -> zbus::Result<bool> {
     proxy.user_is_disabled(user).await
 }
 
-async fn user_disable(user: i32, value: bool) -> zbus::Result<()> {
+async fn user_disable(actor: i32, user: i32, value: bool) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.user_disable(user, value).await
+    proxy.user_disable(actor, user, value).await
 }
 
-async fn user_replace(info: &UserInfo) -> zbus::Result<()> {
+async fn user_replace(actor: i32, info: &UserInfo) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.user_replace(info).await
+    proxy.user_replace(actor, info).await
 }
 
 async fn user_equals(info: &UserInfo) -> zbus::Result<bool> {
@@ -852,78 +1998,144 @@ async fn get_supplier(id: i32) -> zbus::Result<Supplier> {
     proxy.get_supplier(id).await
 }
 
-async fn add_supplier(name: &str, postal_code: &str, city: &str, street: &str, phone: &str, website: &str) -> zbus::Result<()> {
+async fn add_supplier(actor: i32, name: &str, postal_code: &str, city: &str, street: &str, phone: &str, website: &str) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.add_supplier(name, postal_code, city, street, phone, website).await
+    proxy.add_supplier(actor, name, postal_code, city, street, phone, website).await
 }
 
-async fn get_supplier_list() -> zbus::Result<Vec<Supplier>> {
+async fn get_supplier_list(include_archived: bool) -> zbus::Result<Vec<Supplier>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.get_supplier_list().await
+    proxy.get_supplier_list(include_archived).await
 }
 
-async fn get_supplier_product_list(id: i32) -> zbus::Result<Vec<ProductInfo>> {
+async fn supplier_update(actor: i32, supplier: &Supplier) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.get_supplier_product_list(id).await
+    proxy.supplier_update(actor, supplier).await
 }
 
-async fn get_supplier_restock_dates(id: i32) -> zbus::Result<Vec<i64>> {
+async fn get_supplier_catalogue(supplier: i32) -> zbus::Result<Vec<SupplierCatalogueEntry>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.get_supplier_restock_dates(id).await
+    proxy.get_supplier_catalogue(supplier).await
 }
 
-async fn get_member_ids() -> zbus::Result<Vec<i32>> {
+async fn get_product_catalogue(product: i64) -> zbus::Result<Vec<SupplierCatalogueEntry>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.get_member_ids().await
+    proxy.get_product_catalogue(product).await
 }
 
-async fn get_user_list(system: bool) -> zbus::Result<Vec<(i32, String)>> {
+async fn supplier_catalogue_set(actor: i32, supplier: i32, product: i64, article_number: &str, price: u32) -> zbus::Result<()> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.supplier_catalogue_set(actor, supplier, product, article_number, price).await
+}
 
-    let list = if system { proxy.get_system_member_ids().await? } else {proxy.get_member_ids().await?};
+async fn supplier_catalogue_remove(actor: i32, supplier: i32, product: i64) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.supplier_catalogue_remove(actor, supplier, product).await
+}
 
+async fn supplier_deposit_return(actor: i32, supplier: i32, amount: u32, comment: &str) -> zbus::Result<i64> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.supplier_deposit_return(actor, supplier, amount, comment).await
+}
+
+async fn get_supplier_deposits_named(supplier: i32, from: i64, to: i64) -> zbus::Result<Vec<NamedSupplierDeposit>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    let suppliers = proxy.get_supplier_list(true).await?;
     let mut result = Vec::new();
 
-    for uid in list {
-        result.push(
-            (uid, proxy.get_username(uid).await?)
-        );
+    for deposit in proxy.get_supplier_deposits(supplier, from, to).await? {
+        let supplier_name = match suppliers.iter().find(|s| s.id == deposit.supplier as i64) {
+            Some(supplier) => supplier.name.clone(),
+            None => "Unknown".to_string(),
+        };
+        let product_name = if deposit.product == 0 { String::new() } else { proxy.get_product_name(deposit.product).await? };
+        result.push(NamedSupplierDeposit { deposit: deposit, supplier_name: supplier_name, product_name: product_name });
     }
-    
+
     Ok(result)
 }
 
-async fn get_user_auth(uid: i32) -> zbus::Result<UserAuth> {
+async fn get_deposit_overview() -> zbus::Result<DepositOverview> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.get_user_auth(uid).await
+    let suppliers = proxy.get_deposit_balances().await?;
+
+    Ok(DepositOverview {
+        supplier_deposit: suppliers.iter().map(|s| s.balance).sum(),
+        customer_deposit: proxy.get_customer_deposit().await?,
+        suppliers: suppliers,
+    })
 }
 
-async fn set_user_auth(authdata: UserAuth) -> zbus::Result<()> {
+async fn get_supplier_product_list(id: i32) -> zbus::Result<Vec<ProductInfo>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.set_user_auth(authdata).await
+    proxy.get_supplier_product_list(id).await
 }
 
-async fn set_user_password(userid: i32, theme: &str) -> zbus::Result<()> {
+async fn get_supplier_restock_dates(id: i32) -> zbus::Result<Vec<i64>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.set_user_password(userid, theme).await
+    proxy.get_supplier_restock_dates(id).await
 }
 
-async fn set_user_theme(userid: i32, theme: &str) -> zbus::Result<()> {
+async fn get_member_ids() -> zbus::Result<Vec<i32>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
-    proxy.set_user_theme(userid, theme).await
+    proxy.get_member_ids().await
 }
 
-async fn get_user_info(uid: i32) -> zbus::Result<UserInfo> {
+async fn get_user_list(system: bool) -> zbus::Result<Vec<(i32, String)>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+
+    let list = if system { proxy.get_system_member_ids().await? } else {proxy.get_member_ids().await?};
+
+    let mut result = Vec::new();
+
+    for uid in list {
+        result.push(
+            (uid, proxy.get_username(uid).await?)
+        );
+    }
+    
+    Ok(result)
+}
+
+async fn get_user_auth(uid: i32) -> zbus::Result<UserAuth> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_user_auth(uid).await
+}
+
+async fn set_user_auth(actor: i32, authdata: UserAuth) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.set_user_auth(actor, authdata).await
+}
+
+async fn set_user_password(actor: i32, userid: i32, theme: &str) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.set_user_password(actor, userid, theme).await
+}
+
+async fn set_user_theme(actor: i32, userid: i32, theme: &str) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.set_user_theme(actor, userid, theme).await
+}
+
+async fn get_user_info(uid: i32) -> zbus::Result<UserInfo> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
     proxy.get_user_info(uid).await
@@ -949,6 +2161,60 @@ async fn get_user_sale_stats(uid: i32, timecode: &str) -> zbus::Result<Vec<UserS
     proxy.get_user_sale_stats(uid, timecode).await
 }
 
+async fn get_user_invoices(uid: i32) -> zbus::Result<Vec<InvoiceInfo>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_user_invoices(uid).await
+}
+
+async fn get_invoice_info(id: &str) -> zbus::Result<InvoiceInfo> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_invoice_info(id).await
+}
+
+async fn get_invoice_pdf(id: &str) -> zbus::Result<Vec<u8>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_invoice_pdf(id).await
+}
+
+async fn get_user_invoice_sum(uid: i32, start: i64, stop: i64) -> zbus::Result<i32> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_user_invoice_sum(uid, start, stop).await
+}
+
+async fn dispute_create(uid: i32, timestamp: i64, product: i64, comment: &str) -> zbus::Result<i64> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.dispute_create(uid, timestamp, product, comment).await
+}
+
+async fn get_user_disputes(uid: i32) -> zbus::Result<Vec<DisputeInfo>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_user_disputes(uid).await
+}
+
+async fn get_disputes(only_open: bool) -> zbus::Result<Vec<DisputeInfo>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_disputes(only_open).await
+}
+
+async fn get_audit_log(actor: i32, entity: &str, from: i64, to: i64) -> zbus::Result<Vec<AuditEntry>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_audit_log(actor, entity, from, to).await
+}
+
+async fn dispute_resolve(dispute: i64, admin: i32, action: &str, reassign_to: i32, resolution: &str) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.dispute_resolve(dispute, admin, action, reassign_to, resolution).await
+}
+
 async fn check_user_password(userid: i32, password: &str) -> zbus::Result<bool> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
@@ -964,6 +2230,7 @@ async fn get_session_with_sessionid(sessionid: &str) -> zbus::Result<Session> {
             auth_cashbox: false,
             auth_products: false,
             auth_users: false,
+            csrf_token: String::new(),
         })},
         Ok(uid) => uid,
     };
@@ -973,6 +2240,7 @@ async fn get_session_with_sessionid(sessionid: &str) -> zbus::Result<Session> {
 
     let name = proxy.get_username(uid).await?;
     let auth = proxy.get_user_auth(uid).await?;
+    let csrf_token = proxy.get_session_csrf_token(sessionid).await?;
 
     Ok(Session {
         uid: auth.id,
@@ -981,38 +2249,129 @@ async fn get_session_with_sessionid(sessionid: &str) -> zbus::Result<Session> {
         auth_cashbox: auth.auth_cashbox || auth.superuser,
         auth_products: auth.auth_products || auth.superuser,
         auth_users: auth.auth_users || auth.superuser,
+        csrf_token: csrf_token,
+    })
+}
+
+/* the session of an API token owner, limited to the scopes of the token */
+async fn get_api_session(token: &str) -> zbus::Result<Session> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+
+    let info = proxy.get_api_token(token).await?;
+    if proxy.user_is_disabled(info.user).await? {
+        return Err(zbus::Error::Failure("account is disabled".to_string()));
+    }
+
+    let name = proxy.get_username(info.user).await?;
+    let auth = proxy.get_user_auth(info.user).await?;
+    let scoped = |scope: &str| info.scopes.iter().any(|s| s == scope);
+
+    Ok(Session {
+        uid: auth.id,
+        name: name,
+        superuser: false,
+        auth_cashbox: (auth.auth_cashbox || auth.superuser) && scoped("cashbox"),
+        auth_products: (auth.auth_products || auth.superuser) && scoped("products"),
+        auth_users: (auth.auth_users || auth.superuser) && scoped("users"),
+        csrf_token: String::new(),
     })
 }
 
+async fn api_token_create(actor: i32, userid: i32, name: &str, token: &str, scopes: Vec<String>) -> zbus::Result<i64> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.api_token_create(actor, userid, name, token, scopes).await
+}
+
+async fn api_token_revoke(actor: i32, userid: i32, token: i64) -> zbus::Result<()> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.api_token_revoke(actor, userid, token).await
+}
+
+async fn get_user_api_tokens(userid: i32) -> zbus::Result<Vec<ApiTokenInfo>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_user_api_tokens(userid).await
+}
+
 async fn get_sales(start: i64, stop: i64) -> zbus::Result<Vec<SalesEntry>> {
     let connection = Connection::system().await?;
     let proxy = ShopDBProxy::new(&connection).await?;
     proxy.get_sales(start, stop).await
 }
 
-async fn get_session(cookies: &CookieJar<'_>) -> zbus::Result<Session> {
-    get_session_with_sessionid(&cookies.get("sessionid").map(|c| c.value()).unwrap_or("invalid")).await
+async fn get_profit_report(from: i64, to: i64) -> zbus::Result<Vec<ProfitReportEntry>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_profit_report(from, to).await
+}
+
+async fn get_stock_value(timestamp: i64) -> zbus::Result<Vec<StockValueEntry>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_stock_value(timestamp).await
+}
+
+async fn get_stock_lots(ean: i64) -> zbus::Result<Vec<StockLot>> {
+    let connection = Connection::system().await?;
+    let proxy = ShopDBProxy::new(&connection).await?;
+    proxy.get_stock_lots(ean).await
+}
+
+/* returns per product lines, per category lines and the total */
+async fn profit_report(from: i64, to: i64) -> zbus::Result<(Vec<ProfitReportLine>, Vec<ProfitReportLine>, ProfitReportLine)> {
+    let mut products = Vec::new();
+    let mut categories: Vec<ProfitReportLine> = Vec::new();
+    let mut total = ProfitReportLine::new(0, "Total", "");
+
+    for entry in get_profit_report(from, to).await? {
+        let mut line = ProfitReportLine::new(entry.product.ean, &entry.product.name, &entry.category);
+        line.add(entry.units, entry.revenue, entry.cost);
+
+        /* entries are sorted by category */
+        match categories.last_mut() {
+            Some(category) if category.category == entry.category => category.add(entry.units, entry.revenue, entry.cost),
+            _ => {
+                let mut category = ProfitReportLine::new(0, &entry.category, &entry.category);
+                category.add(entry.units, entry.revenue, entry.cost);
+                categories.push(category);
+            },
+        };
+
+        total.add(entry.units, entry.revenue, entry.cost);
+        products.push(line);
+    }
+
+    Ok((products, categories, total))
 }
 
 fn generate_session_id() -> String {
     rand::rng()
         .sample_iter(&Alphanumeric)
-        .take(19)
+        .take(32)
         .map(char::from)
         .collect()
 }
 
 #[post("/login", data = "<logindata>")]
-async fn login(logindata: Form<UserLoginData<'_>>, cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
+async fn login(logindata: Form<UserLoginData<'_>>, cookies: &CookieJar<'_>, user_agent: UserAgent, config: &State<WebConfig>) -> Result<Template, WebShopError> {
     let correctpw = check_user_password(logindata.userid, logindata.password).await?;
     match correctpw {
         false => Ok(Template::render("wrong-password", context! { page: "login", userid: logindata.userid })),
         true => {
             let sessionid = generate_session_id();
-            match set_sessionid(logindata.userid, &sessionid).await {
+            let csrf_token = generate_session_id();
+            match session_create(logindata.userid, &sessionid, &csrf_token, &user_agent.0, config.session_lifetime).await {
                 Err(error) => Ok(Template::render("error", context! { page: "error", errmsg: error.to_string() })),
                 _ => {
-                    cookies.add(Cookie::new("sessionid", format!("{}", sessionid)));
+                    let cookie = Cookie::build(("sessionid", sessionid.clone()))
+                        .http_only(true)
+                        .secure(config.secure_cookies)
+                        .same_site(SameSite::Strict)
+                        .max_age(rocket::time::Duration::seconds(config.session_lifetime));
+                    cookies.add(cookie);
                     let session = get_session_with_sessionid(&sessionid).await?;
                     Ok(Template::render("login", context! { page: "login", session: session }))
                 }
@@ -1021,21 +2380,28 @@ async fn login(logindata: Form<UserLoginData<'_>>, cookies: &CookieJar<'_>) -> R
     }
 }
 
-#[get("/logout")]
-async fn logout(cookies: &CookieJar<'_>) -> Template {
+#[post("/logout", data = "<form>")]
+async fn logout(cookies: &CookieJar<'_>, session: Option<Session>, form: Option<Form<CsrfToken>>) -> Template {
+        let csrf = match (session, form) {
+            (Some(session), Some(form)) => csrf_form_check(&session, &form.csrf_token).is_ok(),
+            _ => false,
+        };
+        if let (Some(cookie), true) = (cookies.get("sessionid"), csrf) {
+            /* invalidate session on the server, the cookie might have been copied */
+            let _ = session_delete(cookie.value()).await;
+        }
         cookies.remove(Cookie::from("sessionid"));
         Template::render("logout", context! { page: "logout"} )
 }
 
 #[get("/")]
-async fn index(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn index(session: Session) -> Result<Template, WebShopError> {
     Ok(Template::render("index", context! { page: "index", session: session }))
 }
 
 #[get("/products")]
-async fn products(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn products(auth: RequireLogin) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
     let categories = get_category_list().await?;
 
@@ -1084,12 +2450,9 @@ fn parse_price(price: &str) -> Result<i32, ParseIntError> {
 }
 
 #[post("/products/new", data = "<info>")]
-async fn product_new(cookies: &CookieJar<'_>, info: Form<NewProduct>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_products {
-        return Err(WebShopError::PermissionDenied());
-    }
+async fn product_new(auth: RequireProducts, info: Form<NewProduct>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    csrf_form_check(&session, &info.csrf_token)?;
 
     if !check_valid_gtin(info.id, 8) && !check_valid_gtin(info.id, 13) {
         return Ok(Template::render("error", context! { page: "error", session: session, errmsg: format!("Product ID '{}' is neither a valid EAN-8 nor EAN-13", info.id) }));
@@ -1123,45 +2486,255 @@ async fn product_new(cookies: &CookieJar<'_>, info: Form<NewProduct>) -> Result<
         return Ok(Template::render("error", context! { page: "error", session: session, errmsg: format!("missing product name") }));
     }
 
-    new_product(info.id, &info.name, info.category, memberprice, guestprice).await?;
+    new_product(session.uid, info.id, &info.name, info.category, memberprice, guestprice).await?;
     Ok(Template::render("products/new", context! { page: "products/new", session: session, ean: info.id, name: &info.name }))
 }
 
 #[get("/products/bestbefore")]
-async fn product_bestbefore(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn product_bestbefore(auth: RequireLogin) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
     let list = get_bestbeforelist().await?;
     Ok(Template::render("products/bestbefore", context! { page: "products/bestbefore", session: session, list: list }))
 }
 
 #[get("/products/inventory")]
-async fn product_inventory(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_products {
-        return Err(WebShopError::PermissionDenied());
-    }
+async fn product_inventory(auth: RequireProducts) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
     let sysusers = get_user_list(true).await?;
-    let suppliers = get_supplier_list().await?;
+    let suppliers = get_supplier_list(false).await?;
     let stock = get_stock().await?;
 
     Ok(Template::render("products/inventory", context! { page: "products/inventory", session: session, sysusers: sysusers, suppliers: suppliers, products: stock }))
 }
 
-async fn product_inventory_apply_helper(cookies: &CookieJar<'_>, data: Json<InventoryData>) -> zbus::Result<()> {
-    let session = get_session(cookies).await?;
+#[get("/products/categories")]
+async fn product_categories(auth: RequireProducts) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
-    if session.auth_products {
-        for operation in &data.operations {
-            if operation.diff > 0 {
-                restock(session.uid, operation.ean, operation.diff as u32, 0, data.supplier, 0).await?;
-            } else if operation.diff < 0 {
-                let count = operation.diff.abs();
-                for _ in 0..count {
-                    buy(data.user, operation.ean).await?;
-                }
+    let categories = get_category_list().await?;
+
+    Ok(Template::render("products/categories", context! { page: "products/categories", session: session, categories: categories }))
+}
+
+#[post("/products/categories/<id>/vat", format = "application/json", data = "<vat>")]
+async fn product_category_vat_set(auth: RequireProducts, _csrf: CsrfChecked, id: i32, vat: Json<i32>) -> Result<Json<i32>, Forbidden<String>> {
+    let vat = vat.into_inner();
+    if vat != 7 && vat != 19 {
+        return Err(Forbidden("Unsupported VAT rate".to_string()));
+    }
+
+    match set_category_vat(auth.0.uid, id, vat).await {
+        Err(error) => { return Err(Forbidden(error.to_string())); },
+        Ok(_) => {},
+    };
+
+    Ok(Json(vat))
+}
+
+#[get("/products/prices")]
+async fn product_prices(auth: RequireProducts) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let now = chrono::offset::Local::now().timestamp();
+
+    let changes = get_price_changes(now, i64::MAX).await?;
+    let categories = get_category_list().await?;
+
+    Ok(Template::render("products/prices", context! { page: "products/prices", session: session, changes: changes, categories: categories }))
+}
+
+#[post("/products/categories/<id>/prices", format = "application/json", data = "<change>")]
+async fn product_category_prices_set(auth: RequireProducts, _csrf: CsrfChecked, id: i32, change: Json<CategoryPriceChange>) -> Result<Json<u32>, Forbidden<String>> {
+    match category_price_change(auth.0.uid, id, change.valid_from, change.percent).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(count) => Ok(Json(count)),
+    }
+}
+
+async fn get_product_csv_entries() -> zbus::Result<Vec<ProductCsvEntry>> {
+    let mut result = Vec::new();
+
+    for product in get_productlist().await? {
+        let mut aliases = product.aliases;
+        aliases.sort();
+
+        result.push(ProductCsvEntry {
+            ean: product.ean,
+            name: product.name,
+            category: product.category,
+            memberprice: product.memberprice,
+            guestprice: product.guestprice,
+            deprecated: product.deprecated,
+            metadata: product_metadata_get(product.ean).await.ok(),
+            aliases: aliases,
+        });
+    }
+
+    Ok(result)
+}
+
+/* products missing in the CSV are kept and aliases are only added, never removed */
+async fn product_csv_changes(data: &str) -> zbus::Result<(Vec<ProductChange>, Vec<String>)> {
+    let mut current = HashMap::new();
+    for entry in get_product_csv_entries().await? {
+        current.insert(entry.ean, entry);
+    }
+
+    let mut changes = Vec::new();
+    let mut errors = Vec::new();
+    let mut seen = Vec::new();
+    let mut csv = csv::ReaderBuilder::new()
+        .delimiter(b';')
+        .has_headers(true)
+        .flexible(true)
+        .from_reader(data.as_bytes());
+
+    for (i, record) in csv.records().enumerate() {
+        /* line 1 is the header */
+        let line = i + 2;
+        let record = match record {
+            Ok(record) => record,
+            Err(error) => { errors.push(format!("line {}: {}", line, error)); continue; },
+        };
+        let mut entry = match ProductCsvEntry::from_record(&record) {
+            Ok(entry) => entry,
+            Err(error) => { errors.push(format!("line {}: {}", line, error)); continue; },
+        };
+
+        if seen.contains(&entry.ean) {
+            errors.push(format!("line {}: duplicate EAN {}", line, entry.ean));
+            continue;
+        }
+        seen.push(entry.ean);
+
+        let old = current.get(&entry.ean).cloned();
+        if let Some(old) = &old {
+            /* old exports do not contain allergens and ingredients, keep the known ones */
+            if let (PRODUCT_CSV_LEGACY_COLUMNS, Some(new), Some(known)) = (record.len(), &mut entry.metadata, &old.metadata) {
+                new.allergens = known.allergens.clone();
+                new.ingredients = known.ingredients.clone();
+            }
+            entry.aliases.extend(old.aliases.iter());
+            entry.aliases.sort();
+            entry.aliases.dedup();
+            if *old == entry {
+                continue;
+            }
+        }
+
+        changes.push(ProductChange { old: old, new: entry });
+    }
+
+    Ok((changes, errors))
+}
+
+async fn product_import_apply_helper(session: &Session, change: &ProductChange) -> zbus::Result<()> {
+    let new = &change.new;
+    if let Err(error) = new.validate() {
+        return Err(zbus::Error::Failure(error));
+    }
+
+    /* categories are referenced by name in the CSV and created on demand */
+    add_category(session.uid, &new.category).await?;
+    let category = match get_category_list().await?.into_iter().find(|c| c.name == new.category) {
+        Some(category) => category.id,
+        None => { return Err(zbus::Error::Failure(format!("unknown category {}", new.category))); },
+    };
+
+    match &change.old {
+        None => {
+            new_product(session.uid, new.ean, &new.name, category, new.memberprice, new.guestprice).await?;
+        },
+        Some(old) => {
+            if old.name != new.name || old.category != new.category {
+                product_update(session.uid, new.ean, &new.name, category).await?;
+            }
+            if old.memberprice != new.memberprice || old.guestprice != new.guestprice {
+                new_price(session.uid, new.ean, get_unix_time(), new.memberprice, new.guestprice).await?;
+            }
+        },
+    };
+
+    let old = change.old.as_ref();
+    if old.map(|o| o.deprecated).unwrap_or(false) != new.deprecated {
+        product_deprecate(session.uid, new.ean, new.deprecated).await?;
+    }
+
+    if let Some(metadata) = &new.metadata {
+        if old.and_then(|o| o.metadata.as_ref()) != Some(metadata) {
+            product_metadata_set(session.uid, new.ean, metadata.clone()).await?;
+        }
+    }
+
+    for alias in &new.aliases {
+        if !old.map(|o| o.aliases.contains(alias)).unwrap_or(false) {
+            ean_alias_add(session.uid, *alias, new.ean).await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[get("/products/export.csv")]
+async fn product_export(_auth: RequireProducts) -> Result<(ContentType, String), WebShopError> {
+    let mut csv = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_writer(Vec::new());
+
+    csv.write_record(PRODUCT_CSV_HEADER)?;
+    for entry in get_product_csv_entries().await? {
+        csv.write_record(entry.to_record())?;
+    }
+
+    let data = csv.into_inner().map_err(|e| e.into_error())?;
+    Ok((ContentType::CSV, String::from_utf8(data)?))
+}
+
+#[get("/products/import")]
+async fn product_import(auth: RequireProducts) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    Ok(Template::render("products/import", context! { page: "products/import", session: session, header: PRODUCT_CSV_HEADER.join(";") }))
+}
+
+#[post("/products/import", data = "<form>")]
+async fn product_import_upload(auth: RequireProducts, mut form: Form<FileUpload<'_>>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    csrf_form_check(&session, &form.csrf_token)?;
+
+    if !form.file.is_complete() {
+        return Ok(Template::render("error", context! { page: "error", session: session, errmsg: "Incomplete file upload!" }));
+    }
+
+    match form.file.persist_to("/tmp/shopsystem-products.csv").await {
+        Err(error) => { return Ok(Template::render("error", context! { page: "error", errmsg: error.to_string(), session: session })) },
+        Ok(_) => {},
+    };
+
+    let csvdata = std::fs::read_to_string("/tmp/shopsystem-products.csv")?;
+    let (changes, errors) = product_csv_changes(&csvdata).await?;
+
+    Ok(Template::render("products/import2", context! { page: "products/import2", session: session, changes: changes, errors: errors }))
+}
+
+#[post("/products/import/apply", format = "application/json", data = "<change>")]
+async fn product_import_apply(auth: RequireProducts, _csrf: CsrfChecked, change: Json<ProductChange>) -> Result<Json<bool>, Forbidden<String>> {
+    let session = auth.0;
+
+    match product_import_apply_helper(&session, &change).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(true)),
+    }
+}
+
+async fn product_inventory_apply_helper(session: &Session, data: Json<InventoryData>) -> zbus::Result<()> {
+    for operation in &data.operations {
+        if operation.diff > 0 {
+            restock(session.uid, session.uid, operation.ean, operation.diff as u32, 0, data.supplier, 0).await?;
+        } else if operation.diff < 0 {
+            let count = operation.diff.abs();
+            for _ in 0..count {
+                buy(data.user, operation.ean).await?;
             }
         }
     }
@@ -1170,15 +2743,15 @@ async fn product_inventory_apply_helper(cookies: &CookieJar<'_>, data: Json<Inve
 }
 
 #[post("/products/inventory/apply", format = "application/json", data = "<data>")]
-async fn product_inventory_apply(cookies: &CookieJar<'_>, data: Json<InventoryData>) -> Result<Json<()>, Forbidden<String>> {
-    match product_inventory_apply_helper(cookies, data).await {
+async fn product_inventory_apply(auth: RequireProducts, _csrf: CsrfChecked, data: Json<InventoryData>) -> Result<Json<()>, Forbidden<String>> {
+    match product_inventory_apply_helper(&auth.0, data).await {
         Err(error) => Err(Forbidden(error.to_string())),
         Ok(_) => Ok(Json(())),
     }
 }
 
 #[get("/products/<ean>/json", rank=1)]
-async fn product_details_json(ean: i64) -> Result<Json<ProductDetails>, WebShopError> {
+async fn product_details_json(_auth: RequireProducts, ean: i64) -> Result<Json<ProductDetails>, WebShopError> {
     let ean = ean_alias_get(ean).await?;
 
     Ok(Json(ProductDetails {
@@ -1191,42 +2764,37 @@ async fn product_details_json(ean: i64) -> Result<Json<ProductDetails>, WebShopE
 }
 
 #[get("/products/<ean>/amount", rank=1)]
-async fn product_amount_json(ean: i64) -> Result<Json<(i32, u32)>, WebShopError> {
+async fn product_amount_json(_auth: RequireProducts, ean: i64) -> Result<Json<(i32, u32)>, WebShopError> {
     Ok(Json(get_product_amount_with_container_size(ean).await?))
 }
 
 #[get("/products/<ean>/sales-info?<timestamp>", rank=1)]
-async fn product_sales_info_json(ean: i64, timestamp: i64) -> Result<Json<u32>, WebShopError> {
+async fn product_sales_info_json(_auth: RequireProducts, ean: i64, timestamp: i64) -> Result<Json<u32>, WebShopError> {
     Ok(Json(get_product_sales_info(ean, timestamp).await?))
 }
 
 #[get("/products/search/<search>", rank=2)]
-async fn product_search_json(search: &str) -> Result<Json<Vec<Product>>, WebShopError> {
+async fn product_search_json(_auth: RequireProducts, search: &str) -> Result<Json<Vec<Product>>, WebShopError> {
     Ok(Json(products_search(search).await?))
 }
 
-async fn product_missing(cookies: &CookieJar<'_>, ean: i64) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn product_missing(session: Session, ean: i64) -> Result<Template, WebShopError> {
     let categories = get_category_list().await?;
     Ok(Template::render("products/missing", context! { page: "products/missing", session: session, ean: ean, categories: categories }))
 }
 
 #[get("/products/restock")]
-async fn product_restock(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_products {
-        return Err(WebShopError::PermissionDenied());
-    }
+async fn product_restock(auth: RequireProducts) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
-    let suppliers = get_supplier_list().await?;
+    let suppliers = get_supplier_list(false).await?;
 
     Ok(Template::render("products/restock", context! { page: "products/restock", session: session, suppliers: suppliers }))
 }
 
 #[get("/products/<ean>")]
-async fn product_details(cookies: &CookieJar<'_>, ean: i64) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn product_details(auth: RequireLogin, ean: i64) -> Result<Template, WebShopError> {
+    let session = auth.0;
     let ean = ean_alias_get(ean).await?;
     let name;
     match get_product_name(ean).await {
@@ -1235,7 +2803,7 @@ async fn product_details(cookies: &CookieJar<'_>, ean: i64) -> Result<Template,
             match err {
                 zbus::Error::MethodError(ref errname, ref errmsg, _) => {
                     if errname.inner().as_str() == "io.mainframe.shopsystem.Database.SQL" && *errmsg == Some("Query returned no rows".to_string()) {
-                        return product_missing(cookies, ean).await;
+                        return product_missing(session, ean).await;
                     } else {
                         return Err(WebShopError::DBusError(err));
                     }
@@ -1253,6 +2821,7 @@ async fn product_details(cookies: &CookieJar<'_>, ean: i64) -> Result<Template,
     let prices = get_prices(ean).await?;
     let rawrestock = get_restocks(ean, false).await?;
     let metadata = product_metadata_get(ean).await.ok().unwrap_or_default();
+    let lots = get_stock_lots(ean).await?;
 
     let mut restock = Vec::new();
     for entry in rawrestock {
@@ -1267,23 +2836,16 @@ async fn product_details(cookies: &CookieJar<'_>, ean: i64) -> Result<Template,
         });
     }
 
-    let suppliers = get_supplier_list().await?;
+    let suppliers = get_supplier_list(false).await?;
+    let catalogue = get_product_catalogue(ean).await?;
+    let now = chrono::offset::Local::now().timestamp();
 
-    Ok(Template::render("products/details", context! { page: "products/details", session: session, ean: ean, aliases: aliases, name: name, category: category, amount: amount, deprecated: deprecated, prices: prices, restock: restock, suppliers: suppliers, metadata: metadata }))
+    Ok(Template::render("products/details", context! { page: "products/details", session: session, ean: ean, aliases: aliases, name: name, category: category, amount: amount, deprecated: deprecated, prices: prices, restock: restock, suppliers: suppliers, catalogue: catalogue, metadata: metadata, lots: lots, now: now }))
 }
 
-#[get("/products/<ean>/deprecate/<deprecated>")]
-async fn web_product_deprecate(cookies: &CookieJar<'_>, ean: i64, deprecated: bool) -> Result<Json<bool>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
-
-    if !session.superuser && !session.auth_products {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
-
-    match product_deprecate(ean, deprecated).await {
+#[post("/products/<ean>/deprecate/<deprecated>")]
+async fn web_product_deprecate(auth: RequireProducts, _csrf: CsrfChecked, ean: i64, deprecated: bool) -> Result<Json<bool>, Forbidden<String>> {
+    match product_deprecate(auth.0.uid, ean, deprecated).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(_) => {},
     };
@@ -1292,42 +2854,38 @@ async fn web_product_deprecate(cookies: &CookieJar<'_>, ean: i64, deprecated: bo
 }
 
 #[post("/products/<ean>/add-prices", format = "application/json", data = "<priceinfo>")]
-async fn web_product_add_prices(cookies: &CookieJar<'_>, ean: i64, priceinfo: Json<PriceInfo>) -> Result<Json<PriceInfo>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
-
-    if !session.superuser && !session.auth_products {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
-
+async fn web_product_add_prices(auth: RequireProducts, _csrf: CsrfChecked, ean: i64, priceinfo: Json<PriceInfo>) -> Result<Json<PriceInfo>, Forbidden<String>> {
     let now = chrono::offset::Local::now().timestamp();
+    /* prices can be scheduled, but not backdated */
+    let valid_from = std::cmp::max(priceinfo.timestamp, now);
 
-    match new_price(ean, now, priceinfo.memberprice, priceinfo.guestprice).await {
+    match new_price(auth.0.uid, ean, valid_from, priceinfo.memberprice, priceinfo.guestprice).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(_) => {},
     };
 
     Ok(Json(PriceInfo {
-        timestamp: now,
+        timestamp: valid_from,
         memberprice: priceinfo.memberprice,
         guestprice: priceinfo.guestprice,
     }))
 }
 
-#[post("/products/<ean>/restock", format = "application/json", data = "<data>")]
-async fn web_product_restock(cookies: &CookieJar<'_>, ean: i64, data: Json<RestockEntry>) -> Result<Json<RestockEntryNamedSupplier>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
+#[post("/products/<ean>/prices/<valid_from>/cancel")]
+async fn web_product_price_cancel(auth: RequireProducts, _csrf: CsrfChecked, ean: i64, valid_from: i64) -> Result<Json<bool>, Forbidden<String>> {
+    match price_cancel(auth.0.uid, ean, valid_from).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
+        Ok(_) => {},
     };
 
-    if !session.superuser && !session.auth_products {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
+    Ok(Json(true))
+}
 
-    match restock(session.uid, ean, data.amount, data.price, data.supplier, data.best_before_date).await {
+#[post("/products/<ean>/restock", format = "application/json", data = "<data>")]
+async fn web_product_restock(auth: RequireProducts, _csrf: CsrfChecked, ean: i64, data: Json<RestockEntry>) -> Result<Json<RestockEntryNamedSupplier>, Forbidden<String>> {
+    let session = auth.0;
+
+    match restock(session.uid, session.uid, ean, data.amount, data.price, data.supplier, data.best_before_date).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(_) => {},
     };
@@ -1348,16 +2906,7 @@ async fn web_product_restock(cookies: &CookieJar<'_>, ean: i64, data: Json<Resto
 }
 
 #[get("/products/<ean>/get-last-restock")]
-async fn web_product_last_restock(cookies: &CookieJar<'_>, ean: i64) -> Result<Json<RestockEntry>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
-
-    if !session.superuser && !session.auth_products {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
-
+async fn web_product_last_restock(_auth: RequireProducts, ean: i64) -> Result<Json<RestockEntry>, Forbidden<String>> {
     let data = match get_last_restock(ean, 1).await {
         Ok(data) => Ok(data),
         Err(err) => Err(Forbidden(err.to_string())),
@@ -1366,17 +2915,8 @@ async fn web_product_last_restock(cookies: &CookieJar<'_>, ean: i64) -> Result<J
     Ok(Json(data))
 }
 
-#[get("/products/<ean>/add-alias/<alias>", format = "application/json")]
-async fn web_product_alias_add(cookies: &CookieJar<'_>, ean: i64, alias: i64) -> Result<Json<i64>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
-
-    if !session.superuser && !session.auth_products {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
-
+#[post("/products/<ean>/add-alias/<alias>")]
+async fn web_product_alias_add(auth: RequireProducts, _csrf: CsrfChecked, ean: i64, alias: i64) -> Result<Json<i64>, Forbidden<String>> {
     /* verify the product exists */
     match get_product_name(ean).await {
         Err(_) => { return Err(Forbidden(String::from("product EAN does not exist"))); },
@@ -1402,7 +2942,7 @@ async fn web_product_alias_add(cookies: &CookieJar<'_>, ean: i64, alias: i64) ->
         Ok(_) => { return Err(Forbidden(String::from("The new EAN already exists as product"))); },
     };
 
-    match ean_alias_add(alias, ean).await {
+    match ean_alias_add(auth.0.uid, alias, ean).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(_) => {},
     };
@@ -1411,7 +2951,7 @@ async fn web_product_alias_add(cookies: &CookieJar<'_>, ean: i64, alias: i64) ->
 }
 
 #[get("/products/<ean>/metadata-get")]
-async fn web_product_metadata_get(_cookies: &CookieJar<'_>, ean: i64) -> Result<Json<ProductMetadata>, Forbidden<String>> {
+async fn web_product_metadata_get(_auth: RequireProducts, ean: i64) -> Result<Json<ProductMetadata>, Forbidden<String>> {
     match product_metadata_get(ean).await {
         Ok(metadata) => Ok(Json(metadata)),
         Err(err) => Err(Forbidden(err.to_string())),
@@ -1419,19 +2959,10 @@ async fn web_product_metadata_get(_cookies: &CookieJar<'_>, ean: i64) -> Result<
 }
 
 #[post("/products/<ean>/metadata-set", format = "application/json", data = "<metadata>")]
-async fn web_product_metadata_set(cookies: &CookieJar<'_>, ean: i64, metadata: Json<ProductMetadata>) -> Result<Json<()>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
-
-    if !session.superuser && !session.auth_products {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
-
+async fn web_product_metadata_set(auth: RequireProducts, _csrf: CsrfChecked, ean: i64, metadata: Json<ProductMetadata>) -> Result<Json<()>, Forbidden<String>> {
     let metadata = metadata.into_inner();
 
-    match product_metadata_set(ean, metadata).await {
+    match product_metadata_set(auth.0.uid, ean, metadata).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(_) => {},
     };
@@ -1440,40 +2971,41 @@ async fn web_product_metadata_set(cookies: &CookieJar<'_>, ean: i64, metadata: J
 }
 
 #[get("/suppliers/order-suggestion")]
-async fn web_product_order_suggestion_step1(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-    let suppliers = get_supplier_list().await?;
+async fn web_product_order_suggestion_step1(auth: RequireProducts) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let suppliers = get_supplier_list(false).await?;
 
     Ok(Template::render("suppliers/order-suggestion-selection", context! { page: "suppliers/order-suggestion", session: session, suppliers: suppliers }))
 }
 
-#[get("/suppliers/<id>/order-suggestion")]
-async fn web_product_order_suggestion_step2(cookies: &CookieJar<'_>, id: i32) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+#[get("/suppliers/<id>/order-suggestion?<coverage>&<history>")]
+async fn web_product_order_suggestion_step2(auth: RequireProducts, id: i32, coverage: Option<u32>, history: Option<u32>) -> Result<Template, WebShopError> {
+    let session = auth.0;
     let supplier_name = get_supplier(id).await?.name;
+    let suggestion = get_order_suggestion(id, coverage.unwrap_or(0), history.unwrap_or(0)).await?;
 
-    Ok(Template::render("suppliers/order-suggestion", context! { page: "suppliers/order-suggestion", session: session, supplier: id, supplier_name: supplier_name }))
+    Ok(Template::render("suppliers/order-suggestion", context! { page: "suppliers/order-suggestion", session: session, supplier: id, supplier_name: supplier_name, suggestion: suggestion }))
 }
 
 #[get("/aliases")]
-async fn aliases(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn aliases(auth: RequireLogin) -> Result<Template, WebShopError> {
+    let session = auth.0;
     let list = ean_alias_list().await?;
 
     Ok(Template::render("aliases/index", context! { page: "aliases/index", session: session, list: list }))
 }
 
 #[get("/suppliers")]
-async fn suppliers(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-    let list = get_supplier_list().await?;
+async fn suppliers(auth: RequireLogin) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let list = get_supplier_list(true).await?;
 
     Ok(Template::render("suppliers/index", context! { page: "suppliers/index", session: session, list: list }))
 }
 
 #[get("/suppliers/list", format = "application/json")]
-async fn supplier_json_list(_cookies: &CookieJar<'_>) -> Result<Json<Vec<Supplier>>, Forbidden<String>> {
-    let list = match get_supplier_list().await {
+async fn supplier_json_list(_auth: RequireProducts) -> Result<Json<Vec<Supplier>>, Forbidden<String>> {
+    let list = match get_supplier_list(false).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(list) => list,
     };
@@ -1482,7 +3014,7 @@ async fn supplier_json_list(_cookies: &CookieJar<'_>) -> Result<Json<Vec<Supplie
 }
 
 #[get("/suppliers/<id>/product-list", format = "application/json")]
-async fn supplier_json_product_list(_cookies: &CookieJar<'_>, id: i32) -> Result<Json<Vec<ProductInfo>>, Forbidden<String>> {
+async fn supplier_json_product_list(_auth: RequireProducts, id: i32) -> Result<Json<Vec<ProductInfo>>, Forbidden<String>> {
     let list = match get_supplier_product_list(id).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(list) => list,
@@ -1492,7 +3024,7 @@ async fn supplier_json_product_list(_cookies: &CookieJar<'_>, id: i32) -> Result
 }
 
 #[get("/suppliers/<id>/restock-dates", format = "application/json")]
-async fn supplier_json_restock_dates(_cookies: &CookieJar<'_>, id: i32) -> Result<Json<Vec<i64>>, Forbidden<String>> {
+async fn supplier_json_restock_dates(_auth: RequireProducts, id: i32) -> Result<Json<Vec<i64>>, Forbidden<String>> {
     let list = match get_supplier_restock_dates(id).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(list) => list,
@@ -1502,47 +3034,307 @@ async fn supplier_json_restock_dates(_cookies: &CookieJar<'_>, id: i32) -> Resul
 }
 
 #[post("/suppliers/new", data = "<info>")]
-async fn web_suppliers_new(cookies: &CookieJar<'_>, info: Form<NewSupplier>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn web_suppliers_new(auth: RequireProducts, info: Form<NewSupplier>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    csrf_form_check(&session, &info.csrf_token)?;
 
-    if !session.superuser && !session.auth_products {
-        return Err(WebShopError::PermissionDenied());
-    }
-
-    add_supplier(&info.name.clone(), &info.postal_code, &info.city, &info.street, &info.phone, &info.website).await?;
+    add_supplier(session.uid, &info.name.clone(), &info.postal_code, &info.city, &info.street, &info.phone, &info.website).await?;
     Ok(Template::render("suppliers/new", context! { page: "suppliers/new", session: session, name: &info.name }))
 }
 
-#[get("/cashbox/status")]
-async fn cashbox_state(cookies: &CookieJar<'_>) -> Result<Json<i32>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
+#[get("/suppliers/<id>")]
+async fn supplier_details(auth: RequireLogin, id: i32) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let supplier = get_supplier(id).await?;
+    let catalogue = get_supplier_catalogue(id).await?;
 
-    if !session.superuser && !session.auth_cashbox {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
+    Ok(Template::render("suppliers/details", context! { page: "suppliers/details", session: session, supplier: supplier, catalogue: catalogue }))
+}
 
-    let cashbox_status = match cashbox_status().await {
+#[post("/suppliers/<id>", format = "application/json", data = "<data>")]
+async fn supplier_edit(auth: RequireProducts, _csrf: CsrfChecked, id: i32, data: Json<Supplier>) -> Result<Json<()>, Forbidden<String>> {
+    let supplier = Supplier { id: id as i64, ..data.into_inner() };
+
+    if supplier.name.trim().is_empty() {
+        return Err(Forbidden("supplier name must not be empty".to_string()));
+    }
+
+    match supplier_update(auth.0.uid, &supplier).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(())),
+    }
+}
+
+#[post("/suppliers/<id>/catalogue", format = "application/json", data = "<data>")]
+async fn supplier_catalogue_add(auth: RequireProducts, _csrf: CsrfChecked, id: i32, data: Json<SupplierCatalogueUpdate>) -> Result<Json<()>, Forbidden<String>> {
+    let ean = match ean_alias_get(data.ean).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(status) => status,
+        Ok(ean) => ean,
     };
 
-    Ok(Json(cashbox_status))
+    match supplier_catalogue_set(auth.0.uid, id, ean, data.article_number.trim(), data.price).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(())),
+    }
 }
 
-#[get("/cashbox/history")]
-async fn cashbox_history_json(cookies: &CookieJar<'_>) -> Result<Json<Vec<NamedCashboxDiff>>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
+#[post("/suppliers/<id>/catalogue/<ean>/remove")]
+async fn supplier_catalogue_delete(auth: RequireProducts, _csrf: CsrfChecked, id: i32, ean: i64) -> Result<Json<()>, Forbidden<String>> {
+    match supplier_catalogue_remove(auth.0.uid, id, ean).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(())),
+    }
+}
+
+#[get("/products/<ean>/catalogue")]
+async fn web_product_catalogue(_auth: RequireProducts, ean: i64) -> Result<Json<Vec<SupplierCatalogueEntry>>, Forbidden<String>> {
+    match get_product_catalogue(ean).await {
+        Ok(catalogue) => Ok(Json(catalogue)),
+        Err(err) => Err(Forbidden(err.to_string())),
+    }
+}
+
+#[get("/purchase-orders?<status>")]
+async fn purchase_orders(auth: RequireProducts, status: Option<String>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let status = status.unwrap_or_default();
+    let suppliers = get_supplier_list(true).await?;
+
+    let mut orders = Vec::new();
+    for order in get_purchase_orders(&status).await? {
+        let supplier_name = match suppliers.iter().find(|s| s.id == order.supplier as i64) {
+            Some(supplier) => supplier.name.clone(),
+            None => "Unknown".to_string(),
+        };
+        orders.push(NamedPurchaseOrder { order: order, supplier_name: supplier_name });
+    }
+
+    Ok(Template::render("suppliers/orders", context! { page: "suppliers/orders", session: session, orders: orders, suppliers: suppliers, status: status, states: ["draft", "ordered", "received", "cancelled"] }))
+}
+
+#[post("/purchase-orders", format = "application/json", data = "<data>")]
+async fn purchase_order_new(auth: RequireProducts, _csrf: CsrfChecked, data: Json<PurchaseOrderRequest>) -> Result<Json<i64>, Forbidden<String>> {
+    let items = data.items.iter().map(|item| (item.ean, item.amount)).collect();
+
+    match purchase_order_create(auth.0.uid, data.supplier, items).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(id) => Ok(Json(id)),
+    }
+}
+
+#[get("/purchase-orders/<id>")]
+async fn purchase_order(auth: RequireProducts, id: i64) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let order = get_purchase_order(id).await?;
+    let supplier = get_supplier(order.supplier).await?;
+    let items = get_purchase_order_items(id).await?;
+
+    Ok(Template::render("suppliers/order", context! { page: "suppliers/order", session: session, order: order, supplier: supplier, items: items }))
+}
+
+#[post("/purchase-orders/<id>", format = "application/json", data = "<data>")]
+async fn purchase_order_update(auth: RequireProducts, _csrf: CsrfChecked, id: i64, data: Json<PurchaseOrderUpdate>) -> Result<Json<()>, Forbidden<String>> {
+    let session = auth.0;
+
+    if let Some(comment) = &data.comment {
+        if let Err(error) = purchase_order_set_comment(session.uid, id, comment.trim()).await {
+            return Err(Forbidden(error.to_string()));
+        }
+    }
+
+    if let Some(status) = &data.status {
+        if let Err(error) = purchase_order_set_status(session.uid, id, status).await {
+            return Err(Forbidden(error.to_string()));
+        }
+    }
+
+    Ok(Json(()))
+}
+
+#[post("/purchase-orders/<id>/items", format = "application/json", data = "<data>")]
+async fn purchase_order_item_set(auth: RequireProducts, _csrf: CsrfChecked, id: i64, data: Json<PurchaseOrderItemUpdate>) -> Result<Json<()>, Forbidden<String>> {
+    let ean = match ean_alias_get(data.ean).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
+        Ok(ean) => ean,
     };
 
-    if !session.superuser && !session.auth_cashbox {
-        return Err(Forbidden("Missing Permission".to_string()));
+    match purchase_order_set_item(auth.0.uid, id, ean, data.amount, data.price).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(())),
+    }
+}
+
+#[post("/purchase-orders/<id>/receive", format = "application/json", data = "<data>")]
+async fn purchase_order_delivery(auth: RequireProducts, _csrf: CsrfChecked, id: i64, data: Json<Vec<PurchaseOrderDelivery>>) -> Result<Json<()>, Forbidden<String>> {
+    let mut deliveries = Vec::new();
+    for delivery in data.into_inner() {
+        let ean = match ean_alias_get(delivery.product).await {
+            Err(error) => { return Err(Forbidden(error.to_string())); },
+            Ok(ean) => ean,
+        };
+        deliveries.push(PurchaseOrderDelivery { product: ean, ..delivery });
+    }
+
+    match purchase_order_receive(auth.0.uid, id, deliveries).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(())),
+    }
+}
+
+#[get("/purchase-orders/<id>/export.csv")]
+async fn purchase_order_csv(_auth: RequireProducts, id: i64) -> Result<(ContentType, String), WebShopError> {
+    let items = get_purchase_order_items(id).await?;
+
+    let mut csv = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_writer(Vec::new());
+
+    csv.write_record(PURCHASE_ORDER_CSV_HEADER)?;
+    for item in items {
+        csv.write_record([
+            item.article_number,
+            item.product.ean.to_string(),
+            item.product.name,
+            item.amount.to_string(),
+            format_cent(item.price as i64),
+            format_cent(item.amount as i64 * item.price as i64),
+        ])?;
+    }
+
+    let data = csv.into_inner().map_err(|e| e.into_error())?;
+    Ok((ContentType::CSV, String::from_utf8(data)?))
+}
+
+fn purchase_order_render_text(ctx: &cairo::Context, x: f64, y: f64, w: i32, msg: &str, alignment: pango::Alignment, bold: bool) -> Result<(), WebShopError> {
+    ctx.save()?;
+    ctx.move_to(x, y);
+    ctx.set_source_rgb(0.0, 0.0, 0.0);
+
+    let layout = pangocairo::functions::create_layout(&ctx);
+
+    let mut font = pango::FontDescription::new();
+    font.set_family("Sans");
+    font.set_size(10 * pango::SCALE);
+    if bold {
+        font.set_weight(pango::Weight::Bold);
+    }
+    layout.set_font_description(Some(&font));
+    layout.set_alignment(alignment);
+    layout.set_ellipsize(pango::EllipsizeMode::End);
+    layout.set_width(w * pango::SCALE);
+    layout.set_text(msg);
+
+    pangocairo::functions::update_layout(ctx, &layout);
+    pangocairo::functions::show_layout(ctx, &layout);
+
+    ctx.restore()?;
+    Ok(())
+}
+
+fn purchase_order_render_row(ctx: &cairo::Context, y: f64, columns: [&str; 6], bold: bool) -> Result<(), WebShopError> {
+    purchase_order_render_text(ctx, 50.0, y, 70, columns[0], pango::Alignment::Left, bold)?;
+    purchase_order_render_text(ctx, 125.0, y, 90, columns[1], pango::Alignment::Left, bold)?;
+    purchase_order_render_text(ctx, 220.0, y, 150, columns[2], pango::Alignment::Left, bold)?;
+    purchase_order_render_text(ctx, 375.0, y, 50, columns[3], pango::Alignment::Right, bold)?;
+    purchase_order_render_text(ctx, 430.0, y, 55, columns[4], pango::Alignment::Right, bold)?;
+    purchase_order_render_text(ctx, 490.0, y, 55, columns[5], pango::Alignment::Right, bold)?;
+    Ok(())
+}
+
+fn purchase_order_render_document(order: &PurchaseOrder, supplier: &Supplier, items: &[PurchaseOrderItem]) -> Result<Vec<u8>, WebShopError> {
+    /* A4 sizes (in points, 72 DPI) */
+    let width  = 595.27559; /* 210mm */
+    let height = 841.88976; /* 297mm */
+    let rows_per_page = 30;
+
+    let buffer: std::io::Cursor<Vec<u8>> = Default::default();
+    let document = cairo::PdfSurface::for_stream(width, height, buffer)?;
+    let ctx = cairo::Context::new(&document)?;
+
+    let timestamp = if order.ordered > 0 { order.ordered } else { order.created };
+    let date = Local.timestamp_opt(timestamp, 0).single().map(|t| t.format("%Y-%m-%d").to_string()).unwrap_or_default();
+    let address = format!("{}\n{}\n{} {}\n{}", supplier.name, supplier.street, supplier.postal_code, supplier.city, supplier.phone);
+    let customer = match supplier.customer_number.is_empty() {
+        true => String::new(),
+        false => format!("Customer number: {}", supplier.customer_number),
+    };
+
+    let pages: Vec<&[PurchaseOrderItem]> = if items.is_empty() { vec![items] } else { items.chunks(rows_per_page).collect() };
+    let total_pages = pages.len();
+    let total: i64 = items.iter().map(|item| item.amount as i64 * item.price as i64).sum();
+
+    for (page, rows) in pages.iter().enumerate() {
+        if page > 0 {
+            ctx.show_page()?;
+        }
+
+        purchase_order_render_text(&ctx, 50.0, 50.0, 300, &format!("Purchase Order #{}", order.id), pango::Alignment::Left, true)?;
+        purchase_order_render_text(&ctx, 345.0, 50.0, 200, &date, pango::Alignment::Right, false)?;
+        purchase_order_render_text(&ctx, 345.0, 90.0, 200, &customer, pango::Alignment::Right, false)?;
+        purchase_order_render_text(&ctx, 50.0, 90.0, 300, &address, pango::Alignment::Left, false)?;
+        if !order.comment.is_empty() {
+            purchase_order_render_text(&ctx, 50.0, 170.0, 495, &order.comment, pango::Alignment::Left, false)?;
+        }
+
+        let mut y = 210.0;
+        purchase_order_render_row(&ctx, y, ["Art. No.", "EAN", "Product", "Amount", "Price", "Total"], true)?;
+        y += 20.0;
+
+        for item in rows.iter() {
+            let ean = item.product.ean.to_string();
+            let amount = item.amount.to_string();
+            let price = format!("{} €", format_cent(item.price as i64));
+            let line_total = format!("{} €", format_cent(item.amount as i64 * item.price as i64));
+            purchase_order_render_row(&ctx, y, [&item.article_number, &ean, &item.product.name, &amount, &price, &line_total], false)?;
+            y += 18.0;
+        }
+
+        if page + 1 == total_pages {
+            let total = format!("{} €", format_cent(total));
+            purchase_order_render_row(&ctx, y + 6.0, ["", "", "Total", "", "", &total], true)?;
+        }
+
+        let footer = format!("Page {} / {}", page + 1, total_pages);
+        purchase_order_render_text(&ctx, 345.0, height - 40.0, 200, &footer, pango::Alignment::Right, false)?;
+    }
+
+    document.flush();
+    let result = document.finish_output_stream();
+    match result {
+        Ok(boxedstream) => {
+            match boxedstream.downcast::<std::io::Cursor<Vec<u8>>>() {
+                Ok(buffer) => Ok(buffer.into_inner()),
+                Err(_err) => Err(WebShopError::UnboxError("Failed to unbox".to_string())),
+            }
+        },
+        Err(e) => {
+            Err(e.error.into())
+        },
     }
+}
+
+#[get("/purchase-orders/<id>/export.pdf")]
+async fn purchase_order_pdf(_auth: RequireProducts, id: i64) -> Result<(ContentType, Vec<u8>), WebShopError> {
+    let order = get_purchase_order(id).await?;
+    let supplier = get_supplier(order.supplier).await?;
+    let items = get_purchase_order_items(id).await?;
+    let pdfdata = purchase_order_render_document(&order, &supplier, &items)?;
+
+    Ok((ContentType::PDF, pdfdata))
+}
+
+#[get("/cashbox/status")]
+async fn cashbox_state(_auth: RequireCashbox) -> Result<Json<i32>, Forbidden<String>> {
+    let cashbox_status = match cashbox_status().await {
+        Err(error) => { return Err(Forbidden(error.to_string())); },
+        Ok(status) => status,
+    };
+
+    Ok(Json(cashbox_status))
+}
 
+#[get("/cashbox/history")]
+async fn cashbox_history_json(_auth: RequireCashbox) -> Result<Json<Vec<NamedCashboxEntry>>, Forbidden<String>> {
     let cashbox_history = match cashbox_history_named().await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(history) => history,
@@ -1552,52 +3344,279 @@ async fn cashbox_history_json(cookies: &CookieJar<'_>) -> Result<Json<Vec<NamedC
 }
 
 #[get("/cashbox")]
-async fn cashbox(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_cashbox {
-        return Err(WebShopError::PermissionDenied());
-    }
+async fn cashbox(auth: RequireCashbox) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
     let cashbox_history = cashbox_history_named().await?;
 
     Ok(Template::render("cashbox/index", context! { page: "cashbox/index", session: session, cashbox_history: cashbox_history }))
 }
 
-#[post("/cashbox/update", format = "application/json", data = "<data>")]
-async fn cashbox_update(cookies: &CookieJar<'_>, data: Json<CashboxUpdate>) -> Result<Json<()>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
+#[post("/cashbox/count", format = "application/json", data = "<data>")]
+async fn cashbox_count_add(auth: RequireCashbox, _csrf: CsrfChecked, data: Json<CashboxCountRequest>) -> Result<Json<i64>, Forbidden<String>> {
+    match cashbox_count(auth.0.uid, data.counted, data.comment.trim()).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(id) => Ok(Json(id)),
+    }
+}
+
+#[get("/cashbox/counts?<from>&<to>")]
+async fn cashbox_counts(auth: RequireCashbox, from: Option<String>, to: Option<String>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let today = Local::now().date_naive();
+    let from = parse_date(from, today - chrono::Duration::days(365));
+    let to = parse_date(to, today);
+
+    let mut counts = Vec::new();
+    for count in get_cashbox_counts(date_timestamp(from), date_timestamp(to + chrono::Duration::days(1)) - 1).await? {
+        counts.push(cashbox_count_named(count).await?);
+    }
+
+    Ok(Template::render("cashbox/counts", context! {
+        page: "cashbox/counts",
+        session: session,
+        counts: counts,
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+    }))
+}
+
+#[get("/cashbox/counts/<id>")]
+async fn cashbox_count_report(auth: RequireCashbox, id: i64) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let count = cashbox_count_named(get_cashbox_count(id).await?).await?;
+
+    Ok(Template::render("cashbox/count", context! { page: "cashbox/count", session: session, count: count }))
+}
+
+const CASHBOX_LEDGER_PAGE_SIZE: u32 = 50;
+const CASHBOX_LEDGER_HEADER: [&str; 7] = ["id", "timestamp", "type", "user", "name", "amount", "balance"];
+
+/* ledger period given as YYYY-MM-DD dates, defaults to the current year */
+fn cashbox_ledger_period(from: Option<String>, to: Option<String>) -> (NaiveDate, NaiveDate) {
+    let today = Local::now().date_naive();
+    let from = parse_date(from, today.with_ordinal(1).unwrap_or(today));
+    let to = parse_date(to, today);
+    (from, to)
+}
+
+#[get("/cashbox/ledger?<from>&<to>&<entry_type>&<page>")]
+async fn cashbox_ledger(auth: RequireCashbox, from: Option<String>, to: Option<String>, entry_type: Option<String>, page: Option<u32>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let (from, to) = cashbox_ledger_period(from, to);
+    let entry_type = entry_type.unwrap_or_default();
+    let page = page.unwrap_or(1).max(1);
+
+    let (total, entries) = cashbox_ledger_named(date_timestamp(from), date_timestamp(to + chrono::Duration::days(1)) - 1, &entry_type, (page - 1) * CASHBOX_LEDGER_PAGE_SIZE, CASHBOX_LEDGER_PAGE_SIZE).await?;
+    let pages = total.div_ceil(CASHBOX_LEDGER_PAGE_SIZE).max(1);
+    let types: Vec<(&str, &str)> = ["withdrawal", "deposit", "loss", "donation", "count", "guest_sale"].iter()
+        .map(|t| (*t, cashbox_entry_type_name(t))).collect();
+
+    Ok(Template::render("cashbox/ledger", context! {
+        page: "cashbox/ledger",
+        session: session,
+        entries: entries,
+        total: total,
+        current_page: page,
+        pages: pages,
+        types: types,
+        entry_type: entry_type,
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+    }))
+}
+
+#[get("/cashbox/ledger.csv?<from>&<to>&<entry_type>")]
+async fn cashbox_ledger_csv(_auth: RequireCashbox, from: Option<String>, to: Option<String>, entry_type: Option<String>) -> Result<(ContentType, String), WebShopError> {
+    let (from, to) = cashbox_ledger_period(from, to);
+    let (_, mut entries) = cashbox_ledger_named(date_timestamp(from), date_timestamp(to + chrono::Duration::days(1)) - 1, &entry_type.unwrap_or_default(), 0, 0).await?;
+    entries.reverse();
+
+    let mut csv = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_writer(Vec::new());
+
+    csv.write_record(CASHBOX_LEDGER_HEADER)?;
+    for entry in entries {
+        let time = Local.timestamp_opt(entry.timestamp, 0).single().map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default();
+        csv.write_record([
+            entry.id.to_string(),
+            time,
+            entry.entry_type,
+            entry.user.to_string(),
+            entry.username,
+            format_cent(entry.amount as i64),
+            format_cent(entry.balance),
+        ])?;
+    }
+
+    let data = csv.into_inner().map_err(|e| e.into_error())?;
+    Ok((ContentType::CSV, String::from_utf8(data)?))
+}
+
+#[get("/cashbox/ledger.ods?<from>&<to>&<entry_type>")]
+async fn cashbox_ledger_ods(_auth: RequireCashbox, from: Option<String>, to: Option<String>, entry_type: Option<String>) -> Result<(ContentType, Vec<u8>), WebShopError> {
+    let (from, to) = cashbox_ledger_period(from, to);
+    let (_, mut entries) = cashbox_ledger_named(date_timestamp(from), date_timestamp(to + chrono::Duration::days(1)) - 1, &entry_type.unwrap_or_default(), 0, 0).await?;
+    entries.reverse();
+
+    let mut rows = vec![CASHBOX_LEDGER_HEADER.iter().map(|h| OdsCell::Text(h.to_string())).collect::<Vec<_>>()];
+    for entry in entries {
+        let time = Local.timestamp_opt(entry.timestamp, 0).single().map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default();
+        rows.push(vec![
+            OdsCell::Integer(entry.id),
+            OdsCell::Text(time),
+            OdsCell::Text(entry.entry_type),
+            OdsCell::Integer(entry.user as i64),
+            OdsCell::Text(entry.username),
+            OdsCell::Euro(entry.amount as i64),
+            OdsCell::Euro(entry.balance),
+        ]);
+    }
+
+    let name = format!("Cashbox {} - {}", from.format("%Y-%m-%d"), to.format("%Y-%m-%d"));
+    Ok((ContentType::new("application", "vnd.oasis.opendocument.spreadsheet"), ods_spreadsheet(&name, &rows)))
+}
+
+#[get("/cashbox/report?<from>&<to>")]
+async fn cashbox_report(auth: RequireCashbox, from: Option<String>, to: Option<String>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let today = Local::now().date_naive();
+    let from = parse_date(from, today.with_day(1).unwrap_or(today));
+    let to = parse_date(to, today);
+
+    let (products, categories, total) = profit_report(date_timestamp(from), date_timestamp(to + chrono::Duration::days(1)) - 1).await?;
+
+    Ok(Template::render("cashbox/report", context! {
+        page: "cashbox/report",
+        session: session,
+        products: products,
+        categories: categories,
+        total: total,
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+    }))
+}
+
+#[get("/cashbox/report.csv?<from>&<to>&<group>")]
+async fn cashbox_report_csv(_auth: RequireCashbox, from: Option<String>, to: Option<String>, group: Option<String>) -> Result<(ContentType, String), WebShopError> {
+    let today = Local::now().date_naive();
+    let from = parse_date(from, today.with_day(1).unwrap_or(today));
+    let to = parse_date(to, today);
+
+    let (products, categories, total) = profit_report(date_timestamp(from), date_timestamp(to + chrono::Duration::days(1)) - 1).await?;
+    let lines = match group.as_deref() {
+        Some("category") => categories,
+        _ => products,
     };
 
-    if !session.superuser && !session.auth_cashbox {
-        return Err(Forbidden("Missing Permission".to_string()));
+    let mut csv = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_writer(Vec::new());
+
+    csv.write_record(PROFIT_CSV_HEADER)?;
+    for line in lines.iter().chain(std::iter::once(&total)) {
+        csv.write_record(line.to_record())?;
+    }
+
+    let data = csv.into_inner().map_err(|e| e.into_error())?;
+    Ok((ContentType::CSV, String::from_utf8(data)?))
+}
+
+#[get("/cashbox/stock-value?<date>")]
+async fn cashbox_stock_value(auth: RequireCashbox, date: Option<String>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let date = parse_date(date, Local::now().date_naive());
+
+    let stock = get_stock_value(date_timestamp(date + chrono::Duration::days(1)) - 1).await?;
+    let total: i64 = stock.iter().map(|entry| entry.value).sum();
+
+    Ok(Template::render("cashbox/stockvalue", context! {
+        page: "cashbox/stockvalue",
+        session: session,
+        stock: stock,
+        total: total,
+        date: date.format("%Y-%m-%d").to_string(),
+    }))
+}
+
+#[get("/cashbox/stock-value.csv?<date>")]
+async fn cashbox_stock_value_csv(_auth: RequireCashbox, date: Option<String>) -> Result<(ContentType, String), WebShopError> {
+    let date = parse_date(date, Local::now().date_naive());
+    let stock = get_stock_value(date_timestamp(date + chrono::Duration::days(1)) - 1).await?;
+
+    let mut csv = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_writer(Vec::new());
+
+    csv.write_record(["category", "ean", "product", "amount", "value"])?;
+    for entry in &stock {
+        csv.write_record([
+            entry.category.clone(),
+            entry.product.ean.to_string(),
+            entry.product.name.clone(),
+            entry.amount.to_string(),
+            format_cent(entry.value),
+        ])?;
     }
+    let total: i64 = stock.iter().map(|entry| entry.value).sum();
+    csv.write_record(["", "", "Total", "", format_cent(total).as_str()])?;
+
+    let data = csv.into_inner().map_err(|e| e.into_error())?;
+    Ok((ContentType::CSV, String::from_utf8(data)?))
+}
+
+#[get("/cashbox/deposits?<supplier>&<from>&<to>")]
+async fn cashbox_deposits(auth: RequireCashbox, supplier: Option<i32>, from: Option<String>, to: Option<String>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let supplier = supplier.unwrap_or(0);
+    let (from, to) = cashbox_ledger_period(from, to);
+
+    let overview = get_deposit_overview().await?;
+    let deposits = get_supplier_deposits_named(supplier, date_timestamp(from), date_timestamp(to + chrono::Duration::days(1)) - 1).await?;
+    let suppliers = get_supplier_list(false).await?;
+
+    Ok(Template::render("cashbox/deposits", context! {
+        page: "cashbox/deposits",
+        session: session,
+        overview: overview,
+        deposits: deposits,
+        suppliers: suppliers,
+        supplier: supplier,
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+    }))
+}
+
+#[post("/cashbox/deposits", format = "application/json", data = "<data>")]
+async fn cashbox_deposit_return(auth: RequireCashbox, _csrf: CsrfChecked, data: Json<SupplierDepositReturn>) -> Result<Json<i64>, Forbidden<String>> {
+    let session = auth.0;
+
+    match supplier_deposit_return(session.uid, data.supplier, data.amount, data.comment.trim()).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(id) => Ok(Json(id)),
+    }
+}
 
+async fn cashbox_update_helper(session: &Session, data: &CashboxUpdate) -> zbus::Result<()> {
     let now = chrono::offset::Local::now().timestamp();
-    let amount = if data.amount >= 0 { data.amount } else { -data.amount };
 
-    let result = match data.update_type {
-        CashboxUpdateType::Withdrawal => cashbox_add(session.uid, -amount, now),
-        CashboxUpdateType::Deposit => cashbox_add(session.uid, amount, now),
-        CashboxUpdateType::Loss => cashbox_add(-3, -amount, now),
-        CashboxUpdateType::Donation => cashbox_add(-3, amount, now),
-    }.await;
+    cashbox_add(session.uid, session.uid, data.update_type.entry_type(), data.amount, now).await
+}
+
+#[post("/cashbox/update", format = "application/json", data = "<data>")]
+async fn cashbox_update(auth: RequireCashbox, _csrf: CsrfChecked, data: Json<CashboxUpdate>) -> Result<Json<()>, Forbidden<String>> {
+    let session = auth.0;
 
-    match result {
+    match cashbox_update_helper(&session, &data).await {
         Err(error) => Err(Forbidden(error.to_string())),
         Ok(_) => Ok(Json(())),
     }
 }
 
 #[get("/cashbox/details/<year>/<month>")]
-async fn cashbox_details(cookies: &CookieJar<'_>, year: i32, month: u32) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_cashbox {
-        return Err(WebShopError::PermissionDenied());
-    }
+async fn cashbox_details(auth: RequireCashbox, year: i32, month: u32) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
     let now = chrono::offset::Local::now();
     let year = if year <= 0 || year > 10000 { now.year() } else { year };
@@ -1610,57 +3629,34 @@ async fn cashbox_details(cookies: &CookieJar<'_>, year: i32, month: u32) -> Resu
     let start = start.timestamp();
     let stop = stop.timestamp();
 
-    let changes = cashbox_changes(start, stop).await?;
-    let invoice = get_invoice(0, start, stop).await?;
-
-    let mut debit = 0;
-    for e in invoice {
-        debit += e.price;
-    }
-
-    let mut withdrawal_list = Vec::new();
-    let mut donation_list = Vec::new();
-    let mut loss_list = Vec::new();
-    let mut loss = 0;
-    let mut donation = 0;
-    let mut withdrawal = 0;
-    for change in changes {
-        if change.user == -3 && change.amount < 0 {
-            loss += change.amount;
-            loss_list.push(change);
-        } else if change.user == -3 {
-            donation += change.amount;
-            donation_list.push(change);
-        } else {
-            withdrawal += change.amount;
-            withdrawal_list.push(NamedCashboxDiff {
-                username: get_username(change.user).await.expect("failed to get username"),
-                amount: change.amount,
-                timestamp: change.timestamp,
-            });
+    let (_, mut entries) = cashbox_ledger_named(start, stop - 1, "", 0, 0).await?;
+    entries.reverse();
+
+    let mut totals: Vec<(String, i32)> = ["guest_sale", "withdrawal", "deposit", "loss", "donation", "count"].iter()
+        .map(|t| (cashbox_entry_type_name(t).to_string(), 0)).collect();
+    for entry in &entries {
+        if let Some(total) = totals.iter_mut().find(|(name, _)| *name == entry.type_name) {
+            total.1 += entry.amount;
         }
     }
+    entries.retain(|entry| entry.entry_type != "guest_sale");
 
-    Ok(Template::render("cashbox/details", context! { page: "cashbox/details", session: session, debit: debit, loss: loss, donation: donation, withdrawal: withdrawal, month: monthname, year: year, withdrawal_list: withdrawal_list, donation_list: donation_list, loss_list: loss_list }))
+    Ok(Template::render("cashbox/details", context! { page: "cashbox/details", session: session, month: monthname, year: year, totals: totals, entries: entries }))
 }
 
 #[get("/users")]
-async fn users(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_users {
-        return Err(WebShopError::PermissionDenied());
-    }
+async fn users(auth: RequireUsers) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
     let userlist = get_user_list(false).await?;
     Ok(Template::render("users/index", context! { page: "users/index", session: session, list: userlist }))
 }
 
 #[get("/users/<id>")]
-async fn user_info(cookies: &CookieJar<'_>, id: i32) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn user_info(auth: RequireLogin, id: i32) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
-    if !session.superuser && !session.auth_users && id != session.uid {
+    if !session.may_access_user(id) {
         return Err(WebShopError::PermissionDenied());
     }
 
@@ -1874,16 +3870,7 @@ async fn barcodelist_render_document(users: &Vec<BarcodelistUser>) -> Result<Vec
 }
 
 #[get("/users/barcodelist.pdf")]
-async fn user_barcodelist(cookies: &CookieJar<'_>) -> Result<(ContentType, Vec<u8>), WebShopError> {
-    let session = match get_session(cookies).await {
-        Err(_err) => { return Err(WebShopError::PermissionDenied()); },
-        Ok(session) => session,
-    };
-
-    if !session.superuser && !session.auth_users {
-        return Err(WebShopError::PermissionDenied());
-    }
-
+async fn user_barcodelist(_auth: RequireUsers) -> Result<(ContentType, Vec<u8>), WebShopError> {
     let users = barcodelist_get_users().await?;
     let pdfdata = barcodelist_render_document(&users).await?;
 
@@ -1891,7 +3878,11 @@ async fn user_barcodelist(cookies: &CookieJar<'_>) -> Result<(ContentType, Vec<u
 }
 
 #[get("/users/<id>/barcode.svg")]
-async fn user_barcode(_cookies: &CookieJar<'_>, id: i32) -> Result<(ContentType, String), WebShopError> {
+async fn user_barcode(auth: RequireLogin, id: i32) -> Result<(ContentType, String), WebShopError> {
+    if !auth.0.may_access_user(id) {
+        return Err(WebShopError::PermissionDenied());
+    }
+
     let barcodesvg = SVG::new(100)
         .xdim(2)
         .xmlns("http://www.w3.org/2000/svg".to_string())
@@ -1934,63 +3925,320 @@ async fn user_barcode(_cookies: &CookieJar<'_>, id: i32) -> Result<(ContentType,
     }
 }
 
-#[post("/users/set-sound-theme/<userid>", format = "application/json", data = "<theme>")]
-async fn user_sound_theme_set(cookies: &CookieJar<'_>, userid: i32, theme: Json<String>) -> Result<Json<bool>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
+/* shelf labels are rendered at 105mm x 74mm (A7 landscape) and scaled to the target size */
+const SHELFLABEL_WIDTH: f64 = 297.63780;
+const SHELFLABEL_HEIGHT: f64 = 209.76378;
 
-    if !session.superuser && !session.auth_users && userid != session.uid {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
+struct ShelfLabel {
+    ean: i64,
+    name: String,
+    memberprice: i32,
+    guestprice: i32,
+    metadata: Option<ProductMetadata>,
+    barcode: String,
+}
 
-    let theme = theme.into_inner();
+/* EAN-13 or EAN-8 barcode, the check digit is calculated by barcoders */
+fn shelflabel_barcode(ean: i64) -> Result<String, WebShopError> {
+    let svg = SVG::new(60)
+        .xdim(2)
+        .foreground(Color::black())
+        .background(Color::white());
 
-    match set_user_theme(userid, &theme).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(_) => {},
+    let barcodedata = if check_valid_gtin(ean, 8) {
+        EAN8::new(&format!("{:08}", ean)[..7])?.encode()
+    } else {
+        EAN13::new(&format!("{:013}", ean)[..12])?.encode()
     };
 
-    Ok(Json(true))
+    Ok(svg.generate(&barcodedata)?)
 }
 
-#[post("/users/set-password/<userid>", format = "application/json", data = "<password>")]
-async fn user_password_set(cookies: &CookieJar<'_>, userid: i32, password: Json<String>) -> Result<Json<bool>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
+/* label with the prices valid at the given time */
+async fn shelflabel_get(ean: i64, timestamp: i64) -> Result<ShelfLabel, WebShopError> {
+    let ean = ean_alias_get(ean).await?;
+    let prices = get_prices(ean).await?;
+    let (memberprice, guestprice) = match prices.iter().filter(|p| p.timestamp <= timestamp).last() {
+        Some(price) => (price.memberprice, price.guestprice),
+        None => (0, 0),
     };
 
-    if !session.superuser && !session.auth_users && userid != session.uid {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
+    Ok(ShelfLabel {
+        ean: ean,
+        name: get_product_name(ean).await?,
+        memberprice: memberprice,
+        guestprice: guestprice,
+        metadata: product_metadata_get(ean).await.ok(),
+        barcode: shelflabel_barcode(ean)?,
+    })
+}
 
-    let password = password.into_inner();
+/* renders left aligned text and returns the y position below it, lines limits the number of lines */
+fn shelflabel_render_text(ctx: &cairo::Context, x: f64, y: f64, w: i32, size: i32, bold: bool, lines: i32, msg: &str) -> Result<f64, WebShopError> {
+    ctx.save()?;
+    ctx.move_to(x, y);
+    ctx.set_source_rgb(0.0, 0.0, 0.0);
 
-    if password.is_empty() {
-        return Ok(Json(false));
+    let layout = pangocairo::functions::create_layout(&ctx);
+
+    let mut font = pango::FontDescription::new();
+    font.set_family("LMRoman12");
+    font.set_size(size * pango::SCALE);
+    if bold {
+        font.set_weight(pango::Weight::Bold);
     }
+    layout.set_font_description(Some(&font));
 
-    match set_user_password(userid, &password).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(_) => {},
-    };
+    layout.set_alignment(pango::Alignment::Left);
+    layout.set_wrap(pango::WrapMode::WordChar);
+    layout.set_ellipsize(pango::EllipsizeMode::End);
+    layout.set_height(-lines);
+    layout.set_width(w * pango::SCALE);
+    layout.set_text(msg);
 
-    Ok(Json(true))
+    pangocairo::functions::update_layout(ctx, &layout);
+    pangocairo::functions::show_layout(ctx, &layout);
+    let (_, height) = layout.pixel_size();
+
+    ctx.restore()?;
+    Ok(y + height as f64)
 }
 
-#[get("/users/toggle-auth/<userid>/<permission>")]
-async fn user_toggle_auth(cookies: &CookieJar<'_>, userid: i32, permission: String) -> Result<Json<UserAuth>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
+fn shelflabel_nutrition(metadata: &ProductMetadata) -> String {
+    let unit = if metadata.product_size_is_weight { "g" } else { "ml" };
+    let mut result = String::new();
+
+    if metadata.product_size > 0 {
+        result.push_str(&format!("{} {}\n", metadata.product_size, unit));
+    }
+    result.push_str(&format!("Per 100 {}: {} kcal, carbohydrates {} g, fats {} g, proteins {} g", unit, metadata.calories, metadata.carbohydrates, metadata.fats, metadata.proteins));
+    if !metadata.allergens.is_empty() {
+        result.push_str(&format!("\nAllergens: {}", metadata.allergens));
+    }
+
+    result
+}
+
+/* price per litre or kilogram, e.g. "4.55 €/l, guests 6.06 €/l" */
+fn shelflabel_unit_price(metadata: &ProductMetadata, memberprice: i32, guestprice: i32) -> Option<String> {
+    if metadata.product_size == 0 {
+        return None;
+    }
+
+    let size = metadata.product_size as i64;
+    let unit = if metadata.product_size_is_weight { "kg" } else { "l" };
+    let per_unit = |price: i32| (price as i64 * 1000 + size / 2) / size;
+
+    Some(format!("{} €/{}, guests {} €/{}", format_cent(per_unit(memberprice)), unit, format_cent(per_unit(guestprice)), unit))
+}
+
+fn shelflabel_render(ctx: &cairo::Context, rect: &cairo::Rectangle, label: &ShelfLabel) -> Result<(), WebShopError> {
+    ctx.save()?;
+
+    /* keep the aspect ratio, so that the barcode stays readable, and center the label in the given area */
+    let scale = f64::min(rect.width() / SHELFLABEL_WIDTH, rect.height() / SHELFLABEL_HEIGHT);
+    ctx.translate(rect.x() + (rect.width() - SHELFLABEL_WIDTH * scale) / 2.0, rect.y() + (rect.height() - SHELFLABEL_HEIGHT * scale) / 2.0);
+    ctx.scale(scale, scale);
+
+    /* cutting border */
+    ctx.set_source_rgb(0.7, 0.7, 0.7);
+    ctx.set_line_width(0.5);
+    ctx.rectangle(0.0, 0.0, SHELFLABEL_WIDTH, SHELFLABEL_HEIGHT);
+    ctx.stroke()?;
+
+    shelflabel_render_text(ctx, 12.0, 10.0, 273, 15, true, 2, &label.name)?;
+
+    let y = shelflabel_render_text(ctx, 12.0, 52.0, 160, 30, true, 1, &format!("{} €", format_cent(label.memberprice as i64)))?;
+    let mut y = shelflabel_render_text(ctx, 12.0, y, 160, 11, false, 1, &format!("Guests {} €", format_cent(label.guestprice as i64)))?;
+
+    if let Some(metadata) = &label.metadata {
+        if let Some(unitprice) = shelflabel_unit_price(metadata, label.memberprice, label.guestprice) {
+            y = shelflabel_render_text(ctx, 12.0, y, 160, 9, false, 1, &unitprice)?;
+        }
+        if metadata.deposit > 0 {
+            y = shelflabel_render_text(ctx, 12.0, y, 160, 9, false, 1, &format!("plus {} € deposit", format_cent(metadata.deposit as i64)))?;
+        }
+        shelflabel_render_text(ctx, 12.0, y + 6.0, 150, 7, false, 6, &shelflabel_nutrition(metadata))?;
+    }
+
+    let barcode = cairo::Rectangle::new(172.0, 120.0, 113.0, 80.0);
+    barcodelist_render_svg(ctx, &barcode, &label.barcode)?;
+    ctx.set_source_rgb(0.0, 0.0, 0.0);
+    ctx.select_font_face("LMRoman12", cairo::FontSlant::Normal, cairo::FontWeight::Normal);
+    ctx.set_font_size(8.0);
+    ctx.move_to(172.0, 200.0);
+    ctx.show_text(&format!("{:013}", label.ean))?;
+
+    ctx.restore()?;
+    Ok(())
+}
+
+async fn shelflabel_render_document(label: &ShelfLabel) -> Result<Vec<u8>, WebShopError> {
+    let buffer: std::io::Cursor<Vec<u8>> = Default::default();
+    let document = cairo::PdfSurface::for_stream(SHELFLABEL_WIDTH, SHELFLABEL_HEIGHT, buffer)?;
+    let ctx = cairo::Context::new(&document)?;
+
+    let rect = cairo::Rectangle::new(0.0, 0.0, SHELFLABEL_WIDTH, SHELFLABEL_HEIGHT);
+    shelflabel_render(&ctx, &rect, label)?;
+
+    document.flush();
+    let result = document.finish_output_stream();
+    match result {
+        Ok(boxedstream) => {
+            match boxedstream.downcast::<std::io::Cursor<Vec<u8>>>() {
+                Ok(buffer) => Ok(buffer.into_inner()),
+                Err(_err) => Err(WebShopError::UnboxError("Failed to unbox".to_string())),
+            }
+        },
+        Err(e) => {
+            Err(e.error.into())
+        },
+    }
+}
+
+/* A4 sheet with a grid of labels, the margin is given in mm */
+async fn shelflabel_render_sheet(labels: &Vec<ShelfLabel>, columns: u32, rows: u32, margin: f64) -> Result<Vec<u8>, WebShopError> {
+    /* A4 sizes (in points, 72 DPI) */
+    let width  = 595.27559; /* 210mm */
+    let height = 841.88976; /* 297mm */
+    let margin = margin * 72.0 / 25.4;
+    let cell_width = (width - 2.0 * margin) / columns as f64;
+    let cell_height = (height - 2.0 * margin) / rows as f64;
+    let per_page = columns * rows;
+
+    let buffer: std::io::Cursor<Vec<u8>> = Default::default();
+    let document = cairo::PdfSurface::for_stream(width, height, buffer)?;
+    let ctx = cairo::Context::new(&document)?;
+
+    if labels.is_empty() {
+        ctx.move_to(margin, margin + 12.0);
+        ctx.show_text("No products selected")?;
+    }
+
+    for (position, label) in labels.iter().enumerate() {
+        let position = position as u32;
+        if position > 0 && position % per_page == 0 {
+            ctx.show_page()?;
+        }
+
+        let col = position % columns;
+        let row = (position / columns) % rows;
+        let rect = cairo::Rectangle::new(margin + col as f64 * cell_width, margin + row as f64 * cell_height, cell_width, cell_height);
+        shelflabel_render(&ctx, &rect, label)?;
+    }
+
+    document.flush();
+    let result = document.finish_output_stream();
+    match result {
+        Ok(boxedstream) => {
+            match boxedstream.downcast::<std::io::Cursor<Vec<u8>>>() {
+                Ok(buffer) => Ok(buffer.into_inner()),
+                Err(_err) => Err(WebShopError::UnboxError("Failed to unbox".to_string())),
+            }
+        },
+        Err(e) => {
+            Err(e.error.into())
+        },
+    }
+}
+
+#[get("/products/shelf-labels")]
+async fn product_shelf_labels(auth: RequireProducts) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let today = Local::now().date_naive();
+
+    Ok(Template::render("products/shelflabels", context! {
+        page: "products/shelflabels",
+        session: session,
+        products: get_stock().await?,
+        date: today.format("%Y-%m-%d").to_string(),
+    }))
+}
+
+/* labels for the listed EANs and all products with a price change between since and date */
+#[get("/products/shelf-labels.pdf?<eans>&<since>&<date>&<columns>&<rows>&<margin>")]
+async fn product_shelf_labels_pdf(_auth: RequireProducts, eans: Option<String>, since: Option<String>, date: Option<String>, columns: Option<u32>, rows: Option<u32>, margin: Option<u32>) -> Result<(ContentType, Vec<u8>), WebShopError> {
+    let date = parse_date(date, Local::now().date_naive());
+    let timestamp = date_timestamp(date + chrono::Duration::days(1)) - 1;
+
+    let mut products: Vec<i64> = eans.unwrap_or_default()
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter_map(|ean| ean.trim().parse().ok())
+        .collect();
+
+    if let Some(since) = since.filter(|since| !since.is_empty()) {
+        let since = parse_date(Some(since), date);
+        for change in get_price_changes(date_timestamp(since), timestamp).await? {
+            products.push(change.product.ean);
+        }
+    }
+
+    let mut labels = Vec::new();
+    for ean in products {
+        let label = shelflabel_get(ean, timestamp).await?;
+        if !labels.iter().any(|l: &ShelfLabel| l.ean == label.ean) {
+            labels.push(label);
+        }
+    }
+
+    let columns = columns.unwrap_or(2).clamp(1, 6);
+    let rows = rows.unwrap_or(4).clamp(1, 12);
+    let margin = margin.unwrap_or(10).min(30) as f64;
+    let pdfdata = shelflabel_render_sheet(&labels, columns, rows, margin).await?;
+
+    Ok((ContentType::PDF, pdfdata))
+}
+
+#[get("/products/<ean>/shelf-label.pdf")]
+async fn product_shelf_label(_auth: RequireLogin, ean: i64) -> Result<(ContentType, Vec<u8>), WebShopError> {
+    let label = shelflabel_get(ean, get_unix_time()).await?;
+    let pdfdata = shelflabel_render_document(&label).await?;
+
+    Ok((ContentType::PDF, pdfdata))
+}
+
+#[post("/users/set-sound-theme/<userid>", format = "application/json", data = "<theme>")]
+async fn user_sound_theme_set(auth: RequireLogin, _csrf: CsrfChecked, userid: i32, theme: Json<String>) -> Result<Json<bool>, Forbidden<String>> {
+    let session = auth.0;
+
+    if !session.may_access_user(userid) {
+        return Err(Forbidden("Missing Permission".to_string()));
+    }
+
+    let theme = theme.into_inner();
+
+    match set_user_theme(session.uid, userid, &theme).await {
+        Err(error) => { return Err(Forbidden(error.to_string())); },
+        Ok(_) => {},
     };
 
-    if !session.superuser && !session.auth_users {
+    Ok(Json(true))
+}
+
+#[post("/users/set-password/<userid>", format = "application/json", data = "<password>")]
+async fn user_password_set(auth: RequireLogin, _csrf: CsrfChecked, userid: i32, password: Json<String>) -> Result<Json<bool>, Forbidden<String>> {
+    let session = auth.0;
+
+    if !session.may_access_user(userid) {
         return Err(Forbidden("Missing Permission".to_string()));
     }
 
+    let password = password.into_inner();
+
+    if password.is_empty() {
+        return Ok(Json(false));
+    }
+
+    match set_user_password(session.uid, userid, &password).await {
+        Err(error) => { return Err(Forbidden(error.to_string())); },
+        Ok(_) => {},
+    };
+
+    Ok(Json(true))
+}
+
+#[post("/users/toggle-auth/<userid>/<permission>")]
+async fn user_toggle_auth(auth: RequireUsers, _csrf: CsrfChecked, userid: i32, permission: String) -> Result<Json<UserAuth>, Forbidden<String>> {
     let mut userauth = match get_user_auth(userid).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(userauth) => userauth,
@@ -2003,7 +4251,7 @@ async fn user_toggle_auth(cookies: &CookieJar<'_>, userid: i32, permission: Stri
         _ => { return Err(Forbidden("Invalid Parameter".to_string())); },
     };
 
-    match set_user_auth(userauth).await {
+    match set_user_auth(auth.0.uid, userauth).await {
         Err(error) => { return Err(Forbidden(error.to_string())); },
         Ok(_) => {},
     };
@@ -2028,10 +4276,10 @@ pub fn get_days_from_month(year: i32, month: u32) -> u32 {
 }
 
 #[get("/users/<user_id>/invoice/<year>/<month>/<day>")]
-async fn user_invoice_full(cookies: &CookieJar<'_>, user_id: i32, year: i32, month: u32, day: u32) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn user_invoice_full(auth: RequireLogin, user_id: i32, year: i32, month: u32, day: u32) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
-    if !session.superuser && !session.auth_users && user_id != session.uid {
+    if !session.may_access_user(user_id) {
         return Err(WebShopError::PermissionDenied());
     }
 
@@ -2133,16 +4381,146 @@ async fn user_invoice_full(cookies: &CookieJar<'_>, user_id: i32, year: i32, mon
 }
 
 #[get("/users/<user_id>/invoice")]
-async fn user_invoice(cookies: &CookieJar<'_>, user_id: i32) -> Result<Template, WebShopError> {
+async fn user_invoice(auth: RequireLogin, user_id: i32) -> Result<Template, WebShopError> {
+    let now = chrono::offset::Local::now();
+    user_invoice_full(auth, user_id, now.year(), now.month(), now.day()).await
+}
+
+#[get("/users/<user_id>/invoices")]
+async fn user_invoices(auth: RequireLogin, user_id: i32) -> Result<Template, WebShopError> {
+    let session = auth.0;
+
+    if !session.may_access_user(user_id) {
+        return Err(WebShopError::PermissionDenied());
+    }
+
+    let invoices = get_user_invoices(user_id).await?;
+
+    Ok(Template::render("users/invoices", context! { page: "users/invoices", session: session, user_id: user_id, invoices: invoices }))
+}
+
+#[get("/users/<user_id>/invoices/<invoice_id>/invoice.pdf")]
+async fn user_invoice_pdf(auth: RequireLogin, user_id: i32, invoice_id: &str, config: &State<WebConfig>) -> Result<(ContentType, Vec<u8>), WebShopError> {
+    let session = auth.0;
+
+    if !session.may_access_user(user_id) {
+        return Err(WebShopError::PermissionDenied());
+    }
+
+    let invoice = get_invoice_info(invoice_id).await?;
+
+    /* do not allow access to invoices of other users via a foreign user ID */
+    if invoice.user != user_id {
+        return Err(WebShopError::PermissionDenied());
+    }
+
+    /* an issued invoice must not change, so the PDF is served as it has been mailed */
+    let pdfdata = get_invoice_pdf(&invoice.id).await?;
+    if !pdfdata.is_empty() {
+        return Ok((ContentType::PDF, pdfdata));
+    }
+
+    /* invoices recorded before the PDFs were stored are rendered again with the same ID, date and period */
+    let userinfo = get_user_info(user_id).await?;
+    let entries = get_invoice(user_id, invoice.period_from, invoice.period_to).await?;
+    let recipient = InvoiceRecipient {
+        firstname: userinfo.firstname,
+        lastname: userinfo.lastname,
+        street: userinfo.street,
+        postal_code: userinfo.postal_code,
+        city: userinfo.city,
+        gender: userinfo.gender,
+    };
+
+    let pdfdata = generate_invoice_pdf(&invoice.id, invoice.timestamp, recipient, entries, config.facturx).await?;
+
+    Ok((ContentType::PDF, pdfdata))
+}
+
+#[get("/users/<user_id>/sessions")]
+async fn user_sessions(auth: RequireLogin, cookies: &CookieJar<'_>, user_id: i32) -> Result<Template, WebShopError> {
+    let session = auth.0;
+
+    if !session.may_access_user(user_id) {
+        return Err(WebShopError::PermissionDenied());
+    }
+
+    let current = cookies.get("sessionid").map(|c| c.value().to_string()).unwrap_or_default();
+    let sessions = get_user_sessions(user_id, &current).await?;
+
+    Ok(Template::render("users/sessions", context! { page: "users/sessions", session: session, user_id: user_id, sessions: sessions }))
+}
+
+#[post("/users/<user_id>/sessions/<session_id>/revoke")]
+async fn user_session_revoke(auth: RequireLogin, _csrf: CsrfChecked, user_id: i32, session_id: i64) -> Result<Json<bool>, Forbidden<String>> {
+    let session = auth.0;
+
+    if !session.may_access_user(user_id) {
+        return Err(Forbidden("Missing Permission".to_string()));
+    }
+
+    match session_revoke(session.uid, user_id, session_id).await {
+        Err(error) => { return Err(Forbidden(error.to_string())); },
+        Ok(_) => {},
+    };
+
+    Ok(Json(true))
+}
+
+#[get("/me")]
+async fn member(auth: RequireLogin) -> Result<Template, WebShopError> {
+    let session = auth.0;
+
     let now = chrono::offset::Local::now();
-    user_invoice_full(cookies, user_id, now.year(), now.month(), now.day()).await
+    let start = chrono::Local.with_ymd_and_hms(now.year(), now.month(), 1, 0, 0, 0).unwrap().timestamp();
+    let stop = now.timestamp();
+
+    let purchases = get_invoice(session.uid, start, stop).await?;
+    let sum = get_user_invoice_sum(session.uid, start, stop).await?;
+    let disputes = get_user_disputes(session.uid).await?;
+
+    Ok(Template::render("users/member", context! { page: "users/member", session: session, purchases: purchases, sum: sum, disputes: disputes }))
+}
+
+#[post("/me/report", format = "application/json", data = "<report>")]
+async fn member_report(auth: RequireLogin, _csrf: CsrfChecked, report: Json<PurchaseReport>) -> Result<Json<i64>, Forbidden<String>> {
+    let session = auth.0;
+
+    if report.comment.trim().is_empty() {
+        return Err(Forbidden("Please describe what is wrong with the purchase".to_string()));
+    }
+
+    match dispute_create(session.uid, report.timestamp, report.product, report.comment.trim()).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(id) => Ok(Json(id)),
+    }
+}
+
+#[get("/users/disputes")]
+async fn user_disputes(auth: RequireUsers) -> Result<Template, WebShopError> {
+    let session = auth.0;
+
+    let disputes = get_disputes(false).await?;
+    let userlist = get_user_list(false).await?;
+
+    Ok(Template::render("users/disputes", context! { page: "users/disputes", session: session, disputes: disputes, users: userlist }))
+}
+
+#[post("/users/disputes/<id>/resolve", format = "application/json", data = "<resolution>")]
+async fn user_dispute_resolve(auth: RequireUsers, _csrf: CsrfChecked, id: i64, resolution: Json<DisputeResolution>) -> Result<Json<bool>, Forbidden<String>> {
+    let session = auth.0;
+
+    match dispute_resolve(id, session.uid, &resolution.action, resolution.reassign_to, resolution.resolution.trim()).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(true)),
+    }
 }
 
 #[get("/users/<id>/stats")]
-async fn user_stats(cookies: &CookieJar<'_>, id: i32) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn user_stats(auth: RequireLogin, id: i32) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
-    if !session.superuser && !session.auth_users && id != session.uid {
+    if !session.may_access_user(id) {
         return Err(WebShopError::PermissionDenied());
     }
 
@@ -2155,8 +4533,8 @@ async fn user_stats(cookies: &CookieJar<'_>, id: i32) -> Result<Template, WebSho
 }
 
 #[get("/users/import")]
-async fn user_import(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn user_import(auth: RequireUsers) -> Result<Template, WebShopError> {
+    let session = auth.0;
 
     Ok(Template::render("users/import", context! { page: "users/import", session: session }))
 }
@@ -2195,54 +4573,32 @@ async fn csvlist2changes(csvlist: &Vec<UserInfo>) -> Result<Vec<UserChange>, zbu
     Ok(changes)
 }
 
-#[post("/users/import", data = "<data>")]
-async fn user_import_upload(cookies: &CookieJar<'_>, data: Data<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_users {
-        return Err(WebShopError::PermissionDenied());
-    }
+#[post("/users/import", data = "<form>")]
+async fn user_import_upload(auth: RequireUsers, mut form: Form<FileUpload<'_>>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    csrf_form_check(&session, &form.csrf_token)?;
 
-    /* Hack to get uploaded CSV file in memory */
-    let stream = data.open(1.mebibytes());
-    let data = stream.into_string().await?;
-
-    if !data.is_complete() {
+    if !form.file.is_complete() {
         return Ok(Template::render("error", context! { page: "error", session: session, errmsg: "incomplete upload".to_string() }));
     }
 
-    let csvdata = data.value.split("\r\n\r\n").nth(1);
-    let csvdata = match csvdata {
-        None => { return Ok(Template::render("error", context! { page: "error", session: session, errmsg: "invalid upload".to_string() })); },
-        Some(x) => x,
-    };
-
-    let csvdata = csvdata.split("\n\r\n").nth(0);
-    let csvdata = match csvdata {
-        None => { return Ok(Template::render("error", context! { page: "error", session: session, errmsg: "invalid upload".to_string() })); },
-        Some(x) => x,
+    match form.file.persist_to("/tmp/shopsystem-users.csv").await {
+        Err(error) => { return Ok(Template::render("error", context! { page: "error", errmsg: error.to_string(), session: session })) },
+        Ok(_) => {},
     };
 
-    let csvlist = UserInfoList::from_csv(csvdata)?.data;
+    let csvdata = std::fs::read_to_string("/tmp/shopsystem-users.csv")?;
+    let csvlist = UserInfoList::from_csv(&csvdata)?.data;
     let changes = csvlist2changes(&csvlist).await?;
     Ok(Template::render("users/import2", context! { page: "error", session: session, changes: changes }))
 }
 
 #[post("/users/import/apply", format = "application/json", data = "<change>")]
-async fn user_import_apply(cookies: &CookieJar<'_>, change: Json<UserChange>) -> Result<Json<bool>, Forbidden<String>> {
-    let session = match get_session(cookies).await {
-        Err(error) => { return Err(Forbidden(error.to_string())); },
-        Ok(session) => session,
-    };
-
-    if !session.superuser && !session.auth_users {
-        return Err(Forbidden("Missing Permission".to_string()));
-    }
-
+async fn user_import_apply(auth: RequireUsers, _csrf: CsrfChecked, change: Json<UserChange>) -> Result<Json<bool>, Forbidden<String>> {
     let result = if change.new.is_some() {
-        user_replace(&change.new.as_ref().unwrap()).await
+        user_replace(auth.0.uid, &change.new.as_ref().unwrap()).await
     } else if change.old.is_some() {
-        user_disable(change.old.as_ref().unwrap().id, true).await
+        user_disable(auth.0.uid, change.old.as_ref().unwrap().id, true).await
     } else {
         return Err(Forbidden("Invalid Change".to_string()));
     };
@@ -2254,18 +4610,15 @@ async fn user_import_apply(cookies: &CookieJar<'_>, change: Json<UserChange>) ->
 }
 
 #[get("/users/import-pgp")]
-async fn user_import_pgp(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn user_import_pgp(auth: RequireUsers) -> Result<Template, WebShopError> {
+    let session = auth.0;
     Ok(Template::render("users/import-pgp", context! { page: "users/import-pgp", session: session }))
 }
 
 #[post("/users/import-pgp", data = "<form>")]
-async fn user_import_pgp_upload(cookies: &CookieJar<'_>, mut form: Form<FileUpload<'_>>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
-
-    if !session.superuser && !session.auth_users {
-        return Err(WebShopError::PermissionDenied());
-    }
+async fn user_import_pgp_upload(auth: RequireUsers, mut form: Form<FileUpload<'_>>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    csrf_form_check(&session, &form.csrf_token)?;
 
     if !form.file.is_complete() {
         return Ok(Template::render("error", context! { page: "error", session: session, errmsg: "Incomplete file upload!" }));
@@ -2294,18 +4647,287 @@ fn get_unix_time() -> i64 {
 
 
 #[get("/sales")]
-async fn sales(cookies: &CookieJar<'_>) -> Result<Template, WebShopError> {
-    let session = get_session(cookies).await?;
+async fn sales(auth: RequireSuperuser) -> Result<Template, WebShopError> {
+    let session = auth.0;
     let sales = get_sales(get_unix_time() - 86400, get_unix_time()).await?;
 
     Ok(Template::render("sales/index", context! { page: "sales/index", session: session, sales: sales }))
 }
 
+/* user = 0 and an empty entity show everything, the date range defaults to the last 30 days */
+/* parse a YYYY-MM-DD date as provided by HTML date inputs */
+fn parse_date(value: Option<String>, default: NaiveDate) -> NaiveDate {
+    value.and_then(|v| NaiveDate::parse_from_str(&v, "%Y-%m-%d").ok()).unwrap_or(default)
+}
+
+/* timestamp of local midnight at the start of the given date */
+fn date_timestamp(date: NaiveDate) -> i64 {
+    Local.from_local_datetime(&date.and_hms_opt(0, 0, 0).unwrap()).earliest().map(|d| d.timestamp()).unwrap_or_default()
+}
+
+#[get("/audit?<user>&<entity>&<from>&<to>")]
+async fn audit(auth: RequireSuperuser, user: Option<i32>, entity: Option<String>, from: Option<String>, to: Option<String>) -> Result<Template, WebShopError> {
+    let session = auth.0;
+    let today = Local::now().date_naive();
+
+    let user = user.unwrap_or(0);
+    let entity = entity.unwrap_or_default();
+    let from = parse_date(from, today - chrono::Duration::days(30));
+    let to = parse_date(to, today);
+
+    let entries = get_audit_log(user, &entity, date_timestamp(from), date_timestamp(to + chrono::Duration::days(1))).await?;
+    let userlist = get_user_list(false).await?;
+
+    Ok(Template::render("audit/index", context! {
+        page: "audit/index",
+        session: session,
+        entries: entries,
+        users: userlist,
+        user: user,
+        entity: entity,
+        from: from.format("%Y-%m-%d").to_string(),
+        to: to.format("%Y-%m-%d").to_string(),
+    }))
+}
+
+#[get("/users/<user_id>/tokens")]
+async fn user_api_tokens(auth: RequireLogin, user_id: i32) -> Result<Template, WebShopError> {
+    let session = auth.0;
+
+    if !session.may_access_user(user_id) {
+        return Err(WebShopError::PermissionDenied());
+    }
+
+    let tokens = get_user_api_tokens(user_id).await?;
+
+    Ok(Template::render("users/tokens", context! { page: "users/tokens", session: session, user_id: user_id, tokens: tokens }))
+}
+
+#[post("/users/<user_id>/tokens", format = "application/json", data = "<request>")]
+async fn user_api_token_create(auth: RequireLogin, _csrf: CsrfChecked, user_id: i32, request: Json<NewApiToken>) -> Result<Json<NewApiTokenResponse>, Forbidden<String>> {
+    let session = auth.0;
+
+    /* tokens act on behalf of their owner, so they cannot be created for somebody else */
+    if session.uid != user_id {
+        return Err(Forbidden("Missing Permission".to_string()));
+    }
+
+    let name = request.name.trim();
+    if name.is_empty() {
+        return Err(Forbidden("Missing token name".to_string()));
+    }
+
+    for scope in &request.scopes {
+        match Permission::from_scope(scope) {
+            Some(permission) if permission.granted(&session) => {},
+            Some(_) => { return Err(Forbidden(format!("Missing {} permission", scope))); },
+            None => { return Err(Forbidden(format!("Unknown scope: {}", scope))); },
+        };
+    }
+
+    let token = generate_session_id();
+    match api_token_create(session.uid, user_id, name, &token, request.scopes.clone()).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(id) => Ok(Json(NewApiTokenResponse { id: id, token: token })),
+    }
+}
+
+#[post("/users/<user_id>/tokens/<token_id>/revoke")]
+async fn user_api_token_revoke(auth: RequireLogin, _csrf: CsrfChecked, user_id: i32, token_id: i64) -> Result<Json<bool>, Forbidden<String>> {
+    let session = auth.0;
+
+    if !session.may_access_user(user_id) {
+        return Err(Forbidden("Missing Permission".to_string()));
+    }
+
+    match api_token_revoke(session.uid, user_id, token_id).await {
+        Err(error) => Err(Forbidden(error.to_string())),
+        Ok(_) => Ok(Json(true)),
+    }
+}
+
+/* the following routes are mounted below /api/v1 */
+
+const OPENAPI_SPEC: &str = include_str!("../../data/api/openapi.json");
+
+#[get("/openapi.json")]
+fn api_openapi() -> (ContentType, &'static str) {
+    (ContentType::JSON, OPENAPI_SPEC)
+}
+
+/* time range of the list endpoints, defaults to the last 30 days */
+fn api_time_range(from: Option<i64>, to: Option<i64>) -> (i64, i64) {
+    let to = to.unwrap_or(get_unix_time());
+    let from = from.unwrap_or(to - 30 * 86400);
+    (from, to)
+}
+
+#[get("/products")]
+async fn api_products(_auth: ApiLogin) -> Result<Json<Vec<DetailedProductInfo>>, ApiError> {
+    Ok(Json(get_productlist().await?))
+}
+
+#[get("/products/<ean>")]
+async fn api_product(_auth: ApiLogin, ean: i64) -> Result<Json<ProductDetails>, ApiError> {
+    let ean = ean_alias_get(ean).await?;
+
+    Ok(Json(ProductDetails {
+        ean: ean,
+        name: get_product_name(ean).await?,
+        category: get_product_category(ean).await?,
+        amount: get_product_amount(ean).await?,
+        deprecated: get_product_deprecated(ean).await?,
+    }))
+}
+
+#[get("/products/<ean>/prices")]
+async fn api_product_prices(_auth: ApiLogin, ean: i64) -> Result<Json<Vec<PriceInfo>>, ApiError> {
+    let ean = ean_alias_get(ean).await?;
+    Ok(Json(get_prices(ean).await?))
+}
+
+#[post("/products/<ean>/prices", format = "application/json", data = "<price>")]
+async fn api_product_price_add(auth: ApiProducts, ean: i64, price: Json<PriceInfo>) -> Result<Json<PriceInfo>, ApiError> {
+    let session = auth.0;
+    let ean = ean_alias_get(ean).await?;
+
+    if price.memberprice < 0 || price.guestprice < 0 {
+        return Err(ApiError::new(Status::BadRequest, "prices must not be negative"));
+    }
+
+    /* prices can be scheduled, but not backdated */
+    let valid_from = std::cmp::max(price.timestamp, chrono::offset::Local::now().timestamp());
+
+    new_price(session.uid, ean, valid_from, price.memberprice, price.guestprice).await?;
+    Ok(Json(PriceInfo {
+        timestamp: valid_from,
+        memberprice: price.memberprice,
+        guestprice: price.guestprice,
+    }))
+}
+
+#[get("/products/<ean>/restocks")]
+async fn api_product_restocks(_auth: ApiProducts, ean: i64) -> Result<Json<Vec<RestockEntry>>, ApiError> {
+    let ean = ean_alias_get(ean).await?;
+    Ok(Json(get_restocks(ean, true).await?))
+}
+
+#[post("/products/<ean>/restocks", format = "application/json", data = "<data>")]
+async fn api_product_restock_add(auth: ApiProducts, ean: i64, data: Json<RestockEntry>) -> Result<Json<RestockEntry>, ApiError> {
+    let session = auth.0;
+    let ean = ean_alias_get(ean).await?;
+
+    restock(session.uid, session.uid, ean, data.amount, data.price, data.supplier, data.best_before_date).await?;
+
+    Ok(Json(RestockEntry {
+        timestamp: get_unix_time(),
+        amount: data.amount,
+        price: data.price,
+        supplier: data.supplier,
+        best_before_date: data.best_before_date,
+    }))
+}
+
+#[get("/stock")]
+async fn api_stock(_auth: ApiLogin) -> Result<Json<Vec<StockItem>>, ApiError> {
+    Ok(Json(get_stock().await?))
+}
+
+#[get("/users")]
+async fn api_users(_auth: ApiUsers) -> Result<Json<Vec<UserInfo>>, ApiError> {
+    let mut users = Vec::new();
+    for uid in get_member_ids().await? {
+        users.push(get_user_info(uid).await?);
+    }
+    Ok(Json(users))
+}
+
+#[get("/users/<user_id>")]
+async fn api_user(auth: ApiLogin, user_id: i32) -> Result<Json<UserInfo>, ApiError> {
+    if !auth.0.may_access_user(user_id) {
+        return Err(ApiError::new(Status::Forbidden, "Permission Denied (requires users permission)"));
+    }
+
+    Ok(Json(get_user_info(user_id).await?))
+}
+
+#[get("/users/<user_id>/purchases?<from>&<to>")]
+async fn api_user_purchases(auth: ApiLogin, user_id: i32, from: Option<i64>, to: Option<i64>) -> Result<Json<Vec<InvoiceEntry>>, ApiError> {
+    if !auth.0.may_access_user(user_id) {
+        return Err(ApiError::new(Status::Forbidden, "Permission Denied (requires users permission)"));
+    }
+
+    let (from, to) = api_time_range(from, to);
+    Ok(Json(get_invoice(user_id, from, to).await?))
+}
+
+#[get("/sales?<from>&<to>")]
+async fn api_sales(_auth: ApiUsers, from: Option<i64>, to: Option<i64>) -> Result<Json<Vec<SalesEntry>>, ApiError> {
+    let (from, to) = api_time_range(from, to);
+    Ok(Json(get_sales(from, to).await?))
+}
+
+#[get("/cashbox")]
+async fn api_cashbox(_auth: ApiCashbox) -> Result<Json<ApiCashbox>, ApiError> {
+    Ok(Json(ApiCashbox {
+        status: cashbox_status().await?,
+        history: cashbox_history_named().await?,
+    }))
+}
+
+#[post("/cashbox", format = "application/json", data = "<data>")]
+async fn api_cashbox_update(auth: ApiCashbox, data: Json<CashboxUpdate>) -> Result<Json<ApiCashbox>, ApiError> {
+    let session = auth.0;
+
+    cashbox_update_helper(&session, &data).await?;
+
+    Ok(Json(ApiCashbox {
+        status: cashbox_status().await?,
+        history: cashbox_history_named().await?,
+    }))
+}
+
+#[get("/suppliers/<id>/order-suggestion?<coverage>&<history>")]
+async fn api_order_suggestion(_auth: ApiProducts, id: i32, coverage: Option<u32>, history: Option<u32>) -> Result<Json<OrderSuggestionList>, ApiError> {
+    Ok(Json(get_order_suggestion(id, coverage.unwrap_or(0), history.unwrap_or(0)).await?))
+}
+
+#[get("/cashbox/ledger?<from>&<to>&<entry_type>&<offset>&<limit>")]
+async fn api_cashbox_ledger(_auth: ApiCashbox, from: Option<i64>, to: Option<i64>, entry_type: Option<String>, offset: Option<u32>, limit: Option<u32>) -> Result<Json<CashboxLedgerPage>, ApiError> {
+    let (from, to) = api_time_range(from, to);
+    let (total, entries) = cashbox_ledger_named(from, to, &entry_type.unwrap_or_default(), offset.unwrap_or(0), limit.unwrap_or(CASHBOX_LEDGER_PAGE_SIZE)).await?;
+    Ok(Json(CashboxLedgerPage { total, entries }))
+}
+
+#[get("/deposits")]
+async fn api_deposits(_auth: ApiCashbox) -> Result<Json<DepositOverview>, ApiError> {
+    Ok(Json(get_deposit_overview().await?))
+}
+
+#[catch(default)]
+fn api_catcher(status: Status, request: &Request) -> Json<ApiErrorBody> {
+    let error = match (status.code, request.local_cache(|| MissingPermission(None)).0) {
+        (401, _) => "Missing or invalid API token".to_string(),
+        (403, Some(permission)) => format!("Permission Denied (requires {} permission)", permission.name()),
+        _ => status.reason_lossy().to_string(),
+    };
+    Json(ApiErrorBody { status: status.code, error: error })
+}
+
 #[catch(404)]
 fn not_found() -> &'static str {
     "could not find the page (404)"
 }
 
+#[catch(403)]
+fn forbidden(request: &Request) -> Template {
+    let errmsg = match request.local_cache(|| MissingPermission(None)).0 {
+        Some(permission) => format!("Permission Denied (requires {} permission)", permission.name()),
+        None => "Permission Denied".to_string(),
+    };
+    Template::render("error", context! { page: "error", errmsg: errmsg })
+}
+
 fn gendericon<S: BuildHasher>(value: &rocket_dyn_templates::tera::Value, _: &HashMap<String, rocket_dyn_templates::tera::Value, S>) -> rocket_dyn_templates::tera::Result<rocket_dyn_templates::tera::Value> {
     let val = rocket_dyn_templates::tera::try_get_value!("gendericon", "value", String, value);
     let result = match val.as_str() {
@@ -2317,11 +4939,116 @@ fn gendericon<S: BuildHasher>(value: &rocket_dyn_templates::tera::Value, _: &Has
 }
 
 fn cent2euro<S: BuildHasher>(value: &rocket_dyn_templates::tera::Value, _: &HashMap<String, rocket_dyn_templates::tera::Value, S>) -> rocket_dyn_templates::tera::Result<rocket_dyn_templates::tera::Value> {
-    let cent = rocket_dyn_templates::tera::try_get_value!("cent2euro", "value", i32, value);
-    let euro = cent / 100;
-    let cent = (cent % 100).abs();
-    let result = format!("{}.{:02}", euro, cent);
-    Ok(rocket_dyn_templates::tera::to_value(result).unwrap())
+    let cent = rocket_dyn_templates::tera::try_get_value!("cent2euro", "value", i64, value);
+    Ok(rocket_dyn_templates::tera::to_value(format_cent(cent)).unwrap())
+}
+
+/* cell of an OpenDocument spreadsheet export */
+enum OdsCell {
+    Text(String),
+    Integer(i64),
+    Euro(i64),
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/* zip archive without compression, which is sufficient for OpenDocument files */
+fn zip_stored(files: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive = Vec::new();
+    let mut directory = Vec::new();
+
+    for (name, data) in files {
+        let offset = archive.len() as u32;
+        let crc = crc32fast::hash(data);
+        let mut header = Vec::new();
+        header.extend_from_slice(&20u16.to_le_bytes()); /* version needed */
+        header.extend_from_slice(&0u16.to_le_bytes()); /* flags */
+        header.extend_from_slice(&0u16.to_le_bytes()); /* stored */
+        header.extend_from_slice(&0u16.to_le_bytes()); /* time */
+        header.extend_from_slice(&0x21u16.to_le_bytes()); /* date: 1980-01-01 */
+        header.extend_from_slice(&crc.to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); /* extra field length */
+
+        archive.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(name.as_bytes());
+        archive.extend_from_slice(data);
+
+        directory.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        directory.extend_from_slice(&20u16.to_le_bytes()); /* version made by */
+        directory.extend_from_slice(&header);
+        directory.extend_from_slice(&0u16.to_le_bytes()); /* comment length */
+        directory.extend_from_slice(&0u16.to_le_bytes()); /* disk number */
+        directory.extend_from_slice(&0u16.to_le_bytes()); /* internal attributes */
+        directory.extend_from_slice(&0u32.to_le_bytes()); /* external attributes */
+        directory.extend_from_slice(&offset.to_le_bytes());
+        directory.extend_from_slice(name.as_bytes());
+    }
+
+    let directory_offset = archive.len() as u32;
+    archive.extend_from_slice(&directory);
+    archive.extend_from_slice(&0x06054b50u32.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    archive.extend_from_slice(&(directory.len() as u32).to_le_bytes());
+    archive.extend_from_slice(&directory_offset.to_le_bytes());
+    archive.extend_from_slice(&0u16.to_le_bytes());
+    archive
+}
+
+/* OpenDocument spreadsheet with a single table */
+fn ods_spreadsheet(name: &str, rows: &[Vec<OdsCell>]) -> Vec<u8> {
+    let mimetype = "application/vnd.oasis.opendocument.spreadsheet";
+    let manifest = format!(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<manifest:manifest xmlns:manifest=\"urn:oasis:names:tc:opendocument:xmlns:manifest:1.0\" manifest:version=\"1.2\">",
+        "<manifest:file-entry manifest:full-path=\"/\" manifest:version=\"1.2\" manifest:media-type=\"{}\"/>",
+        "<manifest:file-entry manifest:full-path=\"content.xml\" manifest:media-type=\"text/xml\"/>",
+        "</manifest:manifest>\n"), mimetype);
+
+    let mut table = String::new();
+    for row in rows {
+        table.push_str("<table:table-row>");
+        for cell in row {
+            match cell {
+                OdsCell::Text(text) => table.push_str(&format!("<table:table-cell office:value-type=\"string\"><text:p>{}</text:p></table:table-cell>", xml_escape(text))),
+                OdsCell::Integer(value) => table.push_str(&format!("<table:table-cell office:value-type=\"float\" office:value=\"{}\"><text:p>{}</text:p></table:table-cell>", value, value)),
+                OdsCell::Euro(cent) => table.push_str(&format!("<table:table-cell office:value-type=\"currency\" office:currency=\"EUR\" office:value=\"{}\"><text:p>{} €</text:p></table:table-cell>", format_cent(*cent), format_cent(*cent))),
+            }
+        }
+        table.push_str("</table:table-row>");
+    }
+
+    let content = format!(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<office:document-content xmlns:office=\"urn:oasis:names:tc:opendocument:xmlns:office:1.0\" ",
+        "xmlns:table=\"urn:oasis:names:tc:opendocument:xmlns:table:1.0\" ",
+        "xmlns:text=\"urn:oasis:names:tc:opendocument:xmlns:text:1.0\" office:version=\"1.2\">",
+        "<office:body><office:spreadsheet><table:table table:name=\"{}\">{}</table:table></office:spreadsheet></office:body>",
+        "</office:document-content>\n"), xml_escape(name), table);
+
+    /* the mimetype has to be the first file in the archive */
+    zip_stored(&[
+        ("mimetype", mimetype.as_bytes()),
+        ("META-INF/manifest.xml", manifest.as_bytes()),
+        ("content.xml", content.as_bytes()),
+    ])
+}
+
+fn format_cent(cent: i64) -> String {
+    let sign = if cent < 0 { "-" } else { "" };
+    format!("{}{}.{:02}", sign, cent.abs() / 100, cent.abs() % 100)
 }
 
 fn togglebutton<S: BuildHasher>(args: &HashMap<String, rocket_dyn_templates::tera::Value, S>) -> rocket_dyn_templates::tera::Result<rocket_dyn_templates::tera::Value> {
@@ -2370,36 +5097,483 @@ fn togglebutton<S: BuildHasher>(args: &HashMap<String, rocket_dyn_templates::ter
     Ok(rocket_dyn_templates::tera::to_value(result).unwrap())
 }
 
-#[launch]
-fn rocket() -> _ {
-    let mut cfg = Ini::new();
-    cfg.load("/etc/shopsystem/config.ini").expect("failed to load config");
-    let path = cfg.get("GENERAL", "datapath").unwrap_or("/usr/share/shopsystem/".to_string());
-    let templatepath = format!("{}/{}", path, "templates/");
-    let staticpath = format!("{}/{}", path, "templates/static/");
-
+fn build_rocket(templatepath: String, staticpath: String, webconfig: WebConfig, sessions: Box<dyn SessionLookup>) -> rocket::Rocket<rocket::Build> {
     let figment = rocket::Config::figment()
         .merge(("template_dir", templatepath));
 
     rocket::custom(figment)
-        .register("/", catchers![not_found])
+        .manage(webconfig)
+        .manage(sessions)
+        .register("/", catchers![not_found, forbidden])
+        .register("/api/v1", catchers![api_catcher])
         .mount("/static", rocket::fs::FileServer::from(staticpath))
         .mount("/", routes![login, logout, index, products, product_new, product_details,
             product_restock, product_search_json, product_details_json, product_amount_json,
             product_sales_info_json, web_product_deprecate, web_product_add_prices,
             web_product_restock, web_product_last_restock, web_product_alias_add,
-            web_product_metadata_get, web_product_metadata_set,
+            web_product_metadata_get, web_product_metadata_set, product_shelf_label,
+            product_shelf_labels, product_shelf_labels_pdf,
             web_product_order_suggestion_step1, web_product_order_suggestion_step2,
-            product_bestbefore, product_inventory, product_inventory_apply, aliases,
+            supplier_details, supplier_edit, supplier_catalogue_add, supplier_catalogue_delete, web_product_catalogue,
+            purchase_orders, purchase_order_new, purchase_order, purchase_order_update,
+            purchase_order_item_set, purchase_order_delivery, purchase_order_csv, purchase_order_pdf,
+            product_bestbefore, product_inventory, product_inventory_apply, product_categories,
+            product_category_vat_set, product_prices, product_category_prices_set,
+            web_product_price_cancel, product_export, product_import, product_import_upload,
+            product_import_apply, aliases,
             suppliers, web_suppliers_new, supplier_json_list, supplier_json_product_list,
             supplier_json_restock_dates, cashbox, cashbox_state, cashbox_history_json,
-            cashbox_update, cashbox_details, users, user_info, user_barcode, user_barcodelist,
+            cashbox_update, cashbox_details, cashbox_report, cashbox_report_csv,
+            cashbox_stock_value, cashbox_stock_value_csv, cashbox_deposits, cashbox_deposit_return,
+            cashbox_count_add, cashbox_counts, cashbox_count_report,
+            cashbox_ledger, cashbox_ledger_csv, cashbox_ledger_ods, users, user_info, user_barcode, user_barcodelist,
             user_sound_theme_set, user_password_set, user_toggle_auth, user_invoice,
-            user_invoice_full, user_stats, user_import, user_import_upload,
-            user_import_apply, user_import_pgp, user_import_pgp_upload, sales])
+            user_invoice_full, user_invoices, user_invoice_pdf, user_sessions, user_session_revoke,
+            user_stats, member, member_report, user_disputes, user_dispute_resolve,
+            user_import, user_import_upload,
+            user_api_tokens, user_api_token_create, user_api_token_revoke,
+            user_import_apply, user_import_pgp, user_import_pgp_upload, sales, audit])
+        .mount("/api/v1", routes![api_openapi, api_products, api_product, api_product_prices,
+            api_product_price_add, api_product_restocks, api_product_restock_add, api_stock,
+            api_users, api_user, api_user_purchases, api_sales, api_cashbox, api_cashbox_update, api_cashbox_ledger, api_order_suggestion,
+            api_deposits])
         .attach(Template::custom(|engines| {
             engines.tera.register_filter("cent2euro", cent2euro);
             engines.tera.register_filter("gendericon", gendericon);
             engines.tera.register_function("togglebutton", togglebutton);
         }))
 }
+
+#[launch]
+fn rocket() -> _ {
+    let mut cfg = Ini::new();
+    cfg.load("/etc/shopsystem/config.ini").expect("failed to load config");
+    let path = cfg.get("GENERAL", "datapath").unwrap_or("/usr/share/shopsystem/".to_string());
+    let templatepath = format!("{}/{}", path, "templates/");
+    let staticpath = format!("{}/{}", path, "templates/static/");
+
+    let webconfig = WebConfig {
+        secure_cookies: cfg.get("WEB", "secure_cookies").unwrap_or("yes".to_string()) != "no",
+        session_lifetime: cfg.getint("WEB", "session_lifetime").ok().flatten().unwrap_or(7 * 24 * 60 * 60),
+        facturx: cfg.get("INVOICE", "facturx").unwrap_or_default() == "yes",
+    };
+
+    build_rocket(templatepath, staticpath, webconfig, Box::new(DBusSessionLookup))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rocket::http::{Accept, Header, Method};
+    use rocket::local::blocking::Client;
+
+    /* every mounted route with an example URI and the permission required to access it */
+    const ROUTE_PERMISSIONS: &[(Method, &str, &str, Option<Permission>)] = &[
+        (Method::Post, "/login", "/login", None),
+        (Method::Post, "/logout", "/logout", None),
+        (Method::Get, "/", "/", None),
+        (Method::Get, "/products", "/products", Some(Permission::Login)),
+        (Method::Post, "/products/new", "/products/new", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>", "/products/4029764001807", Some(Permission::Login)),
+        (Method::Get, "/products/restock", "/products/restock", Some(Permission::Products)),
+        (Method::Get, "/products/search/<search>", "/products/search/mate", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>/json", "/products/4029764001807/json", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>/amount", "/products/4029764001807/amount", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>/sales-info?<timestamp>", "/products/4029764001807/sales-info?timestamp=0", Some(Permission::Products)),
+        (Method::Post, "/products/<ean>/deprecate/<deprecated>", "/products/4029764001807/deprecate/true", Some(Permission::Products)),
+        (Method::Post, "/products/<ean>/add-prices", "/products/4029764001807/add-prices", Some(Permission::Products)),
+        (Method::Post, "/products/<ean>/prices/<valid_from>/cancel", "/products/4029764001807/prices/1767222000/cancel", Some(Permission::Products)),
+        (Method::Post, "/products/<ean>/restock", "/products/4029764001807/restock", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>/get-last-restock", "/products/4029764001807/get-last-restock", Some(Permission::Products)),
+        (Method::Post, "/products/<ean>/add-alias/<alias>", "/products/4029764001807/add-alias/4029764001814", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>/metadata-get", "/products/4029764001807/metadata-get", Some(Permission::Products)),
+        (Method::Post, "/products/<ean>/metadata-set", "/products/4029764001807/metadata-set", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>/shelf-label.pdf", "/products/4029764001807/shelf-label.pdf", Some(Permission::Login)),
+        (Method::Get, "/products/shelf-labels", "/products/shelf-labels", Some(Permission::Products)),
+        (Method::Get, "/products/shelf-labels.pdf?<eans>&<since>&<date>&<columns>&<rows>&<margin>", "/products/shelf-labels.pdf?since=2024-01-01&columns=3", Some(Permission::Products)),
+        (Method::Get, "/suppliers/order-suggestion", "/suppliers/order-suggestion", Some(Permission::Products)),
+        (Method::Get, "/suppliers/<id>/order-suggestion?<coverage>&<history>", "/suppliers/1/order-suggestion?coverage=7", Some(Permission::Products)),
+        (Method::Get, "/suppliers/<id>", "/suppliers/1", Some(Permission::Login)),
+        (Method::Post, "/suppliers/<id>", "/suppliers/1", Some(Permission::Products)),
+        (Method::Post, "/suppliers/<id>/catalogue", "/suppliers/1/catalogue", Some(Permission::Products)),
+        (Method::Post, "/suppliers/<id>/catalogue/<ean>/remove", "/suppliers/1/catalogue/4029764001807/remove", Some(Permission::Products)),
+        (Method::Get, "/products/<ean>/catalogue", "/products/4029764001807/catalogue", Some(Permission::Products)),
+        (Method::Get, "/purchase-orders?<status>", "/purchase-orders?status=draft", Some(Permission::Products)),
+        (Method::Post, "/purchase-orders", "/purchase-orders", Some(Permission::Products)),
+        (Method::Get, "/purchase-orders/<id>", "/purchase-orders/1", Some(Permission::Products)),
+        (Method::Post, "/purchase-orders/<id>", "/purchase-orders/1", Some(Permission::Products)),
+        (Method::Post, "/purchase-orders/<id>/items", "/purchase-orders/1/items", Some(Permission::Products)),
+        (Method::Post, "/purchase-orders/<id>/receive", "/purchase-orders/1/receive", Some(Permission::Products)),
+        (Method::Get, "/purchase-orders/<id>/export.csv", "/purchase-orders/1/export.csv", Some(Permission::Products)),
+        (Method::Get, "/purchase-orders/<id>/export.pdf", "/purchase-orders/1/export.pdf", Some(Permission::Products)),
+        (Method::Get, "/products/bestbefore", "/products/bestbefore", Some(Permission::Login)),
+        (Method::Get, "/products/inventory", "/products/inventory", Some(Permission::Products)),
+        (Method::Post, "/products/inventory/apply", "/products/inventory/apply", Some(Permission::Products)),
+        (Method::Get, "/products/categories", "/products/categories", Some(Permission::Products)),
+        (Method::Post, "/products/categories/<id>/vat", "/products/categories/1/vat", Some(Permission::Products)),
+        (Method::Get, "/products/prices", "/products/prices", Some(Permission::Products)),
+        (Method::Post, "/products/categories/<id>/prices", "/products/categories/1/prices", Some(Permission::Products)),
+        (Method::Get, "/aliases", "/aliases", Some(Permission::Login)),
+        (Method::Get, "/suppliers", "/suppliers", Some(Permission::Login)),
+        (Method::Post, "/suppliers/new", "/suppliers/new", Some(Permission::Products)),
+        (Method::Get, "/suppliers/list", "/suppliers/list", Some(Permission::Products)),
+        (Method::Get, "/suppliers/<id>/product-list", "/suppliers/1/product-list", Some(Permission::Products)),
+        (Method::Get, "/suppliers/<id>/restock-dates", "/suppliers/1/restock-dates", Some(Permission::Products)),
+        (Method::Get, "/cashbox", "/cashbox", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/status", "/cashbox/status", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/history", "/cashbox/history", Some(Permission::Cashbox)),
+        (Method::Post, "/cashbox/update", "/cashbox/update", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/report?<from>&<to>", "/cashbox/report?from=2024-01-01&to=2024-12-31", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/report.csv?<from>&<to>&<group>", "/cashbox/report.csv?group=category", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/stock-value?<date>", "/cashbox/stock-value?date=2024-12-31", Some(Permission::Cashbox)),
+        (Method::Post, "/cashbox/count", "/cashbox/count", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/counts?<from>&<to>", "/cashbox/counts", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/counts/<id>", "/cashbox/counts/1", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/ledger?<from>&<to>&<entry_type>&<page>", "/cashbox/ledger?entry_type=loss&page=2", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/ledger.csv?<from>&<to>&<entry_type>", "/cashbox/ledger.csv", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/ledger.ods?<from>&<to>&<entry_type>", "/cashbox/ledger.ods", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/stock-value.csv?<date>", "/cashbox/stock-value.csv", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/details/<year>/<month>", "/cashbox/details/2024/1", Some(Permission::Cashbox)),
+        (Method::Get, "/cashbox/deposits?<supplier>&<from>&<to>", "/cashbox/deposits?supplier=1", Some(Permission::Cashbox)),
+        (Method::Post, "/cashbox/deposits", "/cashbox/deposits", Some(Permission::Cashbox)),
+        (Method::Get, "/users", "/users", Some(Permission::Users)),
+        (Method::Get, "/users/<id>", "/users/1", Some(Permission::Login)),
+        (Method::Get, "/users/<id>/barcode.svg", "/users/1/barcode.svg", Some(Permission::Login)),
+        (Method::Get, "/users/barcodelist.pdf", "/users/barcodelist.pdf", Some(Permission::Users)),
+        (Method::Post, "/users/set-sound-theme/<userid>", "/users/set-sound-theme/1", Some(Permission::Login)),
+        (Method::Post, "/users/set-password/<userid>", "/users/set-password/1", Some(Permission::Login)),
+        (Method::Post, "/users/toggle-auth/<userid>/<permission>", "/users/toggle-auth/1/products", Some(Permission::Users)),
+        (Method::Get, "/users/<user_id>/invoice", "/users/1/invoice", Some(Permission::Login)),
+        (Method::Get, "/users/<user_id>/invoice/<year>/<month>/<day>", "/users/1/invoice/2024/1/0", Some(Permission::Login)),
+        (Method::Get, "/users/<user_id>/invoices", "/users/1/invoices", Some(Permission::Login)),
+        (Method::Get, "/users/<user_id>/invoices/<invoice_id>/invoice.pdf", "/users/1/invoices/202401001/invoice.pdf", Some(Permission::Login)),
+        (Method::Get, "/users/<user_id>/sessions", "/users/1/sessions", Some(Permission::Login)),
+        (Method::Post, "/users/<user_id>/sessions/<session_id>/revoke", "/users/1/sessions/1/revoke", Some(Permission::Login)),
+        (Method::Get, "/users/<id>/stats", "/users/1/stats", Some(Permission::Login)),
+        (Method::Get, "/me", "/me", Some(Permission::Login)),
+        (Method::Post, "/me/report", "/me/report", Some(Permission::Login)),
+        (Method::Get, "/users/disputes", "/users/disputes", Some(Permission::Users)),
+        (Method::Post, "/users/disputes/<id>/resolve", "/users/disputes/1/resolve", Some(Permission::Users)),
+        (Method::Get, "/users/import", "/users/import", Some(Permission::Users)),
+        (Method::Post, "/users/import", "/users/import", Some(Permission::Users)),
+        (Method::Post, "/users/import/apply", "/users/import/apply", Some(Permission::Users)),
+        (Method::Get, "/users/import-pgp", "/users/import-pgp", Some(Permission::Users)),
+        (Method::Post, "/users/import-pgp", "/users/import-pgp", Some(Permission::Users)),
+        (Method::Get, "/sales", "/sales", Some(Permission::Superuser)),
+        (Method::Get, "/products/export.csv", "/products/export.csv", Some(Permission::Products)),
+        (Method::Get, "/products/import", "/products/import", Some(Permission::Products)),
+        (Method::Post, "/products/import", "/products/import", Some(Permission::Products)),
+        (Method::Post, "/products/import/apply", "/products/import/apply", Some(Permission::Products)),
+        (Method::Get, "/audit?<user>&<entity>&<from>&<to>", "/audit?entity=product", Some(Permission::Superuser)),
+        (Method::Get, "/users/<user_id>/tokens", "/users/1/tokens", Some(Permission::Login)),
+        (Method::Post, "/users/<user_id>/tokens", "/users/1/tokens", Some(Permission::Login)),
+        (Method::Post, "/users/<user_id>/tokens/<token_id>/revoke", "/users/1/tokens/1/revoke", Some(Permission::Login)),
+        (Method::Get, "/api/v1/openapi.json", "/api/v1/openapi.json", None),
+        (Method::Get, "/api/v1/products", "/api/v1/products", Some(Permission::Login)),
+        (Method::Get, "/api/v1/products/<ean>", "/api/v1/products/4029764001807", Some(Permission::Login)),
+        (Method::Get, "/api/v1/products/<ean>/prices", "/api/v1/products/4029764001807/prices", Some(Permission::Login)),
+        (Method::Post, "/api/v1/products/<ean>/prices", "/api/v1/products/4029764001807/prices", Some(Permission::Products)),
+        (Method::Get, "/api/v1/products/<ean>/restocks", "/api/v1/products/4029764001807/restocks", Some(Permission::Products)),
+        (Method::Post, "/api/v1/products/<ean>/restocks", "/api/v1/products/4029764001807/restocks", Some(Permission::Products)),
+        (Method::Get, "/api/v1/stock", "/api/v1/stock", Some(Permission::Login)),
+        (Method::Get, "/api/v1/users", "/api/v1/users", Some(Permission::Users)),
+        (Method::Get, "/api/v1/users/<user_id>", "/api/v1/users/1", Some(Permission::Login)),
+        (Method::Get, "/api/v1/users/<user_id>/purchases?<from>&<to>", "/api/v1/users/1/purchases", Some(Permission::Login)),
+        (Method::Get, "/api/v1/sales?<from>&<to>", "/api/v1/sales", Some(Permission::Users)),
+        (Method::Get, "/api/v1/cashbox", "/api/v1/cashbox", Some(Permission::Cashbox)),
+        (Method::Post, "/api/v1/cashbox", "/api/v1/cashbox", Some(Permission::Cashbox)),
+        (Method::Get, "/api/v1/cashbox/ledger?<from>&<to>&<entry_type>&<offset>&<limit>", "/api/v1/cashbox/ledger?limit=10", Some(Permission::Cashbox)),
+        (Method::Get, "/api/v1/suppliers/<id>/order-suggestion?<coverage>&<history>", "/api/v1/suppliers/1/order-suggestion", Some(Permission::Products)),
+        (Method::Get, "/api/v1/deposits", "/api/v1/deposits", Some(Permission::Cashbox)),
+    ];
+
+    const STUB_LEVELS: &[&str] = &["login", "products", "cashbox", "users", "superuser"];
+    const STUB_CSRF_TOKEN: &str = "stub-csrf-token";
+
+    /* sessions for the "stub-<level>" session ids and API tokens, so that the guards can be tested without D-Bus */
+    fn stub_session(id: &str) -> Option<Session> {
+        let login = Session {
+            uid: 1,
+            name: String::from("Stub"),
+            superuser: false,
+            auth_cashbox: false,
+            auth_products: false,
+            auth_users: false,
+            csrf_token: STUB_CSRF_TOKEN.to_string(),
+        };
+
+        match id.strip_prefix("stub-")? {
+            "login" => Some(login),
+            "products" => Some(Session { auth_products: true, ..login }),
+            "cashbox" => Some(Session { auth_cashbox: true, ..login }),
+            "users" => Some(Session { auth_users: true, ..login }),
+            "superuser" => Some(Session { superuser: true, auth_cashbox: true, auth_products: true, auth_users: true, ..login }),
+            _ => None,
+        }
+    }
+
+    /* unknown session ids are guests, unknown API tokens are invalid */
+    struct StubSessionLookup;
+
+    #[rocket::async_trait]
+    impl SessionLookup for StubSessionLookup {
+        async fn session(&self, sessionid: &str) -> zbus::Result<Session> {
+            Ok(stub_session(sessionid).unwrap_or(Session {
+                uid: 0,
+                name: String::from("Guest"),
+                superuser: false,
+                auth_cashbox: false,
+                auth_products: false,
+                auth_users: false,
+                csrf_token: String::new(),
+            }))
+        }
+
+        async fn api_session(&self, token: &str) -> zbus::Result<Session> {
+            stub_session(token).ok_or(zbus::Error::Failure("invalid API token".to_string()))
+        }
+    }
+
+    /* true if the request was refused for missing the given permission */
+    fn request_denied(client: &Client, method: Method, uri: &str, level: &str, permission: Permission) -> bool {
+        let id = format!("stub-{}", level);
+        let request = client.req(method, uri)
+            .header(ContentType::JSON)
+            .header(Accept::JSON)
+            .header(Header::new("X-CSRF-Token", STUB_CSRF_TOKEN));
+        let request = match uri.starts_with("/api/") {
+            true => request.header(Header::new("Authorization", format!("Bearer {}", id))),
+            false => request.cookie(Cookie::new("sessionid", id)),
+        };
+
+        let response = request.dispatch();
+        let status = response.status();
+        let body = response.into_string().unwrap_or_default();
+        status == Status::Forbidden && body.contains(&format!("requires {} permission", permission.name()))
+    }
+
+    fn client() -> Client {
+        let webconfig = WebConfig {
+            secure_cookies: false,
+            session_lifetime: 3600,
+            facturx: false,
+        };
+        let rocket = build_rocket("templates/".to_string(), "templates/static/".to_string(), webconfig, Box::new(StubSessionLookup));
+        Client::untracked(rocket).expect("valid rocket instance")
+    }
+
+    #[test]
+    fn all_routes_have_permission() {
+        let client = client();
+        for route in client.rocket().routes() {
+            let uri = route.uri.to_string();
+            if uri.starts_with("/static") {
+                continue;
+            }
+            let known = ROUTE_PERMISSIONS.iter().any(|(method, pattern, _, _)| route.method == *method && *pattern == uri);
+            assert!(known, "route {} {} has no permission entry", route.method, uri);
+        }
+    }
+
+    #[test]
+    fn guests_are_denied() {
+        /* no session cookie, so every request is handled as guest */
+        let client = client();
+        for (method, _, uri, permission) in ROUTE_PERMISSIONS {
+            let permission = match permission {
+                Some(permission) => permission,
+                None => continue,
+            };
+
+            let response = client.req(*method, *uri)
+                .header(ContentType::JSON)
+                .header(Accept::JSON)
+                .dispatch();
+
+            /* API clients authenticate with a token instead of the session cookie */
+            if uri.starts_with("/api/") {
+                assert_eq!(response.status(), Status::Unauthorized, "{} {}", method, uri);
+                let body: serde_json::Value = response.into_json().expect("JSON error body");
+                assert_eq!(body["status"], 401, "{} {}", method, uri);
+                continue;
+            }
+
+            assert_eq!(response.status(), Status::Forbidden, "{} {}", method, uri);
+
+            let body = response.into_string().unwrap_or_default();
+            let expected = format!("requires {} permission", permission.name());
+            assert!(body.contains(&expected), "{} {} does not require {}", method, uri, permission.name());
+        }
+    }
+
+    #[test]
+    fn api_errors_are_json() {
+        let client = client();
+        let response = client.get("/api/v1/does-not-exist").dispatch();
+        assert_eq!(response.status(), Status::NotFound);
+        let body: serde_json::Value = response.into_json().expect("JSON error body");
+        assert_eq!(body["status"], 404);
+        assert!(body["error"].is_string());
+
+        let response = client.get("/api/v1/stock").header(Header::new("Authorization", "Bearer invalid")).dispatch();
+        assert_eq!(response.status(), Status::Unauthorized);
+    }
+
+    #[test]
+    fn openapi_describes_all_routes() {
+        let spec: serde_json::Value = serde_json::from_str(OPENAPI_SPEC).expect("valid OpenAPI JSON");
+        let client = client();
+        for route in client.rocket().routes() {
+            let uri = route.uri.to_string();
+            let path = match uri.split('?').next().unwrap_or_default().strip_prefix("/api/v1") {
+                Some(path) => path.replace('<', "{").replace('>', "}"),
+                None => continue,
+            };
+            let method = route.method.as_str().to_lowercase();
+            assert!(spec["paths"][&path][&method].is_object(), "{} {} is not documented", method, path);
+        }
+    }
+
+    #[test]
+    fn permission_checks() {
+        let guest = Session {
+            uid: 0,
+            name: String::from("Guest"),
+            superuser: false,
+            auth_cashbox: false,
+            auth_products: false,
+            auth_users: false,
+            csrf_token: String::new(),
+        };
+        let products = Session { uid: 2, auth_products: true, ..guest.clone() };
+        let admin = Session { uid: 3, superuser: true, auth_cashbox: true, auth_products: true, auth_users: true, ..guest.clone() };
+
+        assert!(!Permission::Login.granted(&guest));
+        assert!(Permission::Login.granted(&products));
+        assert!(Permission::Products.granted(&products));
+        assert!(!Permission::Cashbox.granted(&products));
+        assert!(!Permission::Users.granted(&products));
+        assert!(!Permission::Superuser.granted(&products));
+        assert!(Permission::Superuser.granted(&admin));
+
+        assert!(products.may_access_user(2));
+        assert!(!products.may_access_user(3));
+        assert!(admin.may_access_user(2));
+    }
+
+    #[test]
+    fn permission_levels() {
+        let client = client();
+        for level in STUB_LEVELS {
+            let session = stub_session(&format!("stub-{}", level)).expect("stub session");
+            for (method, _, uri, permission) in ROUTE_PERMISSIONS {
+                let permission = match permission {
+                    Some(permission) => *permission,
+                    None => continue,
+                };
+
+                let denied = request_denied(&client, *method, uri, level, permission);
+                assert_eq!(denied, !permission.granted(&session), "{} {} as {} user", method, uri, level);
+            }
+        }
+
+        assert!(request_denied(&client, Method::Get, "/cashbox/status", "products", Permission::Cashbox));
+        assert!(request_denied(&client, Method::Post, "/cashbox/update", "products", Permission::Cashbox));
+        assert!(request_denied(&client, Method::Get, "/users", "products", Permission::Users));
+        assert!(request_denied(&client, Method::Post, "/users/import/apply", "products", Permission::Users));
+        assert!(request_denied(&client, Method::Get, "/sales", "users", Permission::Superuser));
+        assert!(!request_denied(&client, Method::Get, "/cashbox/status", "cashbox", Permission::Cashbox));
+    }
+
+    #[test]
+    fn ods_spreadsheet_archive() {
+        let rows = vec![
+            vec![OdsCell::Text("Type".to_string()), OdsCell::Text("Amount".to_string())],
+            vec![OdsCell::Text("loss <&>".to_string()), OdsCell::Euro(-1250)],
+        ];
+        let archive = ods_spreadsheet("Ledger", &rows);
+
+        /* the mimetype must be the first entry, stored uncompressed and without extra field */
+        let mimetype = b"application/vnd.oasis.opendocument.spreadsheet";
+        assert_eq!(&archive[0..4], &0x04034b50u32.to_le_bytes());
+        assert_eq!(&archive[8..10], &0u16.to_le_bytes());
+        assert_eq!(&archive[18..22], &(mimetype.len() as u32).to_le_bytes());
+        assert_eq!(&archive[22..26], &(mimetype.len() as u32).to_le_bytes());
+        assert_eq!(&archive[28..30], &0u16.to_le_bytes());
+        assert_eq!(&archive[30..38], b"mimetype");
+        assert_eq!(&archive[38..38 + mimetype.len()], mimetype);
+
+        let files = compress_tools::list_archive_files(std::io::Cursor::new(&archive)).expect("readable zip archive");
+        assert_eq!(files, vec!["mimetype", "META-INF/manifest.xml", "content.xml"]);
+
+        let mut content = Vec::new();
+        compress_tools::uncompress_archive_file(std::io::Cursor::new(&archive), &mut content, "content.xml").expect("content.xml");
+        let content = String::from_utf8(content).expect("UTF-8 content");
+        assert!(content.contains("<table:table table:name=\"Ledger\">"));
+        assert!(content.contains("<text:p>loss &lt;&amp;&gt;</text:p>"));
+        assert!(content.contains("office:value=\"-12.50\""));
+    }
+
+    #[test]
+    fn product_csv_round_trip() {
+        let metadata = ProductMetadata {
+            product_size: 500,
+            product_size_is_weight: false,
+            container_size: 20,
+            calories: 20,
+            carbohydrates: 5,
+            fats: 0,
+            proteins: 0,
+            deposit: 15,
+            container_deposit: 150,
+            allergens: "none".to_string(),
+            ingredients: "water; mate extract".to_string(),
+        };
+        let with_metadata = ProductCsvEntry {
+            ean: 4029764001807,
+            name: "Club-Mate 0,5l".to_string(),
+            category: "Drinks".to_string(),
+            memberprice: 150,
+            guestprice: 200,
+            deprecated: false,
+            metadata: Some(metadata),
+            aliases: vec![40084015, 4029764001814],
+        };
+        let without_metadata = ProductCsvEntry { metadata: None, aliases: Vec::new(), deprecated: true, ..with_metadata.clone() };
+
+        for entry in [with_metadata, without_metadata] {
+            let record = entry.to_record();
+            assert_eq!(record.len(), PRODUCT_CSV_HEADER.len());
+            let parsed = ProductCsvEntry::from_record(&csv::StringRecord::from(record)).expect("valid record");
+            assert!(parsed == entry, "{:?} does not round trip", entry.to_record());
+        }
+    }
+
+    #[test]
+    fn product_csv_legacy_layout() {
+        let legacy = csv::StringRecord::from(vec!["4029764001807", "Club-Mate", "Drinks", "150", "200", "0", "500", "0", "20", "20", "5", "0", "0", "15", "150", "4029764001814"]);
+        let entry = ProductCsvEntry::from_record(&legacy).expect("old exports are accepted");
+        let metadata = entry.metadata.expect("metadata");
+        assert_eq!((metadata.deposit, metadata.container_deposit), (15, 150));
+        assert!(metadata.allergens.is_empty() && metadata.ingredients.is_empty());
+        assert_eq!(entry.aliases, vec![4029764001814]);
+
+        let truncated = csv::StringRecord::from(vec!["4029764001807", "Club-Mate", "Drinks", "150", "200"]);
+        let error = ProductCsvEntry::from_record(&truncated).err().expect("missing columns");
+        assert_eq!(error, "expected 18 columns (or 16 columns of old exports), found 5");
+    }
+
+    #[test]
+    fn csrf_token_checks() {
+        assert!(csrf_token_matches("abc123", "abc123"));
+        assert!(!csrf_token_matches("abc123", "abc124"));
+        assert!(!csrf_token_matches("abc123", "abc12"));
+        assert!(!csrf_token_matches("abc123", ""));
+        /* guests have no token, an empty form field must not match it */
+        assert!(!csrf_token_matches("", ""));
+
+        let client = client();
+        let response = client.post("/logout").header(ContentType::Form).body("csrf_token=invalid").dispatch();
+        assert_eq!(response.status(), Status::Ok);
+    }
+}
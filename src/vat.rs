@@ -0,0 +1,80 @@
+/* Copyright 2023, Sebastian Reichel <sre@mainframe.io>
+ *
+ * Permission to use, copy, modify, and/or distribute this software for any
+ * purpose with or without fee is hereby granted, provided that the above
+ * copyright notice and this permission notice appear in all copies.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS" AND THE AUTHOR DISCLAIMS ALL WARRANTIES
+ * WITH REGARD TO THIS SOFTWARE INCLUDING ALL IMPLIED WARRANTIES OF
+ * MERCHANTABILITY AND FITNESS. IN NO EVENT SHALL THE AUTHOR BE LIABLE FOR
+ * ANY SPECIAL, DIRECT, INDIRECT, OR CONSEQUENTIAL DAMAGES OR ANY DAMAGES
+ * WHATSOEVER RESULTING FROM LOSS OF USE, DATA OR PROFITS, WHETHER IN AN
+ * ACTION OF CONTRACT, NEGLIGENCE OR OTHER TORTIOUS ACTION, ARISING OUT OF
+ * OR IN CONNECTION WITH THE USE OR PERFORMANCE OF THIS SOFTWARE.
+ */
+
+/* tax included in the entries with the same rate */
+pub struct VatBreakdown {
+	pub rate: i32,
+	pub gross: i32,
+	pub net: i32,
+	pub tax: i32,
+}
+
+/* integer division, rounding half away from zero for negative amounts as well */
+pub fn div_round(dividend: i64, divisor: i64) -> i64 {
+	let half = divisor / 2;
+	if dividend < 0 { (dividend - half) / divisor } else { (dividend + half) / divisor }
+}
+
+/* net amount in cent of a gross amount in cent */
+pub fn net_amount(gross: i32, rate: i32) -> i32 {
+	div_round(gross as i64 * 100, 100 + rate as i64) as i32
+}
+
+/* prices are gross prices, so net and tax are calculated from the sum per tax rate, entries are (rate, price) */
+pub fn vat_breakdown(entries: impl IntoIterator<Item = (i32, i32)>) -> Vec<VatBreakdown> {
+	let mut result: Vec<VatBreakdown> = Vec::new();
+
+	for (rate, price) in entries {
+		match result.iter_mut().find(|b| b.rate == rate) {
+			Some(b) => b.gross += price,
+			None => result.push(VatBreakdown { rate, gross: price, net: 0, tax: 0 }),
+		}
+	}
+
+	for b in result.iter_mut() {
+		b.net = net_amount(b.gross, b.rate);
+		b.tax = b.gross - b.net;
+	}
+
+	result.sort_by_key(|b| b.rate);
+	result
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn breakdown_per_rate() {
+		let breakdown = vat_breakdown([(19, 150), (7, 107), (19, 100), (19, -12)]);
+
+		assert_eq!(breakdown.len(), 2);
+		assert_eq!((breakdown[0].rate, breakdown[0].gross, breakdown[0].net, breakdown[0].tax), (7, 107, 100, 7));
+		/* the tax is rounded once for the sum, not per entry */
+		assert_eq!((breakdown[1].rate, breakdown[1].gross, breakdown[1].net, breakdown[1].tax), (19, 238, 200, 38));
+	}
+
+	#[test]
+	fn breakdown_of_credits() {
+		/* e.g. a month with returned deposit only */
+		let breakdown = vat_breakdown([(19, -119)]);
+		assert_eq!((breakdown[0].gross, breakdown[0].net, breakdown[0].tax), (-119, -100, -19));
+
+		assert_eq!(net_amount(-150, 7), -140);
+		assert_eq!(net_amount(150, 7), 140);
+		assert_eq!(div_round(-5, 10), -1);
+		assert_eq!(div_round(-4, 10), 0);
+	}
+}
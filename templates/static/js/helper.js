@@ -1,3 +1,14 @@
+var csrf_token = function() {
+	return jQuery('meta[name="csrf-token"]').attr('content') || "";
+};
+
+/* send CSRF token with all state changing AJAX requests */
+jQuery.ajaxPrefilter(function( options, originalOptions, jqXHR ) {
+	if (options.type.toUpperCase() !== "GET" && csrf_token() !== "") {
+		jqXHR.setRequestHeader("X-CSRF-Token", csrf_token());
+	}
+});
+
 jQuery["postJSON"] = function( url, data, callback ) {
 	// shift arguments if data argument was omitted
 	if ( jQuery.isFunction( data ) ) {
@@ -68,6 +79,10 @@ var isodate2ts = function(iso) {
 	return Math.floor(new Date(iso+"T23:59:59").getTime() / 1000);
 }
 
+var isodate2startts = function(iso) {
+	return Math.floor(new Date(iso+"T00:00:00").getTime() / 1000);
+}
+
 var find_max_y = function(xytable) {
 	max = 0;
 	xytable.forEach(function(entry) {